@@ -31,14 +31,21 @@ pub struct BasicAccount {
     pub code_hash: H256,
     /// ABI hash of the account.
     pub abi_hash: H256,
+    /// Block number of the last mutation, if recorded.
+    /// Encoded as a fifth item only when present.
+    pub last_touched: Option<u64>,
 }
 
 impl Encodable for BasicAccount {
     fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_list(4).append(&self.nonce)
-                       .append(&self.storage_root)
-                       .append(&self.code_hash)
-                       .append(&self.abi_hash);
+        let items = if self.last_touched.is_some() { 5 } else { 4 };
+        s.begin_list(items).append(&self.nonce)
+                           .append(&self.storage_root)
+                           .append(&self.code_hash)
+                           .append(&self.abi_hash);
+        if let Some(ref number) = self.last_touched {
+            s.append(number);
+        }
     }
 }
 
@@ -48,7 +55,8 @@ impl Decodable for BasicAccount {
                nonce: rlp.val_at(0)?,
                storage_root: rlp.val_at(1)?,
                code_hash: rlp.val_at(2)?,
-               abi_hash: rlp.val_at(3)?,               
+               abi_hash: rlp.val_at(3)?,
+               last_touched: if rlp.item_count()? > 4 { Some(rlp.val_at(4)?) } else { None },
            })
     }
 }
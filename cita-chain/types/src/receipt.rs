@@ -43,6 +43,24 @@ pub enum ReceiptError {
     MutableCallInStaticContext,
     OutOfBounds,
     Reverted,
+    //Compliance error
+    ContractBlacklisted,
+    //Quota error
+    OutOfQuota,
+    //Transaction expired
+    InvalidUntilBlock,
+    //Zero address error
+    SendToZeroAddress,
+    //Quota budget exhausted
+    InsufficientQuota,
+    //Creation code not approved
+    CodeNotWhitelisted,
+    //Callee self-destructed earlier in the block
+    SuicidedContract,
+    //More SSTOREs than allowed per transaction
+    TooManyStorageWrites,
+    //Created contract address already in use
+    ContractAddressCollision,
 }
 
 impl ReceiptError {
@@ -64,6 +82,15 @@ impl ReceiptError {
             ReceiptError::MutableCallInStaticContext => "Mutable call in static context.",
             ReceiptError::OutOfBounds => "Out of bounds.",
             ReceiptError::Reverted => "Reverted",
+            ReceiptError::ContractBlacklisted => "Contract is blacklisted.",
+            ReceiptError::OutOfQuota => "Not enough base quota.",
+            ReceiptError::InvalidUntilBlock => "Transaction has expired.",
+            ReceiptError::SendToZeroAddress => "Sending to the zero address is not allowed.",
+            ReceiptError::InsufficientQuota => "Insufficient quota.",
+            ReceiptError::CodeNotWhitelisted => "Contract code is not whitelisted.",
+            ReceiptError::SuicidedContract => "Contract self-destructed earlier in the block.",
+            ReceiptError::TooManyStorageWrites => "Too many storage writes.",
+            ReceiptError::ContractAddressCollision => "Contract address is already in use.",
         };
         desc.to_string()
     }
@@ -85,6 +112,18 @@ impl ReceiptError {
             ReceiptError::MutableCallInStaticContext => ProtoReceiptError::MutableCallInStaticContext,
             ReceiptError::OutOfBounds => ProtoReceiptError::OutOfBounds,
             ReceiptError::Reverted => ProtoReceiptError::Reverted,
+            // libproto has no dedicated variants for the errors below, each is reported as
+            // the closest one: a permission failure if the transaction was refused, a quota
+            // or gas failure if a limit was exhausted.
+            ReceiptError::ContractBlacklisted => ProtoReceiptError::NoCallPermission,
+            ReceiptError::OutOfQuota => ProtoReceiptError::NotEnoughBaseGas,
+            ReceiptError::InvalidUntilBlock => ProtoReceiptError::NoTransactionPermission,
+            ReceiptError::SendToZeroAddress => ProtoReceiptError::NoCallPermission,
+            ReceiptError::InsufficientQuota => ProtoReceiptError::AccountGasLimitReached,
+            ReceiptError::CodeNotWhitelisted => ProtoReceiptError::NoContractPermission,
+            ReceiptError::SuicidedContract => ProtoReceiptError::NoCallPermission,
+            ReceiptError::TooManyStorageWrites => ProtoReceiptError::OutOfGas,
+            ReceiptError::ContractAddressCollision => ProtoReceiptError::NoContractPermission,
         }
     }
 
@@ -127,6 +166,15 @@ impl Decodable for ReceiptError {
             12 => Ok(ReceiptError::MutableCallInStaticContext),
             13 => Ok(ReceiptError::OutOfBounds),
             14 => Ok(ReceiptError::Reverted),
+            15 => Ok(ReceiptError::ContractBlacklisted),
+            16 => Ok(ReceiptError::OutOfQuota),
+            17 => Ok(ReceiptError::InvalidUntilBlock),
+            18 => Ok(ReceiptError::SendToZeroAddress),
+            19 => Ok(ReceiptError::InsufficientQuota),
+            20 => Ok(ReceiptError::CodeNotWhitelisted),
+            21 => Ok(ReceiptError::SuicidedContract),
+            22 => Ok(ReceiptError::TooManyStorageWrites),
+            23 => Ok(ReceiptError::ContractAddressCollision),
             _ => Err(DecoderError::Custom("Unknown Receipt error.")),
         }
     }
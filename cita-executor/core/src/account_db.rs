@@ -22,10 +22,14 @@ use util::*;
 
 static NULL_RLP_STATIC: [u8; 1] = [0x80; 1];
 
-// combines a key with an address hash to ensure uniqueness.
-// leaves the first 96 bits untouched in order to support partial key lookup.
+// number of read-only account dbs opened, lets tests check how often the factory is hit.
+#[cfg(test)]
+thread_local!(pub static READONLY_OPENS: ::std::cell::Cell<usize> = ::std::cell::Cell::new(0));
+
+/// Combines a key with an address hash to ensure uniqueness.
+/// Leaves the first 96 bits untouched in order to support partial key lookup.
 #[inline]
-fn combine_key<'a>(address_hash: &'a H256, key: &'a H256) -> H256 {
+pub fn combine_key<'a>(address_hash: &'a H256, key: &'a H256) -> H256 {
     let mut dst = *key;
     {
         let last_src: &[u8] = &*address_hash;
@@ -57,6 +61,8 @@ impl Factory {
     /// Create a read-only accountdb.
     /// This will panic when write operations are called.
     pub fn readonly<'db>(&self, db: &'db HashDB, address_hash: H256) -> Box<HashDB + 'db> {
+        #[cfg(test)]
+        READONLY_OPENS.with(|opens| opens.set(opens.get() + 1));
         match *self {
             Factory::Mangled => Box::new(AccountDB::from_hash(db, address_hash)),
             Factory::Plain => Box::new(Wrapping(db)),
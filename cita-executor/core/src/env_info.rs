@@ -61,6 +61,17 @@ impl Default for EnvInfo {
     }
 }
 
+impl EnvInfo {
+    /// Environment of block `number` sealed at `timestamp`, for simulating execution at any height.
+    pub fn at_block(number: BlockNumber, timestamp: u64) -> Self {
+        EnvInfo {
+            number: number,
+            timestamp: timestamp,
+            ..Default::default()
+        }
+    }
+}
+
 // impl From<ethjson::vm::Env> for EnvInfo {
 //     fn from(e: ethjson::vm::Env) -> Self {
 //         let number = e.number.into();
@@ -103,4 +114,13 @@ mod tests {
 
         assert_eq!(default_env_info.difficulty, 0.into());
     }
+
+    #[test]
+    fn it_can_be_created_at_block() {
+        let env_info = EnvInfo::at_block(1_000, 1_500_000_000);
+
+        assert_eq!(env_info.number, 1_000);
+        assert_eq!(env_info.timestamp, 1_500_000_000);
+        assert_eq!(env_info.gas_limit, U256::from(u64::max_value()));
+    }
 }
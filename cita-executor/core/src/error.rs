@@ -232,6 +232,37 @@ pub enum Error {
     Snappy(::util::snappy::SnappyError),
     /// Ethkey error.
     Ethkey(EthkeyError),
+    /// A trie node is missing from a partially synced state.
+    /// The caller may fetch and insert the node, then retry.
+    StateIncomplete {
+        /// Hash of the missing node.
+        missing_node: H256,
+    },
+    /// A batch call got more items than `State::max_batch_size`.
+    BatchTooLarge {
+        /// Number of items given.
+        size: usize,
+        /// Most items allowed.
+        limit: usize,
+    },
+    /// A commit to a read-only state, such as one opened with `State::from_mmap`.
+    ReadOnlyState,
+    /// A write to a storage slot frozen with `State::freeze_slot`.
+    SlotFrozen {
+        /// Account owning the slot.
+        address: Address,
+        /// Key of the slot.
+        key: H256,
+    },
+    /// A commit without pending changes altered the state, see `State::assert_commit_idempotent`.
+    CommitNotIdempotent {
+        /// Root after the first commit.
+        root: H256,
+        /// Root after the repeated commit.
+        recommitted_root: H256,
+        /// Database entries written by the repeated commit.
+        node_writes: usize,
+    },
 }
 
 impl fmt::Display for Error {
@@ -250,6 +281,20 @@ impl fmt::Display for Error {
             Error::StdIo(ref err) => err.fmt(f),
             Error::Snappy(ref err) => err.fmt(f),
             Error::Ethkey(ref err) => err.fmt(f),
+            Error::StateIncomplete { ref missing_node } => {
+                f.write_fmt(format_args!("State is incomplete, missing trie node {}", missing_node))
+            }
+            Error::BatchTooLarge { ref size, ref limit } => {
+                f.write_fmt(format_args!("Batch of {} items exceeds the limit of {}", size, limit))
+            }
+            Error::ReadOnlyState => f.write_str("State is read-only"),
+            Error::SlotFrozen { ref address, ref key } => {
+                f.write_fmt(format_args!("Storage slot {} of {} is frozen", key, address))
+            }
+            Error::CommitNotIdempotent { ref root, ref recommitted_root, ref node_writes } => f.write_fmt(format_args!(
+                "Repeated commit moved root {} to {} and wrote {} nodes",
+                root, recommitted_root, node_writes
+            )),
         }
     }
 }
@@ -68,6 +68,16 @@ pub enum Error {
     OutOfBounds,
     /// Execution has been reverted with REVERT.
     Reverted,
+    /// The transaction executed more SSTOREs than `State::max_storage_writes_per_tx`.
+    TooManyStorageWrites {
+        /// Most SSTOREs allowed.
+        limit: usize,
+    },
+    /// A contract tried to create a contract from code missing from `State::allowed_code_hashes`.
+    CodeNotWhitelisted,
+    /// A contract called a contract that self-destructed earlier in the block,
+    /// rejected by `State::suicided_call_policy`.
+    SuicidedContract,
 }
 
 impl From<Box<trie::TrieError>> for Error {
@@ -89,6 +99,9 @@ impl fmt::Display for Error {
             MutableCallInStaticContext => write!(f, "Mutable call in static context"),
             OutOfBounds => write!(f, "Out of bounds"),
             Reverted => write!(f, "Reverted"),
+            TooManyStorageWrites { limit } => write!(f, "Too many storage writes, limit {}", limit),
+            CodeNotWhitelisted => write!(f, "Contract code is not whitelisted"),
+            SuicidedContract => write!(f, "Contract self-destructed earlier in the block"),
         }
     }
 }
@@ -120,6 +120,17 @@ pub trait Ext {
     /// Increments sstore refunds count by 1.
     fn inc_sstore_clears(&mut self);
 
+    /// Records gas charged for an SSTORE.
+    fn add_storage_gas(&mut self, _gas: &U256) {}
+
+    /// Whether a storage slot of the current contract was already accessed in this transaction.
+    fn is_storage_warm(&self, _key: &H256) -> bool {
+        true
+    }
+
+    /// Marks a storage slot of the current contract as accessed in this transaction.
+    fn warm_storage(&mut self, _key: &H256) {}
+
     /// Prepare to trace an operation. Passthrough for the VM trace.
     fn trace_prepare_execute(&mut self, _pc: usize, _instruction: u8, _gas_cost: &U256) -> bool {
         false
@@ -119,10 +119,13 @@ impl<Gas: CostType> Gasometer<Gas> {
                     // !is_zero(&val) && is_zero(newval)
                     schedule.sstore_reset_gas
                 };
-                Request::Gas(Gas::from(gas))
+                let cold = if ext.is_storage_warm(&address) { 0 } else { schedule.cold_sload_gas };
+                Request::Gas(Gas::from(gas + cold))
             }
             instructions::SLOAD => {
-                Request::Gas(Gas::from(schedule.sload_gas))
+                let key = H256::from(stack.peek(0));
+                let cold = if ext.is_storage_warm(&key) { 0 } else { schedule.cold_sload_gas };
+                Request::Gas(Gas::from(schedule.sload_gas + cold))
             }
             instructions::BALANCE => {
                 Request::Gas(Gas::from(schedule.balance_gas))
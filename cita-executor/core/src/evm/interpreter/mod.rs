@@ -133,6 +133,9 @@ impl<Cost: CostType> evm::Evm for Interpreter<Cost> {
             let trace_executed = ext.trace_prepare_execute(reader.position - 1, instruction, &requirements.gas_cost.as_u256());
 
             gasometer.verify_gas(&requirements.gas_cost)?;
+            if instruction == instructions::SSTORE {
+                ext.add_storage_gas(&requirements.gas_cost.as_u256());
+            }
             self.mem.expand(requirements.memory_required_size);
             gasometer.current_mem_gas = requirements.memory_total_gas;
             gasometer.current_gas = gasometer.current_gas - requirements.gas_cost;
@@ -433,6 +436,7 @@ impl<Cost: CostType> Interpreter<Cost> {
             instructions::SLOAD => {
                 let key = H256::from(&stack.pop_back());
                 let word = U256::from(&*ext.storage_at(&key)?);
+                ext.warm_storage(&key);
                 stack.push(word);
             }
             instructions::SSTORE => {
@@ -445,6 +449,7 @@ impl<Cost: CostType> Interpreter<Cost> {
                     ext.inc_sstore_clears();
                 }
                 ext.set_storage(address, H256::from(&val))?;
+                ext.warm_storage(&address);
             }
             instructions::PC => {
                 stack.push(U256::from(code.position - 1));
@@ -37,6 +37,8 @@ pub struct Schedule {
     pub sha3_word_gas: usize,
     /// Gas price for loading from storage
     pub sload_gas: usize,
+    /// Additional gas for the first `SLOAD` or `SSTORE` of a storage slot in a transaction
+    pub cold_sload_gas: usize,
     /// Gas price for setting new value to storage (`storage==0`, `new!=0`)
     pub sstore_set_gas: usize,
     /// Gas price for altering value in storage
@@ -117,6 +119,7 @@ impl Schedule {
             sha3_gas: 30,
             sha3_word_gas: 6,
             sload_gas: 50,
+            cold_sload_gas: 0,
             sstore_set_gas: 20_000,
             sstore_reset_gas: 5000,
             sstore_refund_gas: 15_000,
@@ -23,7 +23,7 @@ use std::fmt;
 use trace::{VMTrace, FlatTrace};
 use types::log_entry::LogEntry;
 use types::state_diff::StateDiff;
-use util::{Bytes, U256, Address, U512, trie};
+use util::{Bytes, H256, U256, Address, U512, trie};
 
 /// The type of the call-like instruction.
 #[derive(Debug, PartialEq, Clone)]
@@ -110,6 +110,10 @@ pub struct Executed {
     pub vm_trace: Option<VMTrace>,
     /// The state diff, if we traced it.
     pub state_diff: Option<StateDiff>,
+    /// Gas charged for SSTOREs whose changes were kept.
+    pub storage_gas: U256,
+    /// Contracts that self-destructed.
+    pub suicides: Vec<Address>,
 }
 
 /// Result of executing the transaction.
@@ -142,6 +146,27 @@ pub enum ExecutionError {
         /// Amount of gas in transaction
         gas: U256,
     },
+    /// Returned when the sender's remaining quota cannot cover any transaction.
+    InsufficientQuota {
+        /// Quota left to the sender in this block.
+        remaining: U256,
+        /// Base quota of a transaction.
+        base_quota: U256,
+    },
+    /// Returned when the transaction expired before the block it is executed in.
+    InvalidUntilBlock {
+        /// Last block the transaction is valid in.
+        valid_until_block: u64,
+        /// Number of the executing block.
+        block_number: u64,
+    },
+    /// Returned when the transaction quota does not cover the base quota charged per transaction.
+    NotEnoughBaseQuota {
+        /// Minimum quota required.
+        required: U256,
+        /// Quota provided.
+        got: U256,
+    },
     /// Returned when transaction nonce does not match state nonce.
     InvalidNonce {
         /// Nonce expected.
@@ -160,17 +185,34 @@ pub enum ExecutionError {
     NoTransactionPermission,
     NoContractPermission,
     NoCallPermission,
+    /// Returned when the transaction calls a blacklisted contract.
+    ContractBlacklisted,
+    /// Returned when the transaction calls the zero address and such calls are rejected.
+    SendToZeroAddress,
+    /// Returned when the transaction creates a contract from code that is not whitelisted.
+    CodeNotWhitelisted,
+    /// Returned when a salted creation targets an address that already holds code or a nonce.
+    ContractAddressCollision(Address),
+    /// Returned when the transaction calls a contract that self-destructed earlier in the block.
+    SuicidedContract(Address),
     /// When execution tries to modify the state in static context
     MutableCallInStaticContext,
     /// Returned when internal evm error occurs.
     Internal(String),
     /// Returned when generic transaction occurs
     TransactionMalformed(String),
+    /// Returned when a trie node required for execution is missing from the database.
+    StateIncomplete(H256),
 }
 
 impl From<Box<trie::TrieError>> for ExecutionError {
     fn from(err: Box<trie::TrieError>) -> Self {
-        ExecutionError::Internal(format!("{}", err))
+        match *err {
+            trie::TrieError::IncompleteDatabase(node) | trie::TrieError::InvalidStateRoot(node) => {
+                ExecutionError::StateIncomplete(node)
+            }
+            ref err => ExecutionError::Internal(format!("{}", err)),
+        }
     }
 }
 
@@ -186,6 +228,9 @@ impl fmt::Display for ExecutionError {
                 ref gas,
             } => format!("Block gas limit reached. The limit is {}, {} has already been used, and {} more is required", gas_limit, gas_used, gas),
             AccountGasLimitReached { ref gas_limit, ref gas } => format!("Account gas limit reached. The limit is {}, {} more is required", gas_limit, gas),
+            InsufficientQuota { ref remaining, ref base_quota } => format!("Insufficient quota. The sender has {} left, but the base quota is {}", remaining, base_quota),
+            InvalidUntilBlock { ref valid_until_block, ref block_number } => format!("Transaction expired. It is valid until block {}, but executed in block {}", valid_until_block, block_number),
+            NotEnoughBaseQuota { ref required, ref got } => format!("Not enough base quota. {} is required, but only {} paid", required, got),
             InvalidNonce { ref expected, ref got } => format!("Invalid transaction nonce: expected {}, found {}", expected, got),
             NotEnoughCash { ref required, ref got } => format!("Cost of transaction exceeds sender balance. {} is required but the sender only has {}", required, got),
             MutableCallInStaticContext => "Mutable Call in static context".to_owned(),
@@ -194,6 +239,12 @@ impl fmt::Display for ExecutionError {
             NoTransactionPermission => "No transaction permission".to_owned(),
             NoContractPermission => "No contract permission".to_owned(),
             NoCallPermission => "No call contract permission".to_owned(),
+            ContractBlacklisted => "Contract is blacklisted".to_owned(),
+            SendToZeroAddress => "Sending to the zero address is not allowed".to_owned(),
+            CodeNotWhitelisted => "Contract code is not whitelisted".to_owned(),
+            ContractAddressCollision(ref address) => format!("Contract address {} is already in use", address),
+            SuicidedContract(ref address) => format!("Contract {} self-destructed earlier in the block", address),
+            StateIncomplete(ref node) => format!("State is incomplete, missing trie node {}", node),
         };
 
         f.write_fmt(format_args!("Transaction execution error ({}).", msg))
@@ -28,11 +28,13 @@ pub use executed::{Executed, ExecutionResult};
 use executed::CallType;
 use externalities::*;
 use native::Factory as NativeFactory;
-use state::{State, Substate};
+use state::{State, Substate, SuicidedCallPolicy};
 use state::backend::Backend as StateBackend;
 use std::cmp;
+use std::mem;
 use std::sync::Arc;
-use trace::{ExecutiveTracer, ExecutiveVMTracer, FlatTrace, NoopTracer, NoopVMTracer, Tracer, VMTrace, VMTracer};
+use trace::{ExecutiveTracer, ExecutiveVMTracer, FlatTrace, NoopTracer, NoopVMTracer, TraceBuffers, Tracer, VMTrace,
+            VMTracer};
 use types::transaction::{Action, SignedTransaction};
 use util::*;
 
@@ -53,6 +55,17 @@ pub fn contract_address(address: &Address, nonce: &U256) -> Address {
     From::from(stream.out().crypt_hash())
 }
 
+/// Returns new address created from the sender, a salt and the hash of the creation code,
+/// independent of the sender's nonce.
+pub fn salted_contract_address(address: &Address, salt: &H256, code_hash: &H256) -> Address {
+    let mut buffer = Vec::with_capacity(1 + 20 + 32 + 32);
+    buffer.push(0xff);
+    buffer.extend_from_slice(&address[..]);
+    buffer.extend_from_slice(&salt[..]);
+    buffer.extend_from_slice(&code_hash[..]);
+    From::from(buffer.crypt_hash())
+}
+
 /// Transaction execution options.
 #[derive(Default, Copy, Clone, PartialEq)]
 pub struct TransactOptions {
@@ -64,6 +77,25 @@ pub struct TransactOptions {
     pub check_permission: bool,
     /// Check account gas limit
     pub check_quota: bool,
+    /// Execute as an internally generated system transaction.
+    ///
+    /// The sender's nonce is neither incremented nor required, and permission
+    /// checks are skipped. This must only be set for transactions that the node
+    /// generates itself; setting it for user-signed transactions allows them to
+    /// bypass permission management and be replayed.
+    pub system_transaction: bool,
+    /// Gas price exposed to contracts through `GASPRICE` instead of the transaction's own.
+    pub gas_price: Option<U256>,
+    /// Summarize the executed instructions in `ApplyOutcome::opcode_profile`.
+    /// Requires VM tracing, which `State::apply_with_options` turns on for it.
+    pub opcode_profiling: bool,
+    /// Record the state transitions of every account in `ApplyOutcome::account_events`.
+    pub account_events: bool,
+    /// Measure the net change in state size in `ApplyOutcome::state_growth`.
+    pub state_growth: bool,
+    /// Deploy a creation transaction to `salted_contract_address` with this salt
+    /// instead of the address derived from the sender's nonce.
+    pub create_salt: Option<H256>,
 }
 
 /// Transaction executor.
@@ -154,17 +186,39 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         &'a mut self,
         t: &mut SignedTransaction,
         options: TransactOptions,
+    ) -> Result<Executed, ExecutionError> {
+        self.transact_reusing(t, options, &mut Substate::new(), &TraceBuffers::default())
+    }
+
+    /// Execute transaction like `transact`, accruing its top-level substate into `substate`
+    /// after clearing it and tracing into buffers taken from `trace_buffers`. Passing the same
+    /// substate and buffers for every transaction keeps their allocations.
+    pub fn transact_reusing(
+        &'a mut self,
+        t: &mut SignedTransaction,
+        options: TransactOptions,
+        substate: &mut Substate,
+        trace_buffers: &TraceBuffers,
     ) -> Result<Executed, ExecutionError> {
         match (options.tracing, options.vm_tracing) {
-            (true, true) => self.transact_with_tracer(
+            (true, true) => self.transact_with_substate(
                 t,
                 options,
-                ExecutiveTracer::default(),
+                substate,
+                ExecutiveTracer::reusing(trace_buffers),
                 ExecutiveVMTracer::toplevel(),
             ),
-            (true, false) => self.transact_with_tracer(t, options, ExecutiveTracer::default(), NoopVMTracer),
-            (false, true) => self.transact_with_tracer(t, options, NoopTracer, ExecutiveVMTracer::toplevel()),
-            (false, false) => self.transact_with_tracer(t, options, NoopTracer, NoopVMTracer),
+            (true, false) => self.transact_with_substate(
+                t,
+                options,
+                substate,
+                ExecutiveTracer::reusing(trace_buffers),
+                NoopVMTracer,
+            ),
+            (false, true) => {
+                self.transact_with_substate(t, options, substate, NoopTracer, ExecutiveVMTracer::toplevel())
+            }
+            (false, false) => self.transact_with_substate(t, options, substate, NoopTracer, NoopVMTracer),
         }
     }
 
@@ -173,6 +227,21 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         &'a mut self,
         t: &mut SignedTransaction,
         options: TransactOptions,
+        tracer: T,
+        vm_tracer: V,
+    ) -> Result<Executed, ExecutionError>
+    where
+        T: Tracer,
+        V: VMTracer,
+    {
+        self.transact_with_substate(t, options, &mut Substate::new(), tracer, vm_tracer)
+    }
+
+    fn transact_with_substate<T, V>(
+        &'a mut self,
+        t: &mut SignedTransaction,
+        options: TransactOptions,
+        substate: &mut Substate,
         mut tracer: T,
         mut vm_tracer: V,
     ) -> Result<Executed, ExecutionError>
@@ -182,12 +251,16 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
     {
         let sender = *t.sender();
         let nonce = self.state.nonce(&sender)?;
+        self.state.reset_warm_storage();
+        self.state.reset_storage_writes();
 
         // NOTE: there can be no invalid transactions from this point
-        self.state.inc_nonce(&sender)?;
+        if !options.system_transaction {
+            self.state.inc_nonce(&sender)?;
+        }
 
         trace!("permission should be check: {}", options.check_permission);
-        if options.check_permission {
+        if options.check_permission && !options.system_transaction {
             self.check_permission(t)?;
         }
 
@@ -203,8 +276,23 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         t.set_account_nonce(nonce);
 
         trace!("quota should be checked: {}", options.check_quota);
+        let base_quota = if options.check_quota {
+            self.state.base_quota
+        } else {
+            U256::zero()
+        };
         if options.check_quota {
             self.check_quota(t)?;
+            let required = match t.action {
+                Action::Store | Action::AbiStore => base_quota,
+                _ => base_quota + base_gas_required,
+            };
+            if t.gas < required {
+                return Err(From::from(ExecutionError::NotEnoughBaseQuota {
+                    required: required,
+                    got: t.gas,
+                }));
+            }
         }
 
         if t.action == Action::AbiStore {
@@ -224,34 +312,45 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         }
         // NOTE: there can be no invalid transactions from this point
 
-        let mut substate = Substate::new();
+        substate.clear();
+        let gas_price = options.gas_price.unwrap_or(t.gas_price);
 
         let (result, output) = match t.action {
             Action::Store | Action::AbiStore => (
                 Ok(FinalizationResult {
-                    gas_left: t.gas,
+                    gas_left: t.gas - base_quota,
                     return_data: ReturnData::empty(),
                     apply_state: true,
                 }),
                 vec![],
             ),
             Action::Create => {
-                let new_address = contract_address(&sender, &nonce);
+                let new_address = match options.create_salt {
+                    Some(ref salt) => {
+                        let new_address = salted_contract_address(&sender, salt, &t.data.crypt_hash());
+                        // a salted address may be reused, never overwrite a deployed account
+                        if self.state.exists_and_has_code_or_nonce(&new_address)? {
+                            return Err(ExecutionError::ContractAddressCollision(new_address));
+                        }
+                        new_address
+                    }
+                    None => contract_address(&sender, &nonce),
+                };
                 let params = ActionParams {
                     code_address: new_address,
                     code_hash: t.data.crypt_hash(),
                     address: new_address,
                     sender: sender,
                     origin: sender,
-                    gas: t.gas - base_gas_required,
-                    gas_price: t.gas_price,
+                    gas: t.gas - base_gas_required - base_quota,
+                    gas_price: gas_price,
                     value: ActionValue::Transfer(t.value),
                     code: Some(Arc::new(t.data.clone())),
                     data: None,
                     call_type: CallType::None,
                 };
                 (
-                    self.create(params, &mut substate, &mut tracer, &mut vm_tracer),
+                    self.create(params, substate, &mut tracer, &mut vm_tracer),
                     vec![],
                 )
             }
@@ -261,8 +360,8 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
                     address: *address,
                     sender: sender,
                     origin: sender,
-                    gas: t.gas - base_gas_required,
-                    gas_price: t.gas_price,
+                    gas: t.gas - base_gas_required - base_quota,
+                    gas_price: gas_price,
                     value: ActionValue::Transfer(t.value),
                     code: self.state.code(address)?,
                     code_hash: self.state.code_hash(address)?,
@@ -274,7 +373,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
                 (
                     self.call(
                         params,
-                        &mut substate,
+                        substate,
                         BytesRef::Flexible(&mut out),
                         &mut tracer,
                         &mut vm_tracer,
@@ -298,10 +397,6 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
     /// Check the sender's permission
     fn check_permission(&self, t: &SignedTransaction) -> Result<(), ExecutionError> {
         let sender = *t.sender();
-        let send_tx_cont = Address::from(0x1);
-        let send_tx_func = vec![0; 4];
-        let create_contract_cont = Address::from(0x2);
-        let create_contract_func = vec![0; 4];
         // check contract create/call permission
         trace!(
             "executive creators: {:?}, senders: {:?}",
@@ -309,33 +404,19 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
             self.state.senders
         );
 
-        let has_send_permission = contains_resource(
-            &self.state.account_permissions,
-            &sender,
-            send_tx_cont,
-            send_tx_func,
-        );
-        let has_create_permission = contains_resource(
-            &self.state.account_permissions,
-            &sender,
-            create_contract_cont,
-            create_contract_func,
-        );
         trace!(
             "has send permission: {:?}, has create permission: {:?}",
-            has_send_permission,
-            has_create_permission
+            self.state.can_send(&sender),
+            self.state.can_create(&sender)
         );
         match t.action {
             Action::Create => {
-                if sender != Address::zero() && !self.state.creators.contains(&sender) && !has_create_permission {
+                if !self.state.can_create(&sender) {
                     return Err(From::from(ExecutionError::NoContractPermission));
                 }
             }
             Action::Call(address) => {
-                if sender != Address::zero() && !self.state.senders.contains(&sender)
-                    && !self.state.creators.contains(&sender) && !has_send_permission
-                {
+                if !self.state.can_send(&sender) {
                     return Err(From::from(ExecutionError::NoTransactionPermission));
                 }
                 trace!("t.data {:?}", t.data);
@@ -354,9 +435,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
                 }
             }
             _ => {
-                if sender != Address::zero() && !self.state.senders.contains(&sender)
-                    && !self.state.creators.contains(&sender) && !has_send_permission
-                {
+                if !self.state.can_send(&sender) {
                     return Err(From::from(ExecutionError::NoTransactionPermission));
                 }
             }
@@ -469,16 +548,24 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         {
             return Err(evm::Error::MutableCallInStaticContext);
         }
+        // the transaction's own target is checked by `State::apply_with_options`
+        if self.depth > 0 && self.state.suicided_call_policy == SuicidedCallPolicy::Reject
+            && self.state.suicided_within_block(&params.code_address)
+        {
+            return Err(evm::Error::SuicidedContract);
+        }
 
         // backup used in case of running out of gas
-        self.state.checkpoint();
+        self.state
+            .checkpoint()
+            .map_err(|err| evm::Error::Internal(format!("{}", err)))?;
 
         let static_call = params.call_type == CallType::StaticCall;
 
         if let Some(mut contract) = self.native_factory.new_contract(params.code_address) {
             let cost = U256::from(100);
             if cost <= params.gas {
-                let mut unconfirmed_substate = Substate::new();
+                let mut unconfirmed_substate = self.state.take_substate();
                 let mut trace_output = tracer.prepare_trace_output();
                 let output_policy = OutputPolicy::Return(output, trace_output.as_mut());
                 let res = {
@@ -548,7 +635,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
 
             if params.code.is_some() {
                 // part of substate that may be reverted
-                let mut unconfirmed_substate = Substate::new();
+                let mut unconfirmed_substate = self.state.take_substate();
 
                 // TODO: make ActionParams pass by ref then avoid copy altogether.
                 let mut subvmtracer = vm_tracer.prepare_subtrace(
@@ -630,12 +717,24 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
             );
             return Err(evm::Error::MutableCallInStaticContext);
         }
+        // the code of the transaction's own creation is checked by `State::apply_with_options`
+        if self.depth > 0 {
+            if let Some(ref allowed) = self.state.allowed_code_hashes {
+                if !allowed.contains(&params.code_hash) {
+                    let trace_info = tracer.prepare_trace_create(&params);
+                    tracer.trace_failed_create(trace_info, vec![], evm::Error::CodeNotWhitelisted.into());
+                    return Err(evm::Error::CodeNotWhitelisted);
+                }
+            }
+        }
 
         // backup used in case of running out of gas
-        self.state.checkpoint();
+        self.state
+            .checkpoint()
+            .map_err(|err| evm::Error::Internal(format!("{}", err)))?;
 
         // part of substate that may be reverted
-        let mut unconfirmed_substate = Substate::new();
+        let mut unconfirmed_substate = self.state.take_substate();
 
         // create contract and transfer value to it if necessary
         /*
@@ -695,7 +794,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
     fn finalize(
         &mut self,
         t: &SignedTransaction,
-        substate: Substate,
+        substate: &mut Substate,
         result: evm::Result<FinalizationResult>,
         output: Bytes,
         trace: Vec<FlatTrace>,
@@ -757,7 +856,9 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         self.state.add_balance(&self.info.author, &fees_value, substate.to_cleanup_mode(&schedule))?;
          */
         // perform suicides
-        for address in &substate.suicides {
+        let mut suicides: Vec<Address> = substate.suicides.iter().cloned().collect();
+        suicides.sort();
+        for address in &suicides {
             self.state.kill_account(address);
         }
 
@@ -782,6 +883,8 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
                 trace: trace,
                 vm_trace: vm_trace,
                 state_diff: None,
+                storage_gas: U256::zero(),
+                suicides: suicides,
             }),
             Ok(r) => Ok(Executed {
                 exception: if r.apply_state {
@@ -793,12 +896,14 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
                 gas_used: gas_used,
                 refunded: refunded,
                 cumulative_gas_used: self.info.gas_used + gas_used,
-                logs: substate.logs,
-                contracts_created: substate.contracts_created,
+                logs: mem::replace(&mut substate.logs, Vec::new()),
+                contracts_created: mem::replace(&mut substate.contracts_created, Vec::new()),
                 output: output,
                 trace: trace,
                 vm_trace: vm_trace,
                 state_diff: None,
+                storage_gas: substate.storage_gas,
+                suicides: suicides,
             }),
         }
     }
@@ -807,7 +912,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
         &mut self,
         result: &evm::Result<FinalizationResult>,
         substate: &mut Substate,
-        un_substate: Substate,
+        mut un_substate: Substate,
     ) {
         match *result {
             Err(evm::Error::OutOfGas)
@@ -818,6 +923,7 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
             | Err(evm::Error::MutableCallInStaticContext)
             | Err(evm::Error::OutOfBounds)
             | Err(evm::Error::Reverted)
+            | Err(evm::Error::TooManyStorageWrites { .. })
             | Ok(FinalizationResult {
                 apply_state: false, ..
             }) => {
@@ -825,9 +931,10 @@ impl<'a, B: 'a + StateBackend> Executive<'a, B> {
             }
             Ok(_) | Err(evm::Error::Internal(_)) => {
                 self.state.discard_checkpoint();
-                substate.accrue(un_substate);
+                substate.accrue_drain(&mut un_substate);
             }
         }
+        self.state.recycle_substate(un_substate);
     }
 }
 
@@ -97,6 +97,8 @@ where
     /// Basic `Externalities` constructor.
     #[cfg_attr(feature = "dev", allow(too_many_arguments))]
     pub fn new(state: &'a mut State<B>, env_info: &'a EnvInfo, engine: &'a Engine, vm_factory: &'a Factory, native_factory: &'a NativeFactory, depth: usize, origin_info: OriginInfo, substate: &'a mut Substate, output: OutputPolicy<'a, 'a>, tracer: &'a mut T, vm_tracer: &'a mut V, static_flag: bool) -> Self {
+        let mut schedule = Schedule::new_v1();
+        schedule.cold_sload_gas = state.cold_sload_gas;
         Externalities {
             state: state,
             env_info: env_info,
@@ -106,7 +108,7 @@ where
             depth: depth,
             origin_info: origin_info,
             substate: substate,
-            schedule: Schedule::new_v1(),
+            schedule: schedule,
             output: output,
             tracer: tracer,
             vm_tracer: vm_tracer,
@@ -129,7 +131,15 @@ where
         if self.static_flag {
             Err(evm::Error::MutableCallInStaticContext)
         } else {
-            self.state.set_storage(&self.origin_info.address, key, value).map_err(Into::into)
+            let writes = self.state.note_storage_write();
+            if let Some(limit) = self.state.max_storage_writes_per_tx {
+                if writes > limit {
+                    return Err(evm::Error::TooManyStorageWrites { limit: limit });
+                }
+            }
+            self.state
+                .set_storage(&self.origin_info.address, key, value)
+                .map_err(|err| evm::Error::Internal(format!("{}", err)))
         }
     }
 
@@ -360,6 +370,18 @@ where
         self.substate.sstore_clears_count = self.substate.sstore_clears_count + U256::one();
     }
 
+    fn add_storage_gas(&mut self, gas: &U256) {
+        self.substate.storage_gas = self.substate.storage_gas + *gas;
+    }
+
+    fn is_storage_warm(&self, key: &H256) -> bool {
+        self.state.is_storage_warm(&self.origin_info.address, key)
+    }
+
+    fn warm_storage(&mut self, key: &H256) {
+        self.state.warm_storage(&self.origin_info.address, key);
+    }
+
     fn trace_prepare_execute(&mut self, pc: usize, instruction: u8, gas_cost: &U256) -> bool {
         self.vm_tracer.trace_prepare_execute(pc, instruction, gas_cost)
     }
@@ -25,6 +25,7 @@ extern crate libproto;
 #[macro_use]
 extern crate log;
 extern crate lru_cache;
+extern crate memmap;
 extern crate proof;
 extern crate protobuf;
 extern crate rlp;
@@ -36,6 +37,7 @@ extern crate util;
 
 extern crate bloomable;
 extern crate bloomchain;
+extern crate ethcore_bloom_journal as bloom_journal;
 #[macro_use]
 extern crate rlp_derive;
 extern crate rustc_hex;
@@ -84,6 +86,7 @@ pub mod cache_manager;
 pub mod executive;
 pub mod externalities;
 pub mod pod_account;
+pub mod pod_state;
 #[macro_use]
 pub mod evm;
 pub mod substate;
@@ -43,6 +43,22 @@ use util::{merklehash, Address, H256, HeapSizeOf, U256};
 /// Check the 256 transactions once
 const CHECK_NUM: usize = 0xff;
 
+/// Root block headers commit the receipts of their transactions to: a merkle tree
+/// over the RLP of every receipt in transaction order, `None` standing for a
+/// transaction rejected without a receipt.
+pub fn receipts_root(receipts: &[Option<Receipt>]) -> H256 {
+    merklehash::MerkleTree::from_bytes(receipts.iter().map(|r| r.rlp_bytes().to_vec())).get_root_hash()
+}
+
+/// Block-level log bloom of root block headers: the union of the blooms of all
+/// receipts, so it matches every address and topic logged in the block.
+pub fn logs_bloom(receipts: &[Option<Receipt>]) -> LogBloom {
+    receipts
+        .iter()
+        .filter_map(|r| r.as_ref())
+        .fold(LogBloom::zero(), |b, r| b | r.log_bloom)
+}
+
 /// Trait for a object that has a state database.
 pub trait Drain {
     /// Drop this object and return the underlieing database.
@@ -458,6 +474,56 @@ impl OpenBlock {
                 );
                 self.receipts.push(Some(receipt));
             }
+            Err(Error::Execution(ExecutionError::ContractBlacklisted)) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::ContractBlacklisted),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::SendToZeroAddress)) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::SendToZeroAddress),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::CodeNotWhitelisted)) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::CodeNotWhitelisted),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::SuicidedContract(_))) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::SuicidedContract),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::ContractAddressCollision(_))) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::ContractAddressCollision),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
             Err(Error::Execution(ExecutionError::NotEnoughBaseGas { .. })) => {
                 let receipt = Receipt::new(
                     None,
@@ -468,6 +534,36 @@ impl OpenBlock {
                 );
                 self.receipts.push(Some(receipt));
             }
+            Err(Error::Execution(ExecutionError::NotEnoughBaseQuota { .. })) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::OutOfQuota),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::InsufficientQuota { .. })) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::InsufficientQuota),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
+            Err(Error::Execution(ExecutionError::InvalidUntilBlock { .. })) => {
+                let receipt = Receipt::new(
+                    None,
+                    0.into(),
+                    Vec::new(),
+                    Some(ReceiptError::InvalidUntilBlock),
+                    0.into(),
+                );
+                self.receipts.push(Some(receipt));
+            }
             Err(Error::Execution(ExecutionError::BlockGasLimitReached { .. })) => {
                 let receipt = Receipt::new(
                     None,
@@ -498,21 +594,12 @@ impl OpenBlock {
     pub fn into_closed_block(mut self) -> ClosedBlock {
         // Rebuild block
         let state_root = *self.state.root();
-        let receipts_root =
-            merklehash::MerkleTree::from_bytes(self.receipts.iter().map(|r| r.rlp_bytes().to_vec())).get_root_hash();
+        let receipts_root = receipts_root(&self.receipts);
         self.set_state_root(state_root);
         self.set_receipts_root(receipts_root);
 
         // blocks blooms
-        let log_bloom = self.receipts
-            .clone()
-            .into_iter()
-            .filter_map(|r| r)
-            .fold(LogBloom::zero(), |mut b, r| {
-                b = b | r.log_bloom;
-                b
-            });
-
+        let log_bloom = logs_bloom(&self.receipts);
         self.set_log_bloom(log_bloom);
 
         ClosedBlock { block: self }
@@ -522,7 +609,10 @@ impl OpenBlock {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bloomable::Bloomable;
+    use log_entry::LogEntry;
     use rlp;
+    use util::Hashable;
 
     #[test]
     fn test_encode_and_decode() {
@@ -539,4 +629,52 @@ mod tests {
         assert_eq!(body_rlp, body_encoded);
     }
 
+    #[test]
+    fn receipts_root_is_stable() {
+        let receipt = |gas_used: u64, error: Option<ReceiptError>| {
+            Some(Receipt::new(None, gas_used.into(), Vec::new(), error, 0.into()))
+        };
+        let receipts = vec![
+            receipt(21_000, None),
+            None,
+            receipt(50_000, Some(ReceiptError::OutOfGas)),
+        ];
+
+        let root = receipts_root(&receipts);
+        assert_eq!(root, receipts_root(&receipts.clone()));
+        assert_eq!(
+            root,
+            merklehash::MerkleTree::from_bytes(receipts.iter().map(|r| rlp::encode(r).into_vec())).get_root_hash()
+        );
+        // the root commits to the order and to rejected transactions
+        let mut reordered = receipts.clone();
+        reordered.swap(0, 2);
+        assert!(receipts_root(&reordered) != root);
+        assert!(receipts_root(&receipts[..1]) != receipts_root(&[receipts[0].clone(), None]));
+    }
+
+    #[test]
+    fn logs_bloom_covers_all_logs() {
+        let log = |address: u64, topics: Vec<H256>| LogEntry {
+            address: Address::from(address),
+            topics: topics,
+            data: Vec::new(),
+        };
+        let first = vec![log(1, vec![H256::from(10)]), log(2, vec![H256::from(20), H256::from(21)])];
+        let second = vec![log(3, vec![H256::from(30)])];
+        let receipt = |logs: Vec<LogEntry>| Some(Receipt::new(None, 0.into(), logs, None, 0.into()));
+        let receipts = vec![receipt(first.clone()), None, receipt(second.clone()), receipt(Vec::new())];
+
+        let bloom = logs_bloom(&receipts);
+        for entry in first.iter().chain(second.iter()) {
+            assert!(bloom.contains_bloomed(&entry.address.crypt_hash()));
+            for topic in &entry.topics {
+                assert!(bloom.contains_bloomed(&topic.crypt_hash()));
+            }
+            assert!(bloom.contains_bloom(&entry.bloom()));
+        }
+        assert!(!bloom.contains_bloomed(&H256::from(40).crypt_hash()));
+        assert_eq!(logs_bloom(&[None]), LogBloom::zero());
+    }
+
 }
@@ -545,6 +545,12 @@ impl Executor {
             vm_tracing: analytics.vm_tracing,
             check_permission: false,
             check_quota: false,
+            system_transaction: false,
+            gas_price: None,
+            opcode_profiling: false,
+            account_events: false,
+            state_growth: false,
+            create_salt: None,
         };
 
         let ret = Executive::new(
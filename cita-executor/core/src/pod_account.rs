@@ -53,7 +53,7 @@ impl PodAccount {
     pub fn from_account(acc: &Account) -> PodAccount {
         PodAccount {
             nonce: *acc.nonce(),
-            storage: acc.storage_changes().iter().fold(BTreeMap::new(), |mut m, (k, v)| {
+            storage: acc.storage_changes().fold(BTreeMap::new(), |mut m, (k, v)| {
                 m.insert(*k, *v);
                 m
             }),
@@ -0,0 +1,58 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// Parity is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! State of all accounts in the system expressed in Plain Old Data.
+
+use pod_account::PodAccount;
+use std::collections::BTreeMap;
+use std::fmt;
+use util::Address;
+
+/// State of all accounts in the system expressed in Plain Old Data.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PodState(BTreeMap<Address, PodAccount>);
+
+impl PodState {
+    /// Construct an empty state.
+    pub fn new() -> PodState {
+        Default::default()
+    }
+
+    /// Get the underlying map.
+    pub fn get(&self) -> &BTreeMap<Address, PodAccount> {
+        &self.0
+    }
+
+    /// Drain object to get the underlying map.
+    pub fn drain(self) -> BTreeMap<Address, PodAccount> {
+        self.0
+    }
+}
+
+impl From<BTreeMap<Address, PodAccount>> for PodState {
+    fn from(m: BTreeMap<Address, PodAccount>) -> PodState {
+        PodState(m)
+    }
+}
+
+impl fmt::Display for PodState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (add, acc) in &self.0 {
+            writeln!(f, "{} => {}", add, acc)?;
+        }
+        Ok(())
+    }
+}
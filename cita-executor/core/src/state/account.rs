@@ -21,13 +21,211 @@ use lru_cache::LruCache;
 use pod_account::*;
 use rlp::*;
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{hash_map, HashMap};
 use std::fmt;
+use std::mem;
 use std::sync::Arc;
 use types::basic_account::BasicAccount;
 use util::*;
 
-const STORAGE_CACHE_ITEMS: usize = 8192;
+// Modified storage of an account. Values are kept inline unless interning is enabled,
+// then identical values written to many keys share a single allocation.
+#[derive(Clone)]
+enum StorageOverlay {
+    Plain(HashMap<H256, H256>),
+    // changes and their shared values, keyed by value
+    Interned(HashMap<H256, Arc<H256>>, HashMap<H256, Arc<H256>>),
+}
+
+impl Default for StorageOverlay {
+    fn default() -> Self {
+        StorageOverlay::Plain(HashMap::new())
+    }
+}
+
+impl StorageOverlay {
+    fn intern(&mut self) {
+        let plain = match *self {
+            StorageOverlay::Plain(ref mut changes) => mem::replace(changes, HashMap::new()),
+            StorageOverlay::Interned(..) => return,
+        };
+        *self = StorageOverlay::Interned(HashMap::new(), HashMap::new());
+        for (key, value) in plain {
+            self.insert(key, value);
+        }
+    }
+
+    fn insert(&mut self, key: H256, value: H256) {
+        match *self {
+            StorageOverlay::Plain(ref mut changes) => {
+                changes.insert(key, value);
+            }
+            StorageOverlay::Interned(ref mut changes, ref mut values) => {
+                let value = Arc::clone(values.entry(value).or_insert_with(|| Arc::new(value)));
+                changes.insert(key, value);
+            }
+        }
+    }
+
+    fn get(&self, key: &H256) -> Option<H256> {
+        match *self {
+            StorageOverlay::Plain(ref changes) => changes.get(key).cloned(),
+            StorageOverlay::Interned(ref changes, _) => changes.get(key).map(|value| **value),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            StorageOverlay::Plain(ref changes) => changes.len(),
+            StorageOverlay::Interned(ref changes, _) => changes.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self) {
+        match *self {
+            StorageOverlay::Plain(ref mut changes) => changes.clear(),
+            StorageOverlay::Interned(ref mut changes, ref mut values) => {
+                changes.clear();
+                values.clear();
+            }
+        }
+    }
+
+    // Take all changes, interning stays enabled.
+    fn drain(&mut self) -> Vec<(H256, H256)> {
+        let changes: Vec<(H256, H256)> = match *self {
+            StorageOverlay::Plain(ref mut changes) => changes.drain().collect(),
+            StorageOverlay::Interned(ref mut changes, _) => changes.drain().map(|(k, v)| (k, *v)).collect(),
+        };
+        self.clear();
+        changes
+    }
+
+    fn iter(&self) -> StorageChanges {
+        match *self {
+            StorageOverlay::Plain(ref changes) => StorageChanges::Plain(changes.iter()),
+            StorageOverlay::Interned(ref changes, _) => StorageChanges::Interned(changes.iter()),
+        }
+    }
+
+    fn memory_estimate(&self) -> usize {
+        match *self {
+            StorageOverlay::Plain(ref changes) => changes.len() * 2 * mem::size_of::<H256>(),
+            StorageOverlay::Interned(ref changes, ref values) => {
+                changes.len() * (mem::size_of::<H256>() + mem::size_of::<Arc<H256>>())
+                    + values.len() * mem::size_of::<H256>()
+            }
+        }
+    }
+}
+
+/// Iterator over the modified storage slots of an account, see `Account::storage_changes`.
+pub enum StorageChanges<'a> {
+    #[doc(hidden)]
+    Plain(hash_map::Iter<'a, H256, H256>),
+    #[doc(hidden)]
+    Interned(hash_map::Iter<'a, H256, Arc<H256>>),
+}
+
+impl<'a> Iterator for StorageChanges<'a> {
+    type Item = (&'a H256, &'a H256);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            StorageChanges::Plain(ref mut iter) => iter.next(),
+            StorageChanges::Interned(ref mut iter) => iter.next().map(|(key, value)| (key, &**value)),
+        }
+    }
+}
+
+/// `HashDB` view of an account database serving the staged storage trie nodes of an account first,
+/// see `Account::storage_db`.
+pub struct StagedNodesDB<'a> {
+    db: &'a HashDB,
+    staged: &'a HashMap<H256, DBValue>,
+}
+
+impl<'a> HashDB for StagedNodesDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        let mut keys = self.db.keys();
+        for hash in self.staged.keys() {
+            keys.entry(*hash).or_insert(1);
+        }
+        keys
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        match self.staged.get(key) {
+            Some(node) => Some(node.clone()),
+            None => self.db.get(key),
+        }
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.staged.contains_key(key) || self.db.contains(key)
+    }
+
+    fn insert(&mut self, _value: &[u8]) -> H256 {
+        unimplemented!()
+    }
+
+    fn emplace(&mut self, _key: H256, _value: DBValue) {
+        unimplemented!()
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        unimplemented!()
+    }
+}
+
+/// Set of account fields modified since the last commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DirtyFields(u8);
+
+impl DirtyFields {
+    /// The nonce was changed.
+    pub const NONCE: DirtyFields = DirtyFields(0b0001);
+    /// The code was set.
+    pub const CODE: DirtyFields = DirtyFields(0b0010);
+    /// The ABI was set.
+    pub const ABI: DirtyFields = DirtyFields(0b0100);
+    /// The storage was written or replaced.
+    pub const STORAGE: DirtyFields = DirtyFields(0b1000);
+
+    /// No field modified.
+    pub fn empty() -> DirtyFields {
+        DirtyFields(0)
+    }
+
+    /// Every field modified.
+    pub fn all() -> DirtyFields {
+        DirtyFields(0b1111)
+    }
+
+    /// Whether no field is modified.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all fields of `other` are modified.
+    pub fn contains(&self, other: DirtyFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Mark the fields of `other` as modified.
+    pub fn insert(&mut self, other: DirtyFields) {
+        self.0 |= other.0;
+    }
+
+    /// Mark the fields of `other` as committed.
+    pub fn remove(&mut self, other: DirtyFields) {
+        self.0 &= !other.0;
+    }
+}
 
 /// Single account in the system.
 /// Keeps track of changes to the code and storage.
@@ -38,21 +236,24 @@ pub struct Account {
     // Trie-backed storage.
     storage_root: H256,
     // LRU Cache of the trie-backed storage.
-    // This is limited to `STORAGE_CACHE_ITEMS` recent queries
+    // Unbounded unless limited with `set_storage_cache_limit`.
     storage_cache: RefCell<LruCache<H256, H256>>,
     // Modified storage. Accumulates changes to storage made in `set_storage`
     // Takes precedence over `storage_cache`.
-    storage_changes: HashMap<H256, H256>,
-    // Code hash of the account.
-    code_hash: H256,
+    storage_changes: StorageOverlay,
+    // Nodes of the storage trie below `storage_root` not yet in the account database,
+    // e.g. after `swap_storage`. Written by `commit_storage`.
+    staged_nodes: HashMap<H256, DBValue>,
+    // Code hash of the account. `None` while hashing of new code is deferred.
+    code_hash: Cell<Option<H256>>,
     // Size of the account code.
     code_size: Option<usize>,
     // Code cache of the account.
     code_cache: Arc<Bytes>,
     // Account code new or has been modified.
     code_filth: Filth,
-    // ABI hash of the account.
-    abi_hash: H256,
+    // ABI hash of the account. `None` while hashing of new ABI is deferred.
+    abi_hash: Cell<Option<H256>>,
     // Size of the account ABI.
     abi_size: Option<usize>,
     // ABI cache of the account.
@@ -61,6 +262,10 @@ pub struct Account {
     abi_filth: Filth,
     // Cached address hash.
     address_hash: Cell<Option<H256>>,
+    // Block number of the last mutation, if recorded.
+    last_touched: Option<u64>,
+    // Fields modified since the last commit.
+    dirty_fields: DirtyFields,
 }
 
 impl From<BasicAccount> for Account {
@@ -69,20 +274,48 @@ impl From<BasicAccount> for Account {
             nonce: basic.nonce,
             storage_root: basic.storage_root,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: HashMap::new(),
-            code_hash: basic.code_hash,
+            storage_changes: StorageOverlay::default(),
+            staged_nodes: HashMap::new(),
+            code_hash: Cell::new(Some(basic.code_hash)),
             code_size: None,
             code_cache: Arc::new(vec![]),
             code_filth: Filth::Clean,
-            abi_hash: basic.abi_hash,
+            abi_hash: Cell::new(Some(basic.abi_hash)),
             abi_size: None,
             abi_cache: Arc::new(vec![]),
             abi_filth: Filth::Clean,
             address_hash: Cell::new(None),
+            last_touched: basic.last_touched,
+            dirty_fields: DirtyFields::empty(),
         }
     }
 }
 
+#[cfg(test)]
+thread_local!(pub static HASH_COUNT: Cell<usize> = Cell::new(0));
+
+#[cfg(test)]
+fn hash_bytes(bytes: &Bytes) -> H256 {
+    HASH_COUNT.with(|count| count.set(count.get() + 1));
+    bytes.crypt_hash()
+}
+
+#[cfg(not(test))]
+fn hash_bytes(bytes: &Bytes) -> H256 {
+    bytes.crypt_hash()
+}
+
+#[cfg(test)]
+thread_local!(pub static COMMIT_COUNT: Cell<usize> = Cell::new(0));
+
+#[cfg(test)]
+fn note_commit() {
+    COMMIT_COUNT.with(|count| count.set(count.get() + 1));
+}
+
+#[cfg(not(test))]
+fn note_commit() {}
+
 impl Account {
     #[cfg(test)]
     /// General constructor.
@@ -91,21 +324,30 @@ impl Account {
             nonce: nonce,
             storage_root: HASH_NULL_RLP,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: storage,
-            code_hash: code.crypt_hash(),
+            storage_changes: StorageOverlay::Plain(storage),
+            staged_nodes: HashMap::new(),
+            code_hash: Cell::new(Some(code.crypt_hash())),
             code_size: Some(code.len()),
             code_cache: Arc::new(code),
             code_filth: Filth::Dirty,
-            abi_hash: abi.crypt_hash(),
+            abi_hash: Cell::new(Some(abi.crypt_hash())),
             abi_size: Some(abi.len()),
             abi_cache: Arc::new(abi),
             abi_filth: Filth::Dirty,
             address_hash: Cell::new(None),
+            last_touched: None,
+            dirty_fields: DirtyFields::all(),
         }
     }
 
     fn empty_storage_cache() -> RefCell<LruCache<H256, H256>> {
-        RefCell::new(LruCache::new(STORAGE_CACHE_ITEMS))
+        RefCell::new(LruCache::new(usize::max_value()))
+    }
+
+    /// Limit the number of clean storage slots cached, evicting the least recently
+    /// used ones. Modified slots are kept in `storage_changes` and never evicted.
+    pub fn set_storage_cache_limit(&mut self, items: usize) {
+        self.storage_cache.get_mut().set_capacity(items);
     }
 
     /// General constructor.
@@ -114,8 +356,9 @@ impl Account {
             nonce: pod.nonce,
             storage_root: HASH_NULL_RLP,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: pod.storage.into_iter().collect(),
-            code_hash: pod.code.as_ref().map_or(HASH_EMPTY, |c| c.crypt_hash()),
+            storage_changes: StorageOverlay::Plain(pod.storage.into_iter().collect()),
+            staged_nodes: HashMap::new(),
+            code_hash: Cell::new(Some(pod.code.as_ref().map_or(HASH_EMPTY, |c| c.crypt_hash()))),
             code_filth: Filth::Dirty,
             code_size: Some(pod.code.as_ref().map_or(0, |c| c.len())),
             code_cache: Arc::new(pod.code.map_or_else(
@@ -125,7 +368,7 @@ impl Account {
                 },
                 |c| c,
             )),
-            abi_hash: pod.abi.as_ref().map_or(HASH_EMPTY, |c| c.crypt_hash()),
+            abi_hash: Cell::new(Some(pod.abi.as_ref().map_or(HASH_EMPTY, |c| c.crypt_hash()))),
             abi_filth: Filth::Dirty,
             abi_size: Some(pod.abi.as_ref().map_or(0, |c| c.len())),
             abi_cache: Arc::new(pod.abi.map_or_else(
@@ -136,6 +379,8 @@ impl Account {
                 |c| c,
             )),
             address_hash: Cell::new(None),
+            last_touched: None,
+            dirty_fields: DirtyFields::all(),
         }
     }
 
@@ -145,16 +390,19 @@ impl Account {
             nonce: nonce,
             storage_root: HASH_NULL_RLP,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: HashMap::new(),
-            code_hash: HASH_EMPTY,
+            storage_changes: StorageOverlay::default(),
+            staged_nodes: HashMap::new(),
+            code_hash: Cell::new(Some(HASH_EMPTY)),
             code_cache: Arc::new(vec![]),
             code_size: Some(0),
             code_filth: Filth::Clean,
-            abi_hash: HASH_EMPTY,
+            abi_hash: Cell::new(Some(HASH_EMPTY)),
             abi_cache: Arc::new(vec![]),
             abi_size: Some(0),
             abi_filth: Filth::Clean,
             address_hash: Cell::new(None),
+            last_touched: None,
+            dirty_fields: DirtyFields::empty(),
         }
     }
 
@@ -171,34 +419,53 @@ impl Account {
             nonce: nonce,
             storage_root: HASH_NULL_RLP,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: HashMap::new(),
-            code_hash: HASH_EMPTY,
+            storage_changes: StorageOverlay::default(),
+            staged_nodes: HashMap::new(),
+            code_hash: Cell::new(Some(HASH_EMPTY)),
             code_cache: Arc::new(vec![]),
             code_size: None,
             code_filth: Filth::Clean,
-            abi_hash: HASH_EMPTY,
+            abi_hash: Cell::new(Some(HASH_EMPTY)),
             abi_cache: Arc::new(vec![]),
             abi_size: None,
             abi_filth: Filth::Clean,
             address_hash: Cell::new(None),
+            last_touched: None,
+            dirty_fields: DirtyFields::empty(),
         }
     }
 
     /// Set this account's code to the given code.
     /// NOTE: Account should have been created with `new_contract()`
     pub fn init_code(&mut self, code: Bytes) {
-        self.code_hash = code.crypt_hash();
+        self.init_code_deferred(code);
+        self.code_hash();
+    }
+
+    /// Set this account's code to the given code without hashing it.
+    /// The hash is computed on the first `code_hash()` read or at commit.
+    pub fn init_code_deferred(&mut self, code: Bytes) {
+        self.code_hash.set(None);
         self.code_cache = Arc::new(code);
         self.code_size = Some(self.code_cache.len());
         self.code_filth = Filth::Dirty;
+        self.dirty_fields.insert(DirtyFields::CODE);
     }
 
     /// Set this account's ABI to the given ABI.
     pub fn init_abi(&mut self, abi: Bytes) {
-        self.abi_hash = abi.crypt_hash();
+        self.init_abi_deferred(abi);
+        self.abi_hash();
+    }
+
+    /// Set this account's ABI to the given ABI without hashing it.
+    /// The hash is computed on the first `abi_hash()` read or at commit.
+    pub fn init_abi_deferred(&mut self, abi: Bytes) {
+        self.abi_hash.set(None);
         self.abi_cache = Arc::new(abi);
         self.abi_size = Some(self.abi_cache.len());
         self.abi_filth = Filth::Dirty;
+        self.dirty_fields.insert(DirtyFields::ABI);
     }
 
     /// Reset this account's code to the given code.
@@ -214,6 +481,35 @@ impl Account {
     /// Set (and cache) the contents of the trie's storage at `key` to `value`.
     pub fn set_storage(&mut self, key: H256, value: H256) {
         self.storage_changes.insert(key, value);
+        self.dirty_fields.insert(DirtyFields::STORAGE);
+    }
+
+    /// Drop the whole storage of the account and set exactly the entries of `storage`.
+    /// Slots of the previous storage trie are not carried over.
+    pub fn replace_storage(&mut self, storage: HashMap<H256, H256>) {
+        self.storage_root = HASH_NULL_RLP;
+        self.dirty_fields.insert(DirtyFields::STORAGE);
+        self.storage_cache.get_mut().clear();
+        self.storage_changes.clear();
+        for (key, value) in storage {
+            self.set_storage(key, value);
+        }
+    }
+
+    /// Exchange the storage, committed and uncommitted, with `other`.
+    /// NOTE: The storage trie nodes must be present in the account databases of both accounts.
+    pub fn swap_storage(&mut self, other: &mut Account) {
+        mem::swap(&mut self.storage_root, &mut other.storage_root);
+        mem::swap(&mut self.staged_nodes, &mut other.staged_nodes);
+        mem::swap(&mut self.storage_cache, &mut other.storage_cache);
+        mem::swap(&mut self.storage_changes, &mut other.storage_changes);
+        self.dirty_fields.insert(DirtyFields::STORAGE);
+        other.dirty_fields.insert(DirtyFields::STORAGE);
+    }
+
+    /// Share identical values written with `set_storage` until the next `commit_storage`.
+    pub fn enable_storage_interning(&mut self) {
+        self.storage_changes.intern();
     }
 
     /// Get (and cache) the contents of the trie's storage at `key`.
@@ -223,7 +519,8 @@ impl Account {
             return Ok(value);
         }
 
-        let t = trie_factory.readonly(db, &self.storage_root)?;
+        let db = self.storage_db(db);
+        let t = trie_factory.readonly(&db, &self.storage_root)?;
 
         let item: U256 = t.get_with(key, ::rlp::decode)?.unwrap_or_else(U256::zero);
         let value: H256 = item.into();
@@ -235,7 +532,7 @@ impl Account {
     /// key is not in the cache.
     pub fn cached_storage_at(&self, key: &H256) -> Option<H256> {
         if let Some(value) = self.storage_changes.get(key) {
-            return Some(*value);
+            return Some(value);
         }
         if let Some(value) = self.storage_cache.borrow_mut().get_mut(key) {
             return Some(*value);
@@ -248,14 +545,32 @@ impl Account {
         &self.nonce
     }
 
+    /// return the block number of the last mutation, if recorded.
+    pub fn last_touched(&self) -> Option<u64> {
+        self.last_touched
+    }
+
+    /// Record `number` as the block of the last mutation.
+    pub fn touch(&mut self, number: u64) {
+        self.last_touched = Some(number);
+    }
+
     /// return the code hash associated with this account.
     pub fn code_hash(&self) -> H256 {
-        self.code_hash
+        self.code_hash.get().unwrap_or_else(|| {
+            let hash = hash_bytes(&self.code_cache);
+            self.code_hash.set(Some(hash));
+            hash
+        })
     }
 
     /// return the abi hash associated with this account.
     pub fn abi_hash(&self) -> H256 {
-        self.abi_hash
+        self.abi_hash.get().unwrap_or_else(|| {
+            let hash = hash_bytes(&self.abi_cache);
+            self.abi_hash.set(Some(hash));
+            hash
+        })
     }
 
     /// return the code hash associated with this account.
@@ -271,7 +586,7 @@ impl Account {
     /// returns the account's code. If `None` then the code cache isn't available -
     /// get someone who knows to call `note_code`.
     pub fn code(&self) -> Option<Arc<Bytes>> {
-        if self.code_hash != HASH_EMPTY && self.code_cache.is_empty() {
+        if self.code_hash() != HASH_EMPTY && self.code_cache.is_empty() {
             return None;
         }
         Some(Arc::clone(&self.code_cache))
@@ -280,7 +595,7 @@ impl Account {
     /// returns the account's abi. If `None` then the abi cache isn't available -
     /// get someone who knows to call `abi_code`.
     pub fn abi(&self) -> Option<Arc<Bytes>> {
-        if self.abi_hash != HASH_EMPTY && self.abi_cache.is_empty() {
+        if self.abi_hash() != HASH_EMPTY && self.abi_cache.is_empty() {
             return None;
         }
         Some(Arc::clone(&self.abi_cache))
@@ -302,7 +617,7 @@ impl Account {
     /// Provide a byte array which hashes to the `code_hash`. returns the hash as a result.
     pub fn note_code(&mut self, code: Bytes) -> Result<(), H256> {
         let h = code.crypt_hash();
-        if self.code_hash == h {
+        if self.code_hash() == h {
             self.code_cache = Arc::new(code);
             self.code_size = Some(self.code_cache.len());
             Ok(())
@@ -315,7 +630,7 @@ impl Account {
     /// Provide a byte array which hashes to the `abi_hash`. returns the hash as a result.
     pub fn note_abi(&mut self, abi: Bytes) -> Result<(), H256> {
         let h = abi.crypt_hash();
-        if self.abi_hash == h {
+        if self.abi_hash() == h {
             self.abi_cache = Arc::new(abi);
             self.abi_size = Some(self.abi_cache.len());
             Ok(())
@@ -326,12 +641,12 @@ impl Account {
 
     /// Is `code_cache` valid; such that code is going to return Some?
     pub fn is_cached(&self) -> bool {
-        !self.code_cache.is_empty() || (self.code_cache.is_empty() && self.code_hash == HASH_EMPTY)
+        !self.code_cache.is_empty() || (self.code_cache.is_empty() && self.code_hash() == HASH_EMPTY)
     }
 
     /// Is `abi_cache` valid; such that abi is going to return Some?
     pub fn is_abi_cached(&self) -> bool {
-        !self.abi_cache.is_empty() || (self.abi_cache.is_empty() && self.abi_hash == HASH_EMPTY)
+        !self.abi_cache.is_empty() || (self.abi_cache.is_empty() && self.abi_hash() == HASH_EMPTY)
     }
 
     /// Provide a database to get `code_hash`. Should not be called if it is a contract without code.
@@ -340,7 +655,7 @@ impl Account {
         trace!(
             "Account::cache_code: ic={}; self.code_hash={:?}, self.code_cache={}",
             self.is_cached(),
-            self.code_hash,
+            self.code_hash(),
             self.code_cache.pretty()
         );
 
@@ -348,14 +663,14 @@ impl Account {
             return Some(Arc::clone(&self.code_cache));
         }
 
-        match db.get(&self.code_hash) {
+        match db.get(&self.code_hash()) {
             Some(x) => {
                 self.code_size = Some(x.len());
                 self.code_cache = Arc::new(x.to_vec());
                 Some(Arc::clone(&self.code_cache))
             }
             _ => {
-                warn!("Failed reverse get of {}", self.code_hash);
+                warn!("Failed reverse get of {}", self.code_hash());
                 None
             }
         }
@@ -367,7 +682,7 @@ impl Account {
         trace!(
             "Account::cache_abi: ic={}; self.abi_hash={:?}, self.abi_cache={}",
             self.is_abi_cached(),
-            self.abi_hash,
+            self.abi_hash(),
             self.abi_cache.pretty()
         );
 
@@ -375,14 +690,14 @@ impl Account {
             return Some(Arc::clone(&self.abi_cache));
         }
 
-        match db.get(&self.abi_hash) {
+        match db.get(&self.abi_hash()) {
             Some(x) => {
                 self.abi_size = Some(x.len());
                 self.abi_cache = Arc::new(x.to_vec());
                 Some(Arc::clone(&self.abi_cache))
             }
             _ => {
-                warn!("Failed reverse get of {}", self.abi_hash);
+                warn!("Failed reverse get of {}", self.abi_hash());
                 None
             }
         }
@@ -394,7 +709,7 @@ impl Account {
         trace!(
             "Account::cache_given_code: ic={}; self.code_hash={:?}, self.code_cache={}",
             self.is_cached(),
-            self.code_hash,
+            self.code_hash(),
             self.code_cache.pretty()
         );
 
@@ -408,7 +723,7 @@ impl Account {
         trace!(
             "Account::cache_given_abi: ic={}; self.abi_hash={:?}, self.abi_cache={}",
             self.is_abi_cached(),
-            self.abi_hash,
+            self.abi_hash(),
             self.abi_cache.pretty()
         );
 
@@ -422,17 +737,17 @@ impl Account {
         trace!(
             "Account::cache_code_size: ic={}; self.code_hash={:?}, self.code_cache={}",
             self.is_cached(),
-            self.code_hash,
+            self.code_hash(),
             self.code_cache.pretty()
         );
-        self.code_size.is_some() || if self.code_hash != HASH_EMPTY {
-            match db.get(&self.code_hash) {
+        self.code_size.is_some() || if self.code_hash() != HASH_EMPTY {
+            match db.get(&self.code_hash()) {
                 Some(x) => {
                     self.code_size = Some(x.len());
                     true
                 }
                 _ => {
-                    warn!("Failed reverse get of {}", self.code_hash);
+                    warn!("Failed reverse get of {}", self.code_hash());
                     false
                 }
             }
@@ -447,17 +762,17 @@ impl Account {
         trace!(
             "Account::cache_abi_size: ic={}; self.abi_hash={:?}, self.abi_cache={}",
             self.is_abi_cached(),
-            self.abi_hash,
+            self.abi_hash(),
             self.abi_cache.pretty()
         );
-        self.abi_size.is_some() || if self.abi_hash != HASH_EMPTY {
-            match db.get(&self.abi_hash) {
+        self.abi_size.is_some() || if self.abi_hash() != HASH_EMPTY {
+            match db.get(&self.abi_hash()) {
                 Some(x) => {
                     self.abi_size = Some(x.len());
                     true
                 }
                 _ => {
-                    warn!("Failed reverse get of {}", self.abi_hash);
+                    warn!("Failed reverse get of {}", self.abi_hash());
                     false
                 }
             }
@@ -466,11 +781,23 @@ impl Account {
         }
     }
 
+    /// Estimated number of bytes held by the cached code, ABI and storage of this account.
+    pub fn cache_memory_estimate(&self) -> usize {
+        self.code_cache.len() + self.abi_cache.len()
+            + self.storage_cache.borrow().len() * 2 * mem::size_of::<H256>()
+            + self.storage_changes.memory_estimate()
+    }
+
     /// Determine whether there are any un-`commit()`-ed storage-setting operations.
     pub fn storage_is_clean(&self) -> bool {
         self.storage_changes.is_empty()
     }
 
+    /// Determine whether there is any un-`commit()`-ed storage, code or abi.
+    pub fn has_uncommitted_changes(&self) -> bool {
+        !self.storage_is_clean() || self.code_filth == Filth::Dirty || self.abi_filth == Filth::Dirty
+    }
+
     /// Check if account has zero nonce, no code and no storage.
     ///
     /// NOTE: Will panic if `!self.storage_is_clean()`
@@ -484,7 +811,7 @@ impl Account {
 
     /// Check if account has zero nonce, no code, no abi.
     pub fn is_null(&self) -> bool {
-        self.nonce.is_zero() && self.code_hash == HASH_EMPTY && self.abi_hash == HASH_EMPTY
+        self.nonce.is_zero() && self.code_hash() == HASH_EMPTY && self.abi_hash() == HASH_EMPTY
     }
 
     /// Return the storage root associated with this account or None if it has been altered via the overlay.
@@ -496,18 +823,68 @@ impl Account {
         }
     }
 
-    /// Return the storage overlay.
-    pub fn storage_changes(&self) -> &HashMap<H256, H256> {
-        &self.storage_changes
+    /// Return the storage root the storage overlay applies to.
+    pub fn base_storage_root(&self) -> &H256 {
+        &self.storage_root
+    }
+
+    /// Add nodes of the storage trie below `base_storage_root` missing from the account database.
+    /// They are written by `commit_storage` and dropped with the account, e.g. on revert.
+    pub fn stage_storage_nodes(&mut self, nodes: Vec<(H256, Bytes)>) {
+        for (hash, node) in nodes {
+            self.staged_nodes.insert(hash, DBValue::from_slice(&node));
+        }
+        self.dirty_fields.insert(DirtyFields::STORAGE);
+    }
+
+    /// View of the account database `db` including the staged storage trie nodes.
+    pub fn storage_db<'a>(&'a self, db: &'a HashDB) -> StagedNodesDB<'a> {
+        StagedNodesDB {
+            db: db,
+            staged: &self.staged_nodes,
+        }
+    }
+
+    /// Iterate the storage overlay, modified slots with their new values.
+    /// Use `storage_change` to look up a single slot.
+    pub fn storage_changes(&self) -> StorageChanges {
+        self.storage_changes.iter()
+    }
+
+    /// Return the overlaid value of storage slot `key`, if it was modified.
+    pub fn storage_change(&self, key: &H256) -> Option<H256> {
+        self.storage_changes.get(key)
+    }
+
+    /// Fields modified since the last commit.
+    pub fn dirty_fields(&self) -> DirtyFields {
+        self.dirty_fields
+    }
+
+    /// Forget the modified fields once the account has been written to the state trie.
+    pub fn clear_dirty_fields(&mut self) {
+        self.dirty_fields = DirtyFields::empty();
     }
 
     /// Increment the nonce of the account by one.
     pub fn inc_nonce(&mut self) {
         self.nonce = self.nonce + U256::from(1u8);
+        self.dirty_fields.insert(DirtyFields::NONCE);
+    }
+
+    /// Set the nonce of the account.
+    pub fn set_nonce(&mut self, nonce: U256) {
+        self.nonce = nonce;
+        self.dirty_fields.insert(DirtyFields::NONCE);
     }
 
     /// Commit the `storage_changes` to the backing DB and update `storage_root`.
     pub fn commit_storage(&mut self, trie_factory: &TrieFactory, db: &mut HashDB) -> trie::Result<()> {
+        note_commit();
+        self.dirty_fields.remove(DirtyFields::STORAGE);
+        for (hash, node) in self.staged_nodes.drain() {
+            db.emplace(hash, node);
+        }
         let mut t = trie_factory.from_existing(db, &mut self.storage_root)?;
         for (k, v) in self.storage_changes.drain() {
             // cast key and value to trait type,
@@ -525,6 +902,8 @@ impl Account {
 
     /// Commit any unsaved code. `code_hash` will always return the hash of the `code_cache` after this.
     pub fn commit_code(&mut self, db: &mut HashDB) {
+        note_commit();
+        self.dirty_fields.remove(DirtyFields::CODE);
         trace!(
             "Commiting code of {:?} - {:?}, {:?}",
             self,
@@ -537,7 +916,7 @@ impl Account {
                 self.code_filth = Filth::Clean;
             }
             (true, false) => {
-                db.emplace(self.code_hash, DBValue::from_slice(&*self.code_cache));
+                db.emplace(self.code_hash(), DBValue::from_slice(&*self.code_cache));
                 self.code_size = Some(self.code_cache.len());
                 self.code_filth = Filth::Clean;
             }
@@ -547,6 +926,8 @@ impl Account {
 
     /// Commit any unsaved abi. `abi_hash` will always return the hash of the `abi_cache` after this.
     pub fn commit_abi(&mut self, db: &mut HashDB) {
+        note_commit();
+        self.dirty_fields.remove(DirtyFields::ABI);
         trace!(
             "Commiting abi of {:?} - {:?}, {:?}",
             self,
@@ -559,7 +940,7 @@ impl Account {
                 self.abi_filth = Filth::Clean;
             }
             (true, false) => {
-                db.emplace(self.abi_hash, DBValue::from_slice(&*self.abi_cache));
+                db.emplace(self.abi_hash(), DBValue::from_slice(&*self.abi_cache));
                 self.abi_size = Some(self.abi_cache.len());
                 self.abi_filth = Filth::Clean;
             }
@@ -569,11 +950,14 @@ impl Account {
 
     /// Export to RLP.
     pub fn rlp(&self) -> Bytes {
-        let mut stream = RlpStream::new_list(4);
+        let mut stream = RlpStream::new_list(if self.last_touched.is_some() { 5 } else { 4 });
         stream.append(&self.nonce);
         stream.append(&self.storage_root);
-        stream.append(&self.code_hash);
-        stream.append(&self.abi_hash);
+        stream.append(&self.code_hash());
+        stream.append(&self.abi_hash());
+        if let Some(ref number) = self.last_touched {
+            stream.append(number);
+        }
         stream.out()
     }
 
@@ -583,16 +967,19 @@ impl Account {
             nonce: self.nonce,
             storage_root: self.storage_root,
             storage_cache: Self::empty_storage_cache(),
-            storage_changes: HashMap::new(),
-            code_hash: self.code_hash,
+            storage_changes: StorageOverlay::default(),
+            staged_nodes: HashMap::new(),
+            code_hash: self.code_hash.clone(),
             code_size: self.code_size,
             code_cache: Arc::clone(&self.code_cache),
             code_filth: self.code_filth,
-            abi_hash: self.abi_hash,
+            abi_hash: self.abi_hash.clone(),
             abi_size: self.abi_size,
             abi_cache: Arc::clone(&self.abi_cache),
             abi_filth: self.abi_filth,
             address_hash: self.address_hash.clone(),
+            last_touched: self.last_touched,
+            dirty_fields: self.dirty_fields,
         }
     }
 
@@ -600,6 +987,7 @@ impl Account {
     pub fn clone_dirty(&self) -> Account {
         let mut account = self.clone_basic();
         account.storage_changes = self.storage_changes.clone();
+        account.staged_nodes = self.staged_nodes.clone();
         account.code_cache = Arc::clone(&self.code_cache);
         account.abi_cache = Arc::clone(&self.abi_cache);
         account
@@ -616,6 +1004,8 @@ impl Account {
     /// Basic account data and all modifications are overwritten
     /// with new values.
     pub fn overwrite_with(&mut self, other: Account) {
+        // our cached slots belong to a different storage trie, e.g. after `replace_storage`.
+        let stale_storage_cache = self.storage_root != other.storage_root;
         self.nonce = other.nonce;
         self.storage_root = other.storage_root;
         self.code_hash = other.code_hash;
@@ -627,11 +1017,17 @@ impl Account {
         self.abi_cache = other.abi_cache;
         self.abi_size = other.abi_size;
         self.address_hash = other.address_hash;
+        self.last_touched = other.last_touched;
+        self.dirty_fields = other.dirty_fields;
         let mut cache = self.storage_cache.borrow_mut();
+        if stale_storage_cache {
+            cache.clear();
+        }
         for (k, v) in other.storage_cache.into_inner() {
             cache.insert(k, v); //TODO: cloning should not be required here
         }
         self.storage_changes = other.storage_changes;
+        self.staged_nodes = other.staged_nodes;
     }
 }
 
@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::Arc;
 use util::*;
 
 /// State backend. See module docs for more details.
@@ -24,4 +25,20 @@ pub trait Backend: Send {
 
     /// Treat the backend as a writeable hashdb.
     fn as_hashdb_mut(&mut self) -> &mut HashDB;
+
+    /// Get code shared between states by its hash.
+    fn get_cached_code(&self, _hash: &H256) -> Option<Arc<Bytes>> {
+        None
+    }
+
+    /// Share code loaded from the database with other states.
+    fn cache_code(&self, _hash: H256, _code: Arc<Bytes>) {}
+
+    /// Root of the latest commit made by any state sharing the backend, if tracked.
+    fn latest_root(&self) -> Option<H256> {
+        None
+    }
+
+    /// Publish `root` as the latest committed root.
+    fn note_root(&self, _root: H256) {}
 }
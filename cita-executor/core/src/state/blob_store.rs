@@ -0,0 +1,103 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! External storage for contract code and abi.
+//!
+//! With a `BlobStore` configured in `State::blob_store`, code and abi are written to
+//! and read from the store instead of the account database, keeping the state
+//! database limited to trie nodes.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use util::*;
+
+/// Immutable blobs keyed by their hash.
+pub trait BlobStore: Send + Sync {
+    /// Get the blob with hash `hash`.
+    fn get(&self, hash: &H256) -> Option<Bytes>;
+
+    /// Store `blob` under its hash `hash`.
+    fn put(&self, hash: H256, blob: Bytes);
+}
+
+/// `BlobStore` keeping all blobs in memory.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    blobs: RwLock<HashMap<H256, Bytes>>,
+}
+
+impl MemoryBlobStore {
+    /// Number of blobs stored.
+    pub fn len(&self) -> usize {
+        self.blobs.read().unwrap().len()
+    }
+
+    /// Whether no blob is stored.
+    pub fn is_empty(&self) -> bool {
+        self.blobs.read().unwrap().is_empty()
+    }
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn get(&self, hash: &H256) -> Option<Bytes> {
+        self.blobs.read().unwrap().get(hash).cloned()
+    }
+
+    fn put(&self, hash: H256, blob: Bytes) {
+        self.blobs.write().unwrap().insert(hash, blob);
+    }
+}
+
+/// `HashDB` view of a `BlobStore`, so accounts can commit and load code and abi through it.
+pub struct BlobDB<'a> {
+    store: &'a BlobStore,
+}
+
+impl<'a> BlobDB<'a> {
+    /// Create a new `BlobDB` over `store`.
+    pub fn new(store: &'a BlobStore) -> Self {
+        BlobDB { store: store }
+    }
+}
+
+impl<'a> HashDB for BlobDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        unimplemented!()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        self.store.get(key).map(|blob| DBValue::from_slice(&blob))
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.store.get(key).is_some()
+    }
+
+    fn insert(&mut self, value: &[u8]) -> H256 {
+        let key = value.crypt_hash();
+        self.store.put(key, value.to_vec());
+        key
+    }
+
+    fn emplace(&mut self, key: H256, value: DBValue) {
+        self.store.put(key, value.to_vec());
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        // blobs are immutable and may be shared between accounts
+    }
+}
@@ -0,0 +1,219 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Read-only state backend over a memory-mapped file of trie nodes.
+//!
+//! `State::export_mmap` writes every node of a committed state to a file, sorted by
+//! key, and `State::from_mmap` serves reads straight from the mapped file. The OS page
+//! cache holds the hot nodes, so query nodes serving historical reads keep little in
+//! their own memory.
+//!
+//! File layout, integers little endian:
+//! `MAGIC`, the number of nodes as `u64`, one `(key, offset: u64, len: u32)` entry per
+//! node sorted by key, then the node values at their offsets.
+
+use super::backend::Backend;
+use super::recording_db::RecordingDB;
+use super::{key_address_hash, Account, State};
+use byteorder::{ByteOrder, LittleEndian};
+use error::Error;
+use factory::Factories;
+use memmap::Mmap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use util::*;
+
+const MAGIC: &[u8; 8] = b"CITAMMAP";
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 32 + 8 + 4;
+
+/// `Backend` serving the nodes of a file written by `State::export_mmap`.
+/// Writes panic, states opened with `State::from_mmap` reject them before reaching the backend.
+pub struct MmapBackend {
+    map: Mmap,
+    len: usize,
+}
+
+impl MmapBackend {
+    /// Map the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MmapBackend> {
+        let file = File::open(path)?;
+        // safety: the mapping is only read, and files written by `export_mmap` are not
+        // modified afterwards. Truncating or rewriting the file while it is mapped is undefined.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_LEN || &map[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a state node file"));
+        }
+        let len = LittleEndian::read_u64(&map[8..HEADER_LEN]) as usize;
+        let entries_end = len.checked_mul(ENTRY_LEN).and_then(|entries| entries.checked_add(HEADER_LEN));
+        match entries_end {
+            Some(end) if end <= map.len() => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated state node file")),
+        }
+        Ok(MmapBackend { map: map, len: len })
+    }
+
+    /// Number of nodes in the file.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the file holds no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn entry(&self, index: usize) -> &[u8] {
+        let start = HEADER_LEN + index * ENTRY_LEN;
+        &self.map[start..start + ENTRY_LEN]
+    }
+
+    fn value(&self, key: &H256) -> Option<&[u8]> {
+        // the empty trie is implied, as in the other databases
+        if *key == HASH_NULL_RLP {
+            return Some(&::rlp::NULL_RLP);
+        }
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = (low + high) / 2;
+            let entry = self.entry(middle);
+            match entry[..32].cmp(&key[..]) {
+                ::std::cmp::Ordering::Less => low = middle + 1,
+                ::std::cmp::Ordering::Greater => high = middle,
+                ::std::cmp::Ordering::Equal => {
+                    let offset = LittleEndian::read_u64(&entry[32..40]) as usize;
+                    let len = LittleEndian::read_u32(&entry[40..]) as usize;
+                    return offset.checked_add(len).and_then(|end| self.map.get(offset..end));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl HashDB for MmapBackend {
+    fn keys(&self) -> HashMap<H256, i32> {
+        (0..self.len)
+            .map(|index| (H256::from_slice(&self.entry(index)[..32]), 1))
+            .collect()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        self.value(key).map(DBValue::from_slice)
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.value(key).is_some()
+    }
+
+    fn insert(&mut self, _value: &[u8]) -> H256 {
+        panic!("memory-mapped state is read-only");
+    }
+
+    fn emplace(&mut self, _key: H256, _value: DBValue) {
+        panic!("memory-mapped state is read-only");
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        panic!("memory-mapped state is read-only");
+    }
+}
+
+impl Backend for MmapBackend {
+    fn as_hashdb(&self) -> &HashDB {
+        self
+    }
+
+    fn as_hashdb_mut(&mut self) -> &mut HashDB {
+        self
+    }
+}
+
+impl State<MmapBackend> {
+    /// Open the state with root `root` from a file written by `export_mmap`.
+    /// The state is read-only: `commit` fails with `Error::ReadOnlyState`.
+    pub fn from_mmap<P: AsRef<Path>>(
+        path: P,
+        root: H256,
+        account_start_nonce: U256,
+        factories: Factories,
+    ) -> Result<State<MmapBackend>, Error> {
+        let backend = MmapBackend::open(path)?;
+        let mut state = State::from_existing(backend, root, account_start_nonce, factories)?;
+        state.read_only = true;
+        Ok(state)
+    }
+}
+
+impl<B: Backend> State<B> {
+    /// Write every node of the committed state, the code and abi of its accounts
+    /// included, to a file at `path` for `from_mmap`. Uncommitted changes are not written.
+    ///
+    /// NOTE: Code and abi kept in a `blob_store` are not written.
+    pub fn export_mmap<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let db = RecordingDB::new(self.db.as_hashdb());
+        {
+            let trie = self.factories.trie.readonly(&db, &self.root)?;
+            for item in trie.iter()? {
+                let (key, value) = item?;
+                let account = Account::from_rlp(&value);
+                let address_hash = key_address_hash(&key);
+                let accountdb = self.factories.accountdb.readonly(&db, address_hash);
+                let storage_root = account
+                    .storage_root()
+                    .expect("account decoded from the trie has no storage changes; qed");
+                let storage = self.factories
+                    .trie
+                    .readonly(accountdb.as_hashdb(), storage_root)?;
+                for slot in storage.iter()? {
+                    slot?;
+                }
+                for hash in &[account.code_hash(), account.abi_hash()] {
+                    if *hash != HASH_EMPTY {
+                        accountdb.as_hashdb().get(hash);
+                    }
+                }
+            }
+        }
+        write_nodes(db.into_nodes(), path)
+    }
+}
+
+fn write_nodes<P: AsRef<Path>>(nodes: BTreeMap<H256, DBValue>, path: P) -> Result<(), Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(MAGIC);
+    LittleEndian::write_u64(&mut header[8..], nodes.len() as u64);
+    out.write_all(&header)?;
+
+    let mut offset = HEADER_LEN + nodes.len() * ENTRY_LEN;
+    for (key, value) in &nodes {
+        let mut entry = [0u8; ENTRY_LEN];
+        entry[..32].copy_from_slice(&key[..]);
+        LittleEndian::write_u64(&mut entry[32..40], offset as u64);
+        LittleEndian::write_u32(&mut entry[40..], value.len() as u32);
+        out.write_all(&entry)?;
+        offset += value.len();
+    }
+    for value in nodes.values() {
+        out.write_all(value)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -20,43 +20,441 @@
 //! Unconfirmed sub-states are managed with `checkpoint`s which may be canonicalized
 //! or rolled back.
 
+use bloom_journal::Bloom;
+use byteorder::{BigEndian, ByteOrder};
+use crossbeam;
 use contracts::Resource;
-use engines::NullEngine;
+use contracts::permission_management::contains_resource;
+use engines::{Engine, NullEngine};
 use env_info::EnvInfo;
 use error::Error;
 use evm::Error as EvmError;
-use executive::{Executive, TransactOptions};
+use executed::ExecutionError;
+use executive::{contract_address, salted_contract_address, Executive, TransactOptions};
 use factory::Factories;
+use log_entry::LogEntry;
+use pod_account::PodAccount;
+use pod_state::PodState;
 use receipt::{Receipt, ReceiptError};
-use std::cell::{RefCell, RefMut};
-use std::collections::{HashMap, HashSet};
+use rlp::*;
+use std::cell::{Cell, RefCell, RefMut};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::fmt;
-use std::sync::Arc;
-use trace::FlatTrace;
-use types::transaction::SignedTransaction;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::Sender;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use trace::{FlatTrace, TraceBuffers, VMTrace};
+use types::basic_account::BasicAccount;
+use types::transaction::{Action, SignedTransaction};
 use util::*;
 use util::trie;
+use util::trie::TrieMut;
+pub use util::trie::TrieSpec;
 
 pub mod account;
 pub mod backend;
-
-pub use self::account::Account;
+pub mod blob_store;
+pub mod mmap;
+mod node_cache;
+mod recording_db;
+pub mod snapshot;
+pub mod witness;
+
+pub use self::account::{Account, DirtyFields, StorageChanges};
 use self::backend::*;
+use self::blob_store::{BlobDB, BlobStore};
+use self::node_cache::{NodeCachingDB, TrieNodeCache};
+pub use self::snapshot::StateSnapshot;
+pub use self::witness::{Witness, WitnessBackend};
 use state_db::*;
 pub use substate::Substate;
 
+/// Buffers reused across `State::apply_reuse` calls, create one per block-processing loop:
+/// the transaction's top-level `Substate`, the substates of nested calls and the trace buffers.
+/// Logs, created contracts and traces are owned by each returned `ApplyOutcome`, hand the
+/// outcome back with `recycle` once done with it to reuse their buffers too.
+#[derive(Debug, Default)]
+pub struct ExecContext {
+    substate: Substate,
+    substates: Vec<Substate>,
+    trace_buffers: TraceBuffers,
+}
+
+impl ExecContext {
+    /// Keep the log, created contract and trace buffers of `outcome` for the next transaction.
+    pub fn recycle(&mut self, outcome: ApplyOutcome) {
+        let (mut logs, mut contracts_created, mut trace) =
+            (outcome.receipt.logs, outcome.contracts_created, outcome.trace);
+        if logs.capacity() > self.substate.logs.capacity() {
+            logs.clear();
+            self.substate.logs = logs;
+        }
+        if contracts_created.capacity() > self.substate.contracts_created.capacity() {
+            contracts_created.clear();
+            self.substate.contracts_created = contracts_created;
+        }
+        if trace.capacity() > 0 {
+            trace.clear();
+            self.trace_buffers
+                .lock()
+                .expect("trace buffers lock poisoned")
+                .push(trace);
+        }
+    }
+}
+
 /// Used to return information about an `State::apply` operation.
+#[derive(Debug, Clone, PartialEq)]
 pub struct ApplyOutcome {
     /// The receipt for the applied transaction.
     pub receipt: Receipt,
     /// The trace for the applied transaction, if None if tracing is disabled.
     pub trace: Vec<FlatTrace>,
+    /// The contracts created by the applied transaction.
+    pub contracts_created: Vec<Address>,
+    /// Address of the contract deployed by a successful creation transaction.
+    pub contract_address: Option<Address>,
+    /// Number of accounts other than the sender touched by the applied transaction.
+    pub touched_accounts: usize,
+    /// Portion of the gas used that was charged for SSTOREs, see `storage_gas()`.
+    pub storage_gas: U256,
+    /// Executed instructions, if `TransactOptions::opcode_profiling` was set.
+    pub opcode_profile: Option<OpcodeProfile>,
+    /// Account state transitions in the order they happened, if `TransactOptions::account_events` was set.
+    /// Transitions of reverted calls are included.
+    pub account_events: Vec<AccountEvent>,
+    /// Net change in persisted state size in bytes, see `state_growth_bytes`.
+    /// Zero unless `TransactOptions::state_growth` was set.
+    pub state_growth: i64,
+}
+
+impl ApplyOutcome {
+    /// Whether the transaction changed nothing but the sender's nonce:
+    /// no logs, no created contracts and no other touched accounts.
+    pub fn is_noop(&self) -> bool {
+        self.receipt.logs.is_empty() && self.contracts_created.is_empty() && self.touched_accounts == 0
+    }
+
+    /// Portion of the gas used that was charged for SSTOREs.
+    /// SSTOREs of reverted calls are not included.
+    pub fn storage_gas(&self) -> U256 {
+        self.storage_gas
+    }
+
+    /// Net change in persisted state size caused by the transaction, in bytes.
+    /// Storage slots set from zero add `STORAGE_SLOT_BYTES` and cleared ones subtract it,
+    /// created, replaced and killed code and abi count their size.
+    /// The storage of killed accounts is not counted.
+    /// Only measured if `TransactOptions::state_growth` was set.
+    pub fn state_growth_bytes(&self) -> i64 {
+        self.state_growth
+    }
+}
+
+impl Encodable for ApplyOutcome {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(10);
+        s.append(&self.receipt);
+        s.append_list(&self.trace);
+        s.append_list(&self.contracts_created);
+        match self.contract_address {
+            Some(ref address) => s.append(address),
+            None => s.append_empty_data(),
+        };
+        s.append(&self.touched_accounts);
+        s.append(&self.storage_gas);
+        match self.opcode_profile {
+            Some(ref profile) => s.append(profile),
+            None => s.append_empty_data(),
+        };
+        s.append_list(&self.account_events);
+        // sign flag, then the magnitude
+        s.append(&((self.state_growth < 0) as u8));
+        s.append(&(self.state_growth.wrapping_abs() as u64));
+    }
+}
+
+impl Decodable for ApplyOutcome {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(ApplyOutcome {
+            receipt: rlp.val_at(0)?,
+            trace: rlp.list_at(1)?,
+            contracts_created: rlp.list_at(2)?,
+            contract_address: if rlp.at(3)?.is_empty() {
+                None
+            } else {
+                Some(rlp.val_at(3)?)
+            },
+            touched_accounts: rlp.val_at(4)?,
+            storage_gas: rlp.val_at(5)?,
+            opcode_profile: if rlp.at(6)?.is_data() {
+                None
+            } else {
+                Some(rlp.val_at(6)?)
+            },
+            account_events: rlp.list_at(7)?,
+            state_growth: decode_signed(rlp.val_at(8)?, rlp.val_at(9)?)?,
+        })
+    }
+}
+
+/// Signed value encoded as a sign flag, `1` for negative, and the magnitude.
+fn decode_signed(negative: u8, magnitude: u64) -> Result<i64, DecoderError> {
+    match negative {
+        0 if magnitude <= i64::max_value() as u64 => Ok(magnitude as i64),
+        1 if magnitude <= 1 << 63 => Ok((magnitude as i64).wrapping_neg()),
+        _ => Err(DecoderError::Custom("Invalid signed value.")),
+    }
+}
+
+/// Number of executions and gas charged per opcode, including nested calls.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OpcodeProfile {
+    /// Times each opcode was executed.
+    pub counts: HashMap<u8, u64>,
+    /// Gas charged for each opcode.
+    pub gas: HashMap<u8, U256>,
+}
+
+impl OpcodeProfile {
+    /// Summarize the operations of `trace` and its subtraces.
+    pub fn from_vm_trace(trace: &VMTrace) -> Self {
+        let mut profile = OpcodeProfile::default();
+        profile.add_vm_trace(trace);
+        profile
+    }
+
+    fn add_vm_trace(&mut self, trace: &VMTrace) {
+        for operation in &trace.operations {
+            *self.counts.entry(operation.instruction).or_insert(0) += 1;
+            *self.gas.entry(operation.instruction).or_insert_with(U256::zero) += operation.gas_cost;
+        }
+        for sub in &trace.subs {
+            self.add_vm_trace(sub);
+        }
+    }
+
+    /// The opcode charged the most gas, the lowest one on a tie.
+    pub fn dominant_opcode(&self) -> Option<u8> {
+        let mut gas: Vec<_> = self.gas.iter().collect();
+        gas.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        gas.first().map(|&(opcode, _)| *opcode)
+    }
+}
+
+impl Encodable for OpcodeProfile {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        let mut opcodes: Vec<_> = self.counts.keys().collect();
+        opcodes.sort();
+        s.begin_list(opcodes.len());
+        for opcode in opcodes {
+            s.begin_list(3);
+            s.append(opcode);
+            s.append(&self.counts[opcode]);
+            s.append(&self.gas.get(opcode).cloned().unwrap_or_else(U256::zero));
+        }
+    }
+}
+
+impl Decodable for OpcodeProfile {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let mut profile = OpcodeProfile::default();
+        for item in rlp.iter() {
+            let opcode: u8 = item.val_at(0)?;
+            profile.counts.insert(opcode, item.val_at(1)?);
+            profile.gas.insert(opcode, item.val_at(2)?);
+        }
+        Ok(profile)
+    }
 }
 
 /// Result type for the execution ("application") of a transaction.
 pub type ApplyResult = Result<ApplyOutcome, Error>;
 
+/// State transition of an account during `State::apply`, see `TransactOptions::account_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountEvent {
+    /// A contract account was created.
+    Created(Address),
+    /// The code of the account was set.
+    CodeSet(Address),
+    /// A storage slot was written.
+    StorageWritten {
+        address: Address,
+        key: H256,
+        value: H256,
+    },
+    /// The account was killed.
+    Killed(Address),
+}
+
+impl Encodable for AccountEvent {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match *self {
+            AccountEvent::Created(ref address) => {
+                s.begin_list(2).append(&0u8).append(address);
+            }
+            AccountEvent::CodeSet(ref address) => {
+                s.begin_list(2).append(&1u8).append(address);
+            }
+            AccountEvent::StorageWritten {
+                ref address,
+                ref key,
+                ref value,
+            } => {
+                s.begin_list(4).append(&2u8).append(address).append(key).append(value);
+            }
+            AccountEvent::Killed(ref address) => {
+                s.begin_list(2).append(&3u8).append(address);
+            }
+        }
+    }
+}
+
+impl Decodable for AccountEvent {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        match rlp.val_at::<u8>(0)? {
+            0 => Ok(AccountEvent::Created(rlp.val_at(1)?)),
+            1 => Ok(AccountEvent::CodeSet(rlp.val_at(1)?)),
+            2 => Ok(AccountEvent::StorageWritten {
+                address: rlp.val_at(1)?,
+                key: rlp.val_at(2)?,
+                value: rlp.val_at(3)?,
+            }),
+            3 => Ok(AccountEvent::Killed(rlp.val_at(1)?)),
+            _ => Err(DecoderError::Custom("Unknown account event.")),
+        }
+    }
+}
+
+/// Change written to the trie by `State::commit`, published through `State::commit_sink`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommitEvent {
+    /// A new account was written.
+    AccountCreated(Address),
+    /// An existing account changed.
+    AccountUpdated(Address),
+    /// An existing account was removed.
+    AccountDeleted(Address),
+    /// A storage slot was set, a zero `value` removes the slot.
+    StorageChanged {
+        address: Address,
+        key: H256,
+        value: H256,
+    },
+}
+
+/// Operation of a commit, see `State::commit_plan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitStep {
+    /// Write the changed storage slots of the account, sorted by key.
+    /// A zero value removes the slot.
+    CommitStorage {
+        address: Address,
+        changes: Vec<(H256, H256)>,
+    },
+    /// Write the new code of the account.
+    CommitCode(Address),
+    /// Write the new ABI of the account.
+    CommitAbi(Address),
+    /// Insert or update the account in the state trie.
+    InsertAccount(Address),
+    /// Remove the account from the state trie.
+    RemoveAccount(Address),
+}
+
+/// Trie nodes a commit writes to and removes from the backend, see `State::simulate_commit`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommitJournal {
+    /// Nodes inserted, by hash. Storage nodes are keyed as in the account database.
+    pub inserts: HashMap<H256, Bytes>,
+    /// Hashes of nodes removed.
+    pub removes: HashSet<H256>,
+}
+
+impl CommitJournal {
+    /// Number of node writes, inserts and removes.
+    pub fn len(&self) -> usize {
+        self.inserts.len() + self.removes.len()
+    }
+
+    /// Whether the commit writes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.inserts.is_empty() && self.removes.is_empty()
+    }
+}
+
+/// `HashDB` reading through to `base` and keeping all writes in memory.
+struct JournalDB<'a> {
+    base: &'a HashDB,
+    overlay: MemoryDB,
+}
+
+impl<'a> JournalDB<'a> {
+    fn new(base: &'a HashDB) -> Self {
+        JournalDB {
+            base: base,
+            overlay: MemoryDB::new(),
+        }
+    }
+
+    fn into_journal(mut self) -> CommitJournal {
+        let mut journal = CommitJournal::default();
+        for (key, (value, rc)) in self.overlay.drain() {
+            if rc > 0 {
+                journal.inserts.insert(key, value.to_vec());
+            } else if rc < 0 {
+                journal.removes.insert(key);
+            }
+        }
+        journal
+    }
+}
+
+impl<'a> HashDB for JournalDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        let mut keys = self.base.keys();
+        for (key, rc) in self.overlay.keys() {
+            *keys.entry(key).or_insert(0) += rc;
+        }
+        keys
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        self.overlay.get(key).or_else(|| self.base.get(key))
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, value: &[u8]) -> H256 {
+        self.overlay.insert(value)
+    }
+
+    fn emplace(&mut self, key: H256, value: DBValue) {
+        self.overlay.emplace(key, value)
+    }
+
+    fn remove(&mut self, key: &H256) {
+        self.overlay.remove(key)
+    }
+}
+
+/// `BlobStore` dropping all writes, so a simulated commit leaves the real store untouched.
+struct DiscardBlobStore;
+
+impl BlobStore for DiscardBlobStore {
+    fn get(&self, _hash: &H256) -> Option<Bytes> {
+        None
+    }
+
+    fn put(&self, _hash: H256, _blob: Bytes) {}
+}
+
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 /// Account modification state. Used to check if the account was
 /// Modified in between commits and overall.
@@ -110,6 +508,13 @@ impl AccountEntry {
         }
     }
 
+    // Apply a limit of `items` clean storage slots to the cached account.
+    fn limit_storage_cache(&mut self, items: usize) {
+        if let Some(ref mut account) = self.account {
+            account.set_storage_cache_limit(items);
+        }
+    }
+
     // Create a new account entry and mark it as dirty.
     fn new_dirty(account: Option<Account>) -> AccountEntry {
         AccountEntry {
@@ -196,6 +601,7 @@ impl AccountEntry {
 pub struct State<B: Backend> {
     db: B,
     root: H256,
+    committed_root: H256,
     cache: RefCell<HashMap<Address, AccountEntry>>,
     // The original account is preserved in
     checkpoints: RefCell<Vec<HashMap<Address, Option<AccountEntry>>>>,
@@ -206,6 +612,91 @@ pub struct State<B: Backend> {
     // contract permissions
     pub creators: HashSet<Address>,
     pub account_permissions: HashMap<Address, Vec<Resource>>,
+    // contracts that may not be called, configuration is not affected by checkpoints
+    pub blacklist: HashSet<Address>,
+    // quota charged to every transaction on top of its execution cost when quota is checked
+    pub base_quota: U256,
+    // reject transactions whose `valid_until_block` is below the executing block number
+    pub check_valid_until_block: bool,
+    // reject transactions of senders whose quota is used up before executing them, if quota is checked
+    pub check_quota_budget: bool,
+    // defer hashing of new code and abi until it is read or committed
+    pub defer_code_hashing: bool,
+    // share identical storage values written to many keys of an account
+    pub intern_storage_values: bool,
+    // report missing trie nodes from `apply` as recoverable `Error::StateIncomplete`
+    pub allow_missing_nodes: bool,
+    // block number recorded on every mutated account, if set
+    touch_block: Option<u64>,
+    // collect performance metrics such as `require_stats`
+    pub collect_metrics: bool,
+    // receives the changes of every commit, not inherited by clones
+    pub commit_sink: Option<Sender<CommitEvent>>,
+    // maps a logical address to the address its account is stored under, identity if unset
+    pub address_mapper: Option<Arc<Fn(&Address) -> Address + Send + Sync>>,
+    // engine transactions are executed with, `NullEngine` if unset
+    pub engine: Option<Arc<Engine>>,
+    // clean storage slots cached per account, unlimited if unset
+    pub storage_cache_items: Option<usize>,
+    // guards against accidental burns by calls to the zero address
+    pub zero_address_policy: ZeroAddressPolicy,
+    // how the nonces of system transactions are checked
+    pub nonce_policy: NoncePolicy,
+    // handling of calls to contracts that self-destructed earlier in the block
+    pub suicided_call_policy: SuicidedCallPolicy,
+    // fail the call executing the SSTORE past this many in a transaction, unlimited if unset.
+    // SSTOREs of reverted calls do not count.
+    pub max_storage_writes_per_tx: Option<usize>,
+    // abi given to created contracts until one is registered for them
+    pub default_abi: Option<Bytes>,
+    // extra gas for the first access of a storage slot in a transaction, see `warm_storage`
+    pub cold_sload_gas: usize,
+    // storage slots every transaction applied accesses as warm from its start
+    pub access_list: Vec<(Address, Vec<H256>)>,
+    // stores code and abi outside of the state database, if set
+    pub blob_store: Option<Arc<BlobStore>>,
+    // read accounts missing from the cache at the latest root committed through the backend
+    pub follow_commits: bool,
+    // open checkpoints allowed at once, unlimited if unset
+    pub max_checkpoint_depth: Option<usize>,
+    // remove the storage trie nodes of killed accounts on commit
+    pub prune_killed_storage: bool,
+    // most items accepted by a single batch call: `kill_accounts`, `storage_multiproof`,
+    // `replace_storage`, `commit_accounts` and `incremental_root`
+    pub max_batch_size: usize,
+    // threads committing the storage of dirty accounts, serial if at most 1
+    pub commit_threads: usize,
+    // count reads and writes per account, see `account_access_counts`
+    pub meter_account_access: bool,
+    // hashes of the only code contracts may be created from, unrestricted if unset.
+    // Nested CREATEs of other code fail, transactions creating other code are rejected.
+    pub allowed_code_hashes: Option<HashSet<H256>>,
+    // keep the account trie nodes read until the next commit, see `trie_node_loads`
+    pub cache_trie_nodes: bool,
+    // committed account hashes, shared with clones, see `rebuild_bloom`
+    account_bloom: Option<Arc<Mutex<Bloom>>>,
+    require_stats: Cell<RequireStats>,
+    // slots rejecting writes, by the checkpoint depth they were frozen at
+    frozen_slots: HashMap<(Address, H256), usize>,
+    // named checkpoints, by the checkpoint depth they opened
+    savepoints: Vec<(String, usize)>,
+    access_counts: RefCell<HashMap<Address, (u64, u64)>>,
+    // not inherited by clones
+    trie_nodes: Mutex<TrieNodeCache>,
+    // contracts that self-destructed in the transactions applied since the last `commit`
+    suicide_within_block: HashSet<Address>,
+    // storage slots accessed by the transaction being applied, including its access list,
+    // by the checkpoint depth they were first accessed at
+    warm_slots: HashMap<(Address, H256), usize>,
+    // SSTOREs executed by the transaction being applied, see `max_storage_writes_per_tx`,
+    // by the checkpoint depth they were executed at so reverts drop them
+    storage_writes: Vec<usize>,
+    // reject commits, set for states opened with `from_mmap`
+    read_only: bool,
+    // account transitions recorded during `apply_with_options`, if requested
+    account_events: Option<Vec<AccountEvent>>,
+    // emptied substates of nested calls, lent by `apply_reuse`'s `ExecContext`
+    substate_pool: Option<Vec<Substate>>,
 }
 
 #[derive(Copy, Clone)]
@@ -215,6 +706,242 @@ enum RequireCache {
     Code,
     AbiSize,
     Abi,
+    CodeAndAbi,
+}
+
+/// Number of account-DB reads triggered by each `RequireCache` level.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RequireStats {
+    /// Code loads needed to answer a code size query.
+    pub code_size: u64,
+    /// Code loads.
+    pub code: u64,
+    /// ABI loads needed to answer an ABI size query.
+    pub abi_size: u64,
+    /// ABI loads.
+    pub abi: u64,
+}
+
+/// Handling of transactions calling the zero address, see `State::zero_address_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroAddressPolicy {
+    /// Treat the zero address as an ordinary account.
+    Allow,
+    /// Reject the transaction with `ExecutionError::SendToZeroAddress`.
+    Reject,
+}
+
+impl Default for ZeroAddressPolicy {
+    fn default() -> Self {
+        ZeroAddressPolicy::Allow
+    }
+}
+
+/// Handling of the nonces of system transactions, see `State::nonce_policy`.
+///
+/// Ordinary transactions are deduplicated by their nonce string and always bump
+/// the sender's account nonce, the policy only concerns `TransactOptions::system_transaction`.
+/// The transaction nonce is read as a decimal number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoncePolicy {
+    /// Require the nonce to equal the sender's account nonce, then bump it.
+    /// Replays and reordering are rejected, but one lost transaction stalls all later ones.
+    Strict,
+    /// Require the nonce to be at least the sender's account nonce, then move the
+    /// account nonce past it. Replays are rejected and lost transactions can be skipped,
+    /// but a single large nonce burns every nonce below it.
+    AllowGaps,
+    /// Neither check nor bump the account nonce.
+    /// Nothing stops a system transaction from being replayed, callers must deduplicate.
+    Ignore,
+}
+
+impl Default for NoncePolicy {
+    fn default() -> Self {
+        NoncePolicy::Ignore
+    }
+}
+
+/// Handling of transactions calling a contract that self-destructed earlier in the
+/// block, see `State::suicided_call_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuicidedCallPolicy {
+    /// Call the empty account left behind, as if the contract never existed.
+    CallEmpty,
+    /// Reject the transaction with `ExecutionError::SuicidedContract`.
+    /// Calls from contracts fail with `evm::Error::SuicidedContract` instead.
+    Reject,
+}
+
+impl Default for SuicidedCallPolicy {
+    fn default() -> Self {
+        SuicidedCallPolicy::CallEmpty
+    }
+}
+
+/// Uncommitted changes of a `State`, see `State::dirty_snapshot`.
+pub struct DirtySnapshot {
+    accounts: HashMap<Address, AccountEntry>,
+}
+
+/// Basic fields of an account, see `State::account_view`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountMeta {
+    pub nonce: U256,
+    pub code_hash: H256,
+    pub abi_hash: H256,
+    /// `None` if the storage has uncommitted changes.
+    pub storage_root: Option<H256>,
+}
+
+impl<'a> From<&'a Account> for AccountMeta {
+    fn from(account: &'a Account) -> AccountMeta {
+        AccountMeta {
+            nonce: *account.nonce(),
+            code_hash: account.code_hash(),
+            abi_hash: account.abi_hash(),
+            storage_root: account.storage_root().cloned(),
+        }
+    }
+}
+
+/// Committed and pending versions of an account, see `State::account_view`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountView {
+    /// The account as stored in the trie at the current root.
+    pub committed: Option<AccountMeta>,
+    /// The account as modified in the cache, `None` if it has no uncommitted changes.
+    pub pending: Option<AccountMeta>,
+}
+
+/// Permission changes of a state relative to another, see `State::permissions_diff`.
+/// Addresses are sorted, accounts whose resources did not change are left out.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PermissionDiff {
+    /// Senders missing from the other state.
+    pub added_senders: Vec<Address>,
+    /// Senders of the other state that are no longer senders.
+    pub removed_senders: Vec<Address>,
+    /// Creators missing from the other state.
+    pub added_creators: Vec<Address>,
+    /// Creators of the other state that are no longer creators.
+    pub removed_creators: Vec<Address>,
+    /// Resources an account holds that it does not hold in the other state.
+    pub added_resources: BTreeMap<Address, Vec<Resource>>,
+    /// Resources an account holds in the other state only.
+    pub removed_resources: BTreeMap<Address, Vec<Resource>>,
+}
+
+impl PermissionDiff {
+    /// Whether both states hold the same permissions.
+    pub fn is_empty(&self) -> bool {
+        self.added_senders.is_empty() && self.removed_senders.is_empty() && self.added_creators.is_empty()
+            && self.removed_creators.is_empty() && self.added_resources.is_empty()
+            && self.removed_resources.is_empty()
+    }
+}
+
+/// How `State::merge_dirty_from` resolves writes made by both states.
+/// Accounts conflict on nonce, code, abi or existence, storage conflicts are resolved per slot.
+pub enum ConflictPolicy {
+    /// Abort the merge with a `MergeConflict`.
+    FailOnConflict,
+    /// Keep the write of the merged-in state.
+    PreferOther,
+    /// Keep the write of this state.
+    PreferSelf,
+    /// Decide per conflict, `true` keeps the write of the merged-in state.
+    /// The storage key is given for slot conflicts.
+    CustomFn(Box<Fn(&Address, Option<&H256>) -> bool>),
+}
+
+impl ConflictPolicy {
+    fn prefer_other(&self, address: &Address, key: Option<&H256>) -> Result<bool, MergeConflict> {
+        match *self {
+            ConflictPolicy::FailOnConflict => Err(MergeConflict {
+                address: *address,
+                key: key.cloned(),
+            }),
+            ConflictPolicy::PreferOther => Ok(true),
+            ConflictPolicy::PreferSelf => Ok(false),
+            ConflictPolicy::CustomFn(ref prefer_other) => Ok(prefer_other(address, key)),
+        }
+    }
+}
+
+/// Write made by both states of a merge, see `ConflictPolicy::FailOnConflict`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    pub address: Address,
+    /// `None` if the account itself conflicts.
+    pub key: Option<H256>,
+}
+
+/// Error returned when an account cannot be brought into the cache.
+#[derive(Debug)]
+pub enum RequireError {
+    /// The state root is not present in the backend.
+    MissingStateRoot(H256),
+    /// Reading the account from the trie failed.
+    TrieRead(TrieError),
+}
+
+impl fmt::Display for RequireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RequireError::MissingStateRoot(ref root) => write!(f, "State root {} is missing", root),
+            RequireError::TrieRead(ref err) => write!(f, "Failed to read account: {}", err),
+        }
+    }
+}
+
+/// Why `State::trace_storage_chain` stopped following the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainEnd {
+    /// The next key holds a zero value.
+    Zero,
+    /// The last value points back to a key of the chain.
+    Cycle,
+    /// `max_steps` pairs were visited.
+    MaxSteps,
+}
+
+/// Returned by `State::checkpoint` when `State::max_checkpoint_depth` checkpoints are open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointLimitExceeded {
+    /// The configured maximum depth.
+    pub limit: usize,
+}
+
+impl fmt::Display for CheckpointLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Checkpoint depth limit of {} reached", self.limit)
+    }
+}
+
+impl From<CheckpointLimitExceeded> for Error {
+    fn from(err: CheckpointLimitExceeded) -> Self {
+        Error::Execution(ExecutionError::Internal(format!("{}", err)))
+    }
+}
+
+/// Returned by `State::revert_to_savepoint` for a name without an open savepoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSavepoint {
+    /// The name asked for.
+    pub name: String,
+}
+
+impl fmt::Display for UnknownSavepoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No savepoint named {}", self.name)
+    }
+}
+
+impl From<UnknownSavepoint> for Error {
+    fn from(err: UnknownSavepoint) -> Self {
+        Error::Execution(ExecutionError::Internal(format!("{}", err)))
+    }
 }
 
 /// Mode of dealing with null accounts.
@@ -228,6 +955,32 @@ pub enum CleanupMode<'a> {
     KillEmpty(&'a mut HashSet<Address>),
 }
 
+/// Default of `State::max_batch_size`.
+const DEFAULT_MAX_BATCH_SIZE: usize = 65_536;
+
+/// Most redirections `State::resolve_account` follows.
+const MAX_RESOLVE_HOPS: usize = 64;
+
+/// Largest encoded transaction `State::apply_stream` reads.
+const MAX_STREAM_TRANSACTION_SIZE: usize = 4 * 1024 * 1024;
+
+/// Bytes a storage slot adds to the state, its key and value.
+const STORAGE_SLOT_BYTES: i64 = 64;
+
+/// Storage slot of `mapping(address => address[]) permissions` in `authorization.sol`.
+const AUTHORIZATION_PERMISSIONS_SLOT: u64 = 13;
+/// Storage slot of `address[] all_accounts` in `authorization.sol`.
+const AUTHORIZATION_ALL_ACCOUNTS_SLOT: u64 = 15;
+/// Storage slot of `Resource[] resources` in `permission.sol`.
+const PERMISSION_RESOURCES_SLOT: u64 = 1;
+/// Longest contract array `State::load_permissions_from_contract` reads.
+const MAX_PERMISSION_ARRAY_LEN: u64 = 65_536;
+
+/// Smallest number of accounts an account bloom is sized for.
+const ACCOUNT_BLOOM_MIN_ITEMS: usize = 1024;
+/// False positive rate of an account bloom at its sized capacity.
+const ACCOUNT_BLOOM_FP_RATE: f64 = 0.01;
+
 const SEC_TRIE_DB_UNWRAP_STR: &'static str = "A state can only be created with valid root.\
                                               Creating a SecTrieDB with a valid root will not fail.\
                                               Therefore creating a SecTrieDB with this state's root will not fail.";
@@ -245,13 +998,55 @@ impl<B: Backend> State<B> {
         State {
             db: db,
             root: root,
+            committed_root: root,
             cache: RefCell::new(HashMap::new()),
             checkpoints: RefCell::new(Vec::new()),
             account_start_nonce: account_start_nonce,
             factories: factories,
             senders: HashSet::new(),
             creators: HashSet::new(),
+            blacklist: HashSet::new(),
+            base_quota: U256::zero(),
+            check_valid_until_block: false,
+            check_quota_budget: false,
             account_permissions: HashMap::new(),
+            defer_code_hashing: false,
+            intern_storage_values: false,
+            allow_missing_nodes: false,
+            touch_block: None,
+            collect_metrics: false,
+            commit_sink: None,
+            address_mapper: None,
+            engine: None,
+            storage_cache_items: None,
+            zero_address_policy: ZeroAddressPolicy::default(),
+            nonce_policy: NoncePolicy::default(),
+            suicided_call_policy: SuicidedCallPolicy::default(),
+            max_storage_writes_per_tx: None,
+            default_abi: None,
+            cold_sload_gas: 0,
+            access_list: Vec::new(),
+            blob_store: None,
+            follow_commits: false,
+            max_checkpoint_depth: None,
+            prune_killed_storage: false,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            cache_trie_nodes: false,
+            trie_nodes: Mutex::new(TrieNodeCache::default()),
+            suicide_within_block: HashSet::new(),
+            warm_slots: HashMap::new(),
+            storage_writes: Vec::new(),
+            commit_threads: 1,
+            meter_account_access: false,
+            allowed_code_hashes: None,
+            account_bloom: None,
+            require_stats: Cell::new(RequireStats::default()),
+            frozen_slots: HashMap::new(),
+            savepoints: Vec::new(),
+            access_counts: RefCell::new(HashMap::new()),
+            account_events: None,
+            substate_pool: None,
+            read_only: false,
         }
     }
 
@@ -269,1884 +1064,7554 @@ impl<B: Backend> State<B> {
         let state = State {
             db: db,
             root: root,
+            committed_root: root,
             cache: RefCell::new(HashMap::new()),
             checkpoints: RefCell::new(Vec::new()),
             account_start_nonce: account_start_nonce,
             factories: factories,
             senders: HashSet::new(),
             creators: HashSet::new(),
+            blacklist: HashSet::new(),
+            base_quota: U256::zero(),
+            check_valid_until_block: false,
+            check_quota_budget: false,
             account_permissions: HashMap::new(),
+            defer_code_hashing: false,
+            intern_storage_values: false,
+            allow_missing_nodes: false,
+            touch_block: None,
+            collect_metrics: false,
+            commit_sink: None,
+            address_mapper: None,
+            engine: None,
+            storage_cache_items: None,
+            zero_address_policy: ZeroAddressPolicy::default(),
+            nonce_policy: NoncePolicy::default(),
+            suicided_call_policy: SuicidedCallPolicy::default(),
+            max_storage_writes_per_tx: None,
+            default_abi: None,
+            cold_sload_gas: 0,
+            access_list: Vec::new(),
+            blob_store: None,
+            follow_commits: false,
+            max_checkpoint_depth: None,
+            prune_killed_storage: false,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            cache_trie_nodes: false,
+            trie_nodes: Mutex::new(TrieNodeCache::default()),
+            suicide_within_block: HashSet::new(),
+            warm_slots: HashMap::new(),
+            storage_writes: Vec::new(),
+            commit_threads: 1,
+            meter_account_access: false,
+            allowed_code_hashes: None,
+            account_bloom: None,
+            require_stats: Cell::new(RequireStats::default()),
+            frozen_slots: HashMap::new(),
+            savepoints: Vec::new(),
+            access_counts: RefCell::new(HashMap::new()),
+            account_events: None,
+            substate_pool: None,
+            read_only: false,
         };
 
         Ok(state)
     }
 
-    /// Create a recoverable checkpoint of this state.
-    pub fn checkpoint(&mut self) {
-        self.checkpoints.get_mut().push(HashMap::new());
+    /// Creates new state with existing state root, using tries of the given `spec`
+    /// for accounts and storage instead of the one configured in `factories`.
+    ///
+    /// `TrieSpec::Fat` additionally stores the preimage of every key, which roughly
+    /// doubles the database writes of each insertion, but allows committed accounts
+    /// to be enumerated with `committed_addresses`.
+    pub fn from_existing_with_spec(
+        db: B,
+        root: H256,
+        account_start_nonce: U256,
+        mut factories: Factories,
+        spec: TrieSpec,
+    ) -> Result<State<B>, TrieError> {
+        factories.trie = trie::TrieFactory::new(spec);
+        Self::from_existing(db, root, account_start_nonce, factories)
     }
 
-    /// Merge last checkpoint with previous.
-    pub fn discard_checkpoint(&mut self) {
-        // merge with previous checkpoint
-        let last = self.checkpoints.get_mut().pop();
-        if let Some(mut checkpoint) = last {
-            if let Some(ref mut prev) = self.checkpoints.get_mut().last_mut() {
-                if prev.is_empty() {
-                    **prev = checkpoint;
-                } else {
-                    for (k, v) in checkpoint.drain() {
-                        prev.entry(k).or_insert(v);
-                    }
-                }
+    /// Copy from `other` the settings transactions are executed with: permissions, quota
+    /// checks, policies, gas costs, limits, the address mapper, the engine and the block
+    /// number recorded on mutated accounts. Cache, storage and commit settings are kept.
+    pub fn copy_execution_config<C: Backend>(&mut self, other: &State<C>) {
+        self.senders = other.senders.clone();
+        self.creators = other.creators.clone();
+        self.account_permissions = other.account_permissions.clone();
+        self.blacklist = other.blacklist.clone();
+        self.base_quota = other.base_quota;
+        self.check_valid_until_block = other.check_valid_until_block;
+        self.check_quota_budget = other.check_quota_budget;
+        self.touch_block = other.touch_block;
+        self.address_mapper = other.address_mapper.clone();
+        self.engine = other.engine.clone();
+        self.zero_address_policy = other.zero_address_policy;
+        self.nonce_policy = other.nonce_policy;
+        self.suicided_call_policy = other.suicided_call_policy;
+        self.max_storage_writes_per_tx = other.max_storage_writes_per_tx;
+        self.default_abi = other.default_abi.clone();
+        self.cold_sload_gas = other.cold_sload_gas;
+        self.access_list = other.access_list.clone();
+        self.max_checkpoint_depth = other.max_checkpoint_depth;
+        self.prune_killed_storage = other.prune_killed_storage;
+        self.allowed_code_hashes = other.allowed_code_hashes.clone();
+    }
+
+    /// Build a state holding exactly the accounts of `pod` and commit it.
+    /// Accounts created later start at nonce zero.
+    pub fn from_pod(mut db: B, pod: PodState, factories: Factories) -> Result<State<B>, Error> {
+        let mut root = H256::new();
+        {
+            // init trie and reset root to null
+            let _ = factories.trie.create(db.as_hashdb_mut(), &mut root);
+        }
+        let mut state = State::from_existing(db, root, U256::zero(), factories)?;
+        for (address, account) in pod.drain() {
+            state.insert_cache(&address, AccountEntry::new_dirty(Some(Account::from_pod(account))));
+        }
+        state.commit()?;
+        Ok(state)
+    }
+
+    /// All accounts with their code, abi and complete storage, including uncommitted changes.
+    /// Returns `None` unless the state was created with `TrieSpec::Fat`.
+    pub fn to_pod(&self) -> trie::Result<Option<PodState>> {
+        let mut addresses: HashSet<Address> = match self.committed_addresses()? {
+            Some(addresses) => addresses.into_iter().collect(),
+            None => return Ok(None),
+        };
+        addresses.extend(
+            self.cache
+                .borrow()
+                .iter()
+                .filter(|&(_, entry)| entry.is_dirty())
+                .map(|(address, _)| *address),
+        );
+
+        let mut pod = BTreeMap::new();
+        for address in addresses {
+            let account = self.ensure_cached(&address, RequireCache::CodeAndAbi, false, |acc| {
+                acc.map(|acc| (*acc.nonce(), acc.code(), acc.abi()))
+            })?;
+            if let Some((nonce, code, abi)) = account {
+                let storage = self.storage_snapshot(&address)?
+                    .expect("state has a fat trie; qed");
+                pod.insert(
+                    address,
+                    PodAccount {
+                        nonce: nonce,
+                        code: code.map(|code| code.to_vec()),
+                        abi: abi.map(|abi| abi.to_vec()),
+                        storage: storage,
+                    },
+                );
             }
         }
+        Ok(Some(pod.into()))
     }
 
-    /// Revert to the last checkpoint and discard it.
-    pub fn revert_to_checkpoint(&mut self) {
-        if let Some(mut checkpoint) = self.checkpoints.get_mut().pop() {
-            for (k, v) in checkpoint.drain() {
-                match v {
-                    Some(v) => {
-                        match self.cache.get_mut().entry(k) {
-                            Entry::Occupied(mut e) => {
-                                // Merge checkpointed changes back into the main account
-                                // storage preserving the cache.
-                                e.get_mut().overwrite_with(v);
-                            }
-                            Entry::Vacant(e) => {
-                                e.insert(v);
-                            }
-                        }
+    /// Addresses of all accounts committed to the trie.
+    /// Returns `None` unless the state was created with `TrieSpec::Fat`.
+    pub fn committed_addresses(&self) -> trie::Result<Option<Vec<Address>>> {
+        if !self.factories.trie.is_fat() {
+            return Ok(None);
+        }
+
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let mut addresses = Vec::new();
+        for item in trie.iter()? {
+            let (key, _) = item?;
+            addresses.push(Address::from_slice(&key));
+        }
+        Ok(Some(addresses))
+    }
+
+    /// Number of accounts added, or removed if negative, between committed `old_root` and the current root.
+    /// Walks the keys of both account tries side by side. Uncommitted changes are not counted.
+    pub fn account_count_delta(&self, old_root: H256) -> trie::Result<i64> {
+        let old = trie::TrieDB::new(self.db.as_hashdb(), &old_root)?;
+        let new = trie::TrieDB::new(self.db.as_hashdb(), &self.root)?;
+        let mut old_keys = old.iter()?.peekable();
+        let mut new_keys = new.iter()?.peekable();
+        let mut delta = 0;
+        loop {
+            // both walks yield their keys in ascending order
+            let order = match (old_keys.peek(), new_keys.peek()) {
+                (Some(&Ok((ref old_key, _))), Some(&Ok((ref new_key, _)))) => Some(old_key.cmp(new_key)),
+                (Some(&Ok(_)), None) => Some(cmp::Ordering::Less),
+                (None, Some(&Ok(_))) => Some(cmp::Ordering::Greater),
+                (None, None) => break,
+                _ => None,
+            };
+            match order {
+                Some(cmp::Ordering::Less) => {
+                    old_keys.next();
+                    delta -= 1;
+                }
+                Some(cmp::Ordering::Greater) => {
+                    new_keys.next();
+                    delta += 1;
+                }
+                Some(cmp::Ordering::Equal) => {
+                    old_keys.next();
+                    new_keys.next();
+                }
+                None => {
+                    // surface the failed read of either walk
+                    if let Some(Err(err)) = old_keys.next() {
+                        return Err(err);
                     }
-                    None => {
-                        if let Entry::Occupied(e) = self.cache.get_mut().entry(k) {
-                            if e.get().is_dirty() {
-                                e.remove();
-                            }
-                        }
+                    if let Some(Err(err)) = new_keys.next() {
+                        return Err(err);
                     }
                 }
             }
         }
+        Ok(delta)
     }
 
-    fn insert_cache(&self, address: &Address, account: AccountEntry) {
-        // Dirty account which is not in the cache means this is a new account.
-        // It goes directly into the checkpoint as there's nothing to rever to.
-        //
-        // In all other cases account is read as clean first, and after that made
-        // dirty in and added to the checkpoint with `note_cache`.
-        if account.is_dirty() {
-            if let Some(ref mut checkpoint) = self.checkpoints.borrow_mut().last_mut() {
-                if !checkpoint.contains_key(address) {
-                    checkpoint.insert(*address, self.cache.borrow_mut().insert(*address, account));
-                    return;
-                }
+    /// Committed contracts, accounts with code, that have no ABI registered. Walks the whole trie.
+    /// Returns `None` unless the trie is keyed by address (`TrieSpec::Generic` or `TrieSpec::Fat`),
+    /// secure tries do not keep the addresses.
+    pub fn missing_abi_contracts(&self) -> trie::Result<Option<Vec<Address>>> {
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let mut addresses = Vec::new();
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            if key.len() != 20 {
+                return Ok(None);
+            }
+            let account = Account::from_rlp(&value);
+            if account.code_hash() != HASH_EMPTY && account.abi_hash() == HASH_EMPTY {
+                addresses.push(Address::from_slice(&key));
             }
         }
-        self.cache.borrow_mut().insert(*address, account);
+        Ok(Some(addresses))
     }
 
-    fn note_cache(&self, address: &Address) {
-        if let Some(ref mut checkpoint) = self.checkpoints.borrow_mut().last_mut() {
-            if !checkpoint.contains_key(address) {
-                checkpoint.insert(
-                    *address,
-                    self.cache
-                        .borrow()
-                        .get(address)
-                        .map(AccountEntry::clone_dirty),
-                );
+    /// Committed contracts whose code pushes `target` as a `PUSH20` operand, likely callers of it.
+    /// Walks the whole trie and loads the code of every contract.
+    ///
+    /// NOTE: This is a heuristic. A matching operand may be used as plain data rather than
+    /// a call target, and calls to addresses read from storage, calldata or computed at
+    /// runtime are missed. Like `missing_abi_contracts`, it returns `None` unless the trie
+    /// is keyed by address.
+    pub fn contracts_referencing(&self, target: &Address) -> trie::Result<Option<Vec<Address>>> {
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let mut addresses = Vec::new();
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            if key.len() != 20 {
+                return Ok(None);
+            }
+            let mut account = Account::from_rlp(&value);
+            if account.code_hash() == HASH_EMPTY {
+                continue;
+            }
+            let address = Address::from_slice(&key);
+            let account_db = self.factories
+                .accountdb
+                .readonly(self.db.as_hashdb(), account.address_hash(&address));
+            if let Some(code) = account.cache_code(account_db.as_hashdb()) {
+                if pushes_address(&code, target) {
+                    addresses.push(address);
+                }
             }
         }
+        Ok(Some(addresses))
     }
 
-    /// Destroy the current object and return root and database.
-    pub fn drop(self) -> (H256, B) {
-        (self.root, self.db)
+    /// Determine whether the whole storage trie of committed account `a` is present in the database.
+    /// Returns `false` when the storage root or any node below it is missing, e.g. after an
+    /// incomplete snapshot restore. Missing accounts have nothing to validate.
+    pub fn validate_account_storage(&self, a: &Address) -> trie::Result<bool> {
+        let a = &self.map_address(a);
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let account = match trie.get_with(a, Account::from_rlp)? {
+            Some(account) => account,
+            None => return Ok(true),
+        };
+        let storage_root = account
+            .storage_root()
+            .expect("account decoded from the trie has no storage changes; qed");
+        let accountdb = self.factories
+            .accountdb
+            .readonly(self.db.as_hashdb(), account.address_hash(a));
+        let storage = match self.factories.trie.readonly(accountdb.as_hashdb(), storage_root) {
+            Ok(storage) => storage,
+            Err(err) => match *err {
+                trie::TrieError::InvalidStateRoot(_) => return Ok(false),
+                _ => return Err(err),
+            },
+        };
+        for item in storage.iter()? {
+            if let Err(err) = item {
+                match *err {
+                    trie::TrieError::IncompleteDatabase(_) => return Ok(false),
+                    _ => return Err(err),
+                }
+            }
+        }
+        Ok(true)
     }
 
-    pub fn db(self) -> B {
-        self.db
+    /// Number of nodes the committed storage trie of account `a` keeps in the database.
+    /// Nodes small enough to be embedded in their parent are not counted, an empty
+    /// storage has none. Uncommitted changes are not included.
+    ///
+    /// NOTE: This walks the whole storage trie.
+    pub fn storage_node_count(&self, a: &Address) -> trie::Result<usize> {
+        let a = &self.map_address(a);
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let account = match trie.get_with(a, Account::from_rlp)? {
+            Some(account) => account,
+            None => return Ok(0),
+        };
+        let storage_root = account
+            .storage_root()
+            .expect("account decoded from the trie has no storage changes; qed");
+        if *storage_root == HASH_NULL_RLP {
+            return Ok(0);
+        }
+        let accountdb = self.factories
+            .accountdb
+            .readonly(self.db.as_hashdb(), account.address_hash(a));
+        Ok(trie_nodes(accountdb.as_hashdb(), storage_root)?.len())
     }
 
-    /// Return reference to root
-    pub fn root(&self) -> &H256 {
-        &self.root
+    /// Walk the committed state, rebuilding every storage trie and the account trie, and
+    /// determine whether they hash to `expected`. Reports the fraction of accounts walked
+    /// so far to `progress`. Missing or corrupted nodes and undecodable accounts make the
+    /// state mismatch.
+    ///
+    /// NOTE: This reads the whole state and is meant for validating a synced state offline.
+    pub fn verify_against_root<F>(&self, expected: H256, mut progress: F) -> trie::Result<bool>
+    where
+        F: FnMut(f32),
+    {
+        match self.rebuild_root(&mut progress) {
+            Ok(root) => Ok(root == Some(expected)),
+            Err(err) => match *err {
+                TrieError::IncompleteDatabase(_) | TrieError::InvalidStateRoot(_) => Ok(false),
+                _ => Err(err),
+            },
+        }
     }
 
-    /// Create a new contract at address `contract`. If there is already an account at the address
-    /// it will have its code reset, ready for `init_code()`.
-    pub fn new_contract(&mut self, contract: &Address, nonce_offset: U256) {
-        self.insert_cache(
-            contract,
-            AccountEntry::new_dirty(Some(Account::new_contract(
-                self.account_start_nonce + nonce_offset,
-            ))),
-        );
+    // root of the account trie rebuilt from its items, `None` if an account does not decode
+    // or a storage trie does not hash to the storage root of its account.
+    fn rebuild_root<F>(&self, progress: &mut F) -> trie::Result<Option<H256>>
+    where
+        F: FnMut(f32),
+    {
+        let trie = trie::TrieDB::new(self.db.as_hashdb(), &self.root)?;
+        let total = trie.iter()?.count();
+        let mut db = MemoryDB::new();
+        let mut root = H256::new();
+        {
+            let mut rebuilt = trie::TrieDBMut::new(&mut db, &mut root);
+            for (walked, item) in trie.iter()?.enumerate() {
+                let (key, value) = item?;
+                let account = match UntrustedRlp::new(&value).as_val::<BasicAccount>() {
+                    Ok(account) => account,
+                    Err(_) => return Ok(None),
+                };
+                let address_hash = key_address_hash(&key);
+                let accountdb = self.factories
+                    .accountdb
+                    .readonly(self.db.as_hashdb(), address_hash);
+                if rebuild_trie_root(accountdb.as_hashdb(), &account.storage_root)? != account.storage_root {
+                    return Ok(None);
+                }
+                rebuilt.insert(&key, &value)?;
+                progress((walked + 1) as f32 / total as f32);
+            }
+        }
+        Ok(Some(root))
     }
 
-    /// Remove an existing account.
-    pub fn kill_account(&mut self, account: &Address) {
-        self.insert_cache(account, AccountEntry::new_dirty(None));
-    }
+    /// Trie nodes proving the committed values of `keys` in the storage of account `a`,
+    /// each node included once. Missing accounts have no storage to prove.
+    /// Fails with `Error::BatchTooLarge` if there are more than `max_batch_size` keys.
+    pub fn storage_multiproof(&self, a: &Address, keys: &[H256]) -> Result<Vec<Bytes>, Error> {
+        self.check_batch_size(keys.len())?;
+        let a = &self.map_address(a);
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let account = match trie.get_with(a, Account::from_rlp)? {
+            Some(account) => account,
+            None => return Ok(Vec::new()),
+        };
+        let storage_root = account
+            .storage_root()
+            .expect("account decoded from the trie has no storage changes; qed");
+        let accountdb = self.factories
+            .accountdb
+            .readonly(self.db.as_hashdb(), account.address_hash(a));
+        let storage = self.factories
+            .trie
+            .readonly(accountdb.as_hashdb(), storage_root)?;
 
-    // TODO: Check it later.
-    /// Determine whether an account exists.
-    pub fn exists(&self, a: &Address) -> trie::Result<bool> {
-        // Bloom filter does not contain empty accounts, so it is important here to
-        // check if account exists in the database directly before EIP-161 is in effect.
-        self.ensure_cached(a, RequireCache::None, false, |a| {
-            a.is_some()
-        })
+        let mut recorder = trie::Recorder::new();
+        for key in keys {
+            storage.get_with(key, &mut recorder)?;
+        }
+        let mut seen = HashSet::new();
+        Ok(recorder
+            .drain()
+            .into_iter()
+            .filter(|record| seen.insert(record.hash))
+            .map(|record| record.data)
+            .collect())
     }
 
-    /// Determine whether an account exists and if not empty.
-    pub fn exists_and_not_null(&self, a: &Address) -> trie::Result<bool> {
-        self.ensure_cached(a, RequireCache::None, false, |a| {
-            a.map_or(false, |a| !a.is_null())
-        })
-    }
+    /// All populated storage slots of account `a`, including uncommitted changes.
+    /// Returns `None` unless the state was created with `TrieSpec::Fat`, other tries
+    /// do not keep the slot keys. Compare snapshots with `storage_diff`.
+    pub fn storage_snapshot(&self, a: &Address) -> trie::Result<Option<BTreeMap<H256, H256>>> {
+        if !self.factories.trie.is_fat() {
+            return Ok(None);
+        }
 
-    /// Determine whether an account exists and has code or non-zero nonce.
-    pub fn exists_and_has_code_or_nonce(&self, a: &Address) -> trie::Result<bool> {
-        self.ensure_cached(a, RequireCache::CodeSize, false, |a| {
-            a.map_or(false, |a| {
-                a.code_hash() != HASH_EMPTY || *a.nonce() != self.account_start_nonce
-            })
-        })
+        let account = match self.ensure_cached(a, RequireCache::None, false, |acc| acc.map(Account::clone_dirty))? {
+            Some(account) => account,
+            None => return Ok(Some(BTreeMap::new())),
+        };
+        let accountdb = self.factories
+            .accountdb
+            .readonly(self.db.as_hashdb(), account.address_hash(&self.map_address(a)));
+        let storage_db = account.storage_db(accountdb.as_hashdb());
+        let trie = self.factories
+            .trie
+            .readonly(&storage_db, account.base_storage_root())?;
+        let mut storage = BTreeMap::new();
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            let value: U256 = ::rlp::decode(&value);
+            storage.insert(H256::from_slice(&key), value.into());
+        }
+        for (key, value) in account.storage_changes() {
+            if value.is_zero() {
+                storage.remove(key);
+            } else {
+                storage.insert(*key, *value);
+            }
+        }
+        Ok(Some(storage))
     }
 
-    /// Get the nonce of account `a`.
-    pub fn nonce(&self, a: &Address) -> trie::Result<U256> {
-        self.ensure_cached(a, RequireCache::None, true, |a| {
-            a.as_ref()
-                .map_or(self.account_start_nonce, |account| *account.nonce())
-        })
+    /// Rebuild the account bloom from all accounts committed to the trie.
+    /// The bloom is kept up to date by `commit` afterwards.
+    pub fn rebuild_bloom(&mut self) -> trie::Result<()> {
+        let mut hashes = Vec::new();
+        {
+            let trie = self.factories
+                .trie
+                .readonly(self.db.as_hashdb(), &self.root)?;
+            for item in trie.iter()? {
+                let (key, _) = item?;
+                // address keyed tries yield the addresses, secure tries their hashes
+                hashes.push(if key.len() == 20 {
+                    key.crypt_hash()
+                } else {
+                    H256::from_slice(&key)
+                });
+            }
+        }
+        let mut bloom = Bloom::new_for_fp_rate(
+            cmp::max(hashes.len(), ACCOUNT_BLOOM_MIN_ITEMS),
+            ACCOUNT_BLOOM_FP_RATE,
+        );
+        for hash in &hashes {
+            bloom.set(&**hash);
+        }
+        self.account_bloom = Some(Arc::new(Mutex::new(bloom)));
+        Ok(())
     }
 
-    /// Get the storage root of account `a`.
-    pub fn storage_root(&self, a: &Address) -> trie::Result<Option<H256>> {
-        self.ensure_cached(a, RequireCache::None, true, |a| {
-            a.as_ref()
-                .and_then(|account| account.storage_root().cloned())
-        })
+    /// Drop the account bloom, e.g. when it is suspected to be wrong.
+    pub fn clear_bloom(&mut self) {
+        self.account_bloom = None;
     }
 
-    // TODO: Add global cache.
-    /// Mutate storage of account `address` so that it is `value` for `key`.
-    pub fn storage_at(&self, address: &Address, key: &H256) -> trie::Result<H256> {
-        // Storage key search and update works like this:
-        // 1. If there's an entry for the account in the local cache check for the key and return it if found.
-        // 2. If there's an entry for the account in the global cache check for the key or load it into that account.
-        // 3. If account is missing in the global cache load it into the local cache and cache the key there.
+    /// Whether account `a` is known not to be committed to the trie.
+    /// Always `false` without an account bloom, see `rebuild_bloom`.
+    pub fn definitely_absent(&self, a: &Address) -> bool {
+        match self.account_bloom {
+            Some(ref bloom) => !bloom
+                .lock()
+                .expect("account bloom lock poisoned")
+                .check(&*self.map_address(a).crypt_hash()),
+            None => false,
+        }
+    }
 
-        // check local cache first without updating
+    /// Root of a trie holding only the committed accounts whose address matches `predicate`.
+    /// The main state is left untouched.
+    ///
+    /// NOTE: Requires a trie keyed by address (`TrieSpec::Generic` or `TrieSpec::Fat`).
+    pub fn subtree_root<F>(&self, predicate: F) -> Result<H256, Error>
+    where
+        F: Fn(&Address) -> bool,
+    {
+        let trie = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let mut db = MemoryDB::new();
+        let mut root = H256::new();
         {
-            let local_cache = self.cache.borrow_mut();
-            let mut local_account = None;
-            if let Some(maybe_acc) = local_cache.get(address) {
-                match maybe_acc.account {
-                    Some(ref account) => {
-                        if let Some(value) = account.cached_storage_at(key) {
-                            return Ok(value);
-                        } else {
-                            local_account = Some(maybe_acc);
-                        }
-                    }
-                    _ => return Ok(H256::new()),
+            let mut subtree = self.factories.trie.create(&mut db, &mut root);
+            for item in trie.iter()? {
+                let (key, value) = item?;
+                if key.len() != 20 {
+                    return Err(UtilError::from("subtree roots need a trie keyed by address".to_owned()).into());
                 }
-            }
-
-            // otherwise cache the account localy and cache storage key there.
-            if let Some(ref mut acc) = local_account {
-                if let Some(ref account) = acc.account {
-                    let account_db = self.factories
-                        .accountdb
-                        .readonly(self.db.as_hashdb(), account.address_hash(address));
-                    return account.storage_at(&self.factories.trie, account_db.as_hashdb(), key);
-                } else {
-                    return Ok(H256::new());
+                if predicate(&Address::from_slice(&key)) {
+                    subtree.insert(&key, &value)?;
                 }
             }
         }
+        Ok(root)
+    }
 
-        // TODO: add account bloom. check if the account could exist before any requests to trie
+    /// Total number of populated storage slots across all committed accounts.
+    ///
+    /// NOTE: This walks the storage trie of every account and is meant for offline
+    /// maintenance, not for use while executing blocks. Uncommitted changes are not counted.
+    pub fn total_storage_slots(&self) -> trie::Result<u64> {
+        self.total_storage_slots_with_progress(|_| {})
+    }
 
-        // account is not found in the global cache, get from the DB and insert into local
-        let db = self.factories
+    /// Same as `total_storage_slots`, reporting the number of accounts walked so far to `progress`.
+    pub fn total_storage_slots_with_progress<F>(&self, mut progress: F) -> trie::Result<u64>
+    where
+        F: FnMut(u64),
+    {
+        let trie = self.factories
             .trie
-            .readonly(self.db.as_hashdb(), &self.root)
-            .expect(SEC_TRIE_DB_UNWRAP_STR);
-        let maybe_acc = db.get_with(address, Account::from_rlp)?;
-        let r = maybe_acc.as_ref().map_or(Ok(H256::new()), |a| {
-            let account_db = self.factories
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let mut slots = 0;
+        let mut accounts = 0;
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            let account = Account::from_rlp(&value);
+            let address_hash = key_address_hash(&key);
+            let accountdb = self.factories
                 .accountdb
-                .readonly(self.db.as_hashdb(), a.address_hash(address));
-            a.storage_at(&self.factories.trie, account_db.as_hashdb(), key)
-        });
-        self.insert_cache(address, AccountEntry::new_clean(maybe_acc));
-        r
+                .readonly(self.db.as_hashdb(), address_hash);
+            let storage_root = account
+                .storage_root()
+                .expect("account decoded from the trie has no storage changes; qed");
+            let storage = self.factories
+                .trie
+                .readonly(accountdb.as_hashdb(), storage_root)?;
+            for slot in storage.iter()? {
+                slot?;
+                slots += 1;
+            }
+            accounts += 1;
+            progress(accounts);
+        }
+        Ok(slots)
     }
 
-    /// Get accounts' code.
-    pub fn code(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
-        self.ensure_cached(a, RequireCache::Code, true, |a| {
-            a.as_ref().map_or(None, |a| a.code().clone())
-        })
+    /// Create a recoverable checkpoint of this state.
+    /// Fails without creating one if `max_checkpoint_depth` checkpoints are open,
+    /// the failed checkpoint must then not be reverted or discarded.
+    pub fn checkpoint(&mut self) -> Result<(), CheckpointLimitExceeded> {
+        let checkpoints = self.checkpoints.get_mut();
+        if let Some(limit) = self.max_checkpoint_depth {
+            if checkpoints.len() >= limit {
+                return Err(CheckpointLimitExceeded { limit: limit });
+            }
+        }
+        checkpoints.push(HashMap::new());
+        Ok(())
     }
 
-    /// Get an account's code hash.
-    pub fn code_hash(&self, a: &Address) -> trie::Result<H256> {
-        self.ensure_cached(a, RequireCache::None, true, |a| {
-            a.as_ref().map_or(HASH_EMPTY, |a| a.code_hash())
-        })
+    /// Open a checkpoint labeled `name`, see `revert_to_savepoint`.
+    /// The savepoint ends with its checkpoint, so `discard_checkpoint` releases it.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), CheckpointLimitExceeded> {
+        self.checkpoint()?;
+        let depth = self.checkpoints.get_mut().len();
+        self.savepoints.push((name.to_owned(), depth));
+        Ok(())
     }
 
-    /// Get accounts' code size.
-    pub fn code_size(&self, a: &Address) -> trie::Result<Option<usize>> {
-        self.ensure_cached(a, RequireCache::CodeSize, true, |a| {
-            a.as_ref().and_then(|a| a.code_size())
-        })
+    /// Revert all changes since the latest savepoint named `name`, reverting and
+    /// discarding every checkpoint and savepoint opened after it, and the savepoint itself.
+    pub fn revert_to_savepoint(&mut self, name: &str) -> Result<(), UnknownSavepoint> {
+        let depth = match self.savepoints.iter().rev().find(|&&(ref n, _)| n == name) {
+            Some(&(_, depth)) => depth,
+            None => return Err(UnknownSavepoint { name: name.to_owned() }),
+        };
+        while self.checkpoints.get_mut().len() >= depth {
+            self.revert_to_checkpoint();
+        }
+        Ok(())
     }
 
-    /// Get accounts' ABI.
-    pub fn abi(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
-        self.ensure_cached(a, RequireCache::Abi, true, |a| {
-            a.as_ref().map_or(None, |a| a.abi().clone())
-        })
+    /// Merge last checkpoint with previous.
+    pub fn discard_checkpoint(&mut self) {
+        // merge with previous checkpoint
+        let last = self.checkpoints.get_mut().pop();
+        let depth = self.checkpoints.get_mut().len();
+        for frozen_at in self.frozen_slots.values_mut() {
+            *frozen_at = cmp::min(*frozen_at, depth);
+        }
+        for warmed_at in self.warm_slots.values_mut() {
+            *warmed_at = cmp::min(*warmed_at, depth);
+        }
+        if self.storage_writes.len() > depth + 1 {
+            let writes: usize = self.storage_writes.drain(depth + 1..).sum();
+            self.storage_writes[depth] += writes;
+        }
+        self.savepoints.retain(|&(_, opened_at)| opened_at <= depth);
+        if let Some(mut checkpoint) = last {
+            if let Some(ref mut prev) = self.checkpoints.get_mut().last_mut() {
+                if prev.is_empty() {
+                    **prev = checkpoint;
+                } else {
+                    for (k, v) in checkpoint.drain() {
+                        prev.entry(k).or_insert(v);
+                    }
+                }
+            }
+        }
     }
 
-    /// Get an account's ABI hash.
-    pub fn abi_hash(&self, a: &Address) -> trie::Result<H256> {
-        self.ensure_cached(a, RequireCache::None, true, |a| {
-            a.as_ref().map_or(HASH_EMPTY, |a| a.abi_hash())
-        })
+    /// Revert to the last checkpoint and discard it.
+    pub fn revert_to_checkpoint(&mut self) {
+        if let Some(mut checkpoint) = self.checkpoints.get_mut().pop() {
+            let depth = self.checkpoints.get_mut().len();
+            self.frozen_slots.retain(|_, frozen_at| *frozen_at <= depth);
+            self.warm_slots.retain(|_, warmed_at| *warmed_at <= depth);
+            self.storage_writes.truncate(depth + 1);
+            self.savepoints.retain(|&(_, opened_at)| opened_at <= depth);
+            let limit = self.storage_cache_limit();
+            for (k, v) in checkpoint.drain() {
+                match v {
+                    Some(mut v) => {
+                        v.limit_storage_cache(limit);
+                        match self.cache.get_mut().entry(k) {
+                            Entry::Occupied(mut e) => {
+                                // Merge checkpointed changes back into the main account
+                                // storage preserving the cache.
+                                e.get_mut().overwrite_with(v);
+                            }
+                            Entry::Vacant(e) => {
+                                e.insert(v);
+                            }
+                        }
+                    }
+                    None => {
+                        if let Entry::Occupied(e) = self.cache.get_mut().entry(k) {
+                            if e.get().is_dirty() {
+                                e.remove();
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    /// Get accounts' ABI size.
-    pub fn abi_size(&self, a: &Address) -> trie::Result<Option<usize>> {
-        self.ensure_cached(a, RequireCache::AbiSize, true, |a| {
-            a.as_ref().and_then(|a| a.abi_size())
-        })
+    /// Number of account trie nodes read from the state database by account lookups
+    /// since the state was created, nodes served by `cache_trie_nodes` excluded.
+    pub fn trie_node_loads(&self) -> u64 {
+        self.trie_nodes
+            .lock()
+            .expect("trie node cache lock poisoned")
+            .loads()
     }
 
-    /// Increment the nonce of account `a` by 1.
-    pub fn inc_nonce(&mut self, a: &Address) -> trie::Result<()> {
-        self.require(a, false, false).map(|mut x| x.inc_nonce())
+    // account trie reads of account lookups missing the account cache
+    fn trie_node_db(&self) -> NodeCachingDB {
+        NodeCachingDB::new(self.db.as_hashdb(), &self.trie_nodes, self.cache_trie_nodes)
     }
 
-    /// Mutate storage of account `a` so that it is `value` for `key`.
-    pub fn set_storage(&mut self, a: &Address, key: H256, value: H256) -> trie::Result<()> {
-        if self.storage_at(a, &key)? != value {
-            self.require(a, false, false)?.set_storage(key, value)
-        }
-
-        Ok(())
+    /// Number of checkpoints on the stack.
+    pub fn checkpoint_depth(&self) -> usize {
+        self.checkpoints.borrow().len()
     }
 
-    /// Initialise the code of account `a` so that it is `code`.
-    /// NOTE: Account should have been created with `new_contract`.
-    pub fn init_code(&mut self, a: &Address, code: Bytes) -> trie::Result<()> {
-        self.require_or_from(
-            a,
-            true,
-            false,
-            || Account::new_contract(self.account_start_nonce),
-            |_| {},
-        )?
-            .init_code(code);
-        Ok(())
-    }
+    /// Net change in state size since the last checkpoint, see `ApplyOutcome::state_growth_bytes`.
+    fn checkpoint_state_growth(&self) -> trie::Result<i64> {
+        let checkpoints = self.checkpoints.borrow();
+        let checkpoint = match checkpoints.last() {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(0),
+        };
+        let cache = self.cache.borrow();
+        let mut growth = 0;
+        for (address, backup) in checkpoint {
+            // accounts not cached before the checkpoint are still as committed
+            let original = match *backup {
+                Some(ref entry) => entry.account.as_ref().map(Account::clone_dirty),
+                None => {
+                    let root = self.read_root();
+                    let db = self.factories
+                        .trie
+                        .readonly(self.db.as_hashdb(), &root)?;
+                    db.get_with(address, Account::from_rlp)?
+                }
+            };
+            let current = cache
+                .get(address)
+                .and_then(|entry| entry.account.as_ref().map(Account::clone_dirty));
 
-    /// Reset the code of account `a` so that it is `code`.
-    pub fn reset_code(&mut self, a: &Address, code: Bytes) -> trie::Result<()> {
-        self.require_or_from(
-            a,
-            true,
-            false,
-            || Account::new_contract(self.account_start_nonce),
-            |_| {},
-        )?
-            .reset_code(code);
-        Ok(())
+            if let Some(ref current) = current {
+                let accountdb = self.factories
+                    .accountdb
+                    .readonly(self.db.as_hashdb(), current.address_hash(address));
+                for (key, value) in current.storage_changes() {
+                    let before = match original {
+                        Some(ref original) => original.storage_at(&self.factories.trie, accountdb.as_hashdb(), key)?,
+                        None => H256::new(),
+                    };
+                    match (before.is_zero(), value.is_zero()) {
+                        (true, false) => growth += STORAGE_SLOT_BYTES,
+                        (false, true) => growth -= STORAGE_SLOT_BYTES,
+                        _ => {}
+                    }
+                }
+            }
+
+            let hashes = |account: &Option<Account>| {
+                account
+                    .as_ref()
+                    .map_or((HASH_EMPTY, HASH_EMPTY), |a| (a.code_hash(), a.abi_hash()))
+            };
+            if hashes(&original) != hashes(&current) {
+                growth += self.code_and_abi_bytes(address, current) - self.code_and_abi_bytes(address, original);
+            }
+        }
+        Ok(growth)
     }
 
-    /// Initialise the ABI of account `a` so that it is `abi`.
-    /// NOTE: Account should have been created with `new_contract`.
-    pub fn init_abi(&mut self, a: &Address, abi: Bytes) -> trie::Result<()> {
-        self.require_or_from(
-            a,
-            false,
-            true,
-            || Account::new_contract(self.account_start_nonce),
-            |_| {},
-        )?
-            .init_abi(abi);
-        Ok(())
+    fn code_and_abi_bytes(&self, address: &Address, account: Option<Account>) -> i64 {
+        account.map_or(0, |mut account| {
+            let accountdb = self.factories
+                .accountdb
+                .readonly(self.db.as_hashdb(), account.address_hash(address));
+            self.update_account_cache(RequireCache::CodeAndAbi, &mut account, accountdb.as_hashdb());
+            (account.code_size().unwrap_or(0) + account.abi_size().unwrap_or(0)) as i64
+        })
     }
 
-    /// Reset the abi of account `a` so that it is `abi`.
-    pub fn reset_abi(&mut self, a: &Address, abi: Bytes) -> trie::Result<()> {
-        self.require_or_from(
-            a,
-            false,
-            true,
-            || Account::new_contract(self.account_start_nonce),
-            |_| {},
-        )?
-            .reset_abi(abi);
-        Ok(())
+    /// Collapse each run of consecutive checkpoints without recorded changes into one.
+    /// Reverting an empty checkpoint changes nothing, so only the depth is affected:
+    /// returns the new `checkpoint_depth` of every old one, indexed by the old depth.
+    /// Callers holding paired checkpoint and revert calls, like `Executive`, must translate
+    /// their depths through it and only pair the remaining checkpoints.
+    pub fn compact_checkpoints(&mut self) -> Vec<usize> {
+        // depth each checkpoint depth maps to once the empty runs are collapsed
+        let mut depths = vec![0];
+        {
+            let checkpoints = self.checkpoints.get_mut();
+            for (i, checkpoint) in checkpoints.iter().enumerate() {
+                let depth = depths[i];
+                let collapsed = i > 0 && checkpoint.is_empty() && checkpoints[i - 1].is_empty();
+                depths.push(if collapsed { depth } else { depth + 1 });
+            }
+        }
+        for frozen_at in self.frozen_slots.values_mut() {
+            *frozen_at = depths[*frozen_at];
+        }
+        for warmed_at in self.warm_slots.values_mut() {
+            *warmed_at = depths[*warmed_at];
+        }
+        for &mut (_, ref mut opened_at) in &mut self.savepoints {
+            *opened_at = depths[*opened_at];
+        }
+        let mut storage_writes = Vec::new();
+        for (depth, writes) in self.storage_writes.iter().enumerate() {
+            let depth = depths[depth];
+            if storage_writes.len() <= depth {
+                storage_writes.resize(depth + 1, 0);
+            }
+            storage_writes[depth] += *writes;
+        }
+        self.storage_writes = storage_writes;
+        self.checkpoints
+            .get_mut()
+            .dedup_by(|checkpoint, previous| checkpoint.is_empty() && previous.is_empty());
+        depths
     }
 
-    /// Execute a given transaction.
-    /// This will change the state accordingly.
-    pub fn apply(
-        &mut self,
-        env_info: &EnvInfo,
-        t: &mut SignedTransaction,
-        tracing: bool,
-        check_permission: bool,
-        check_quota: bool,
-    ) -> ApplyResult {
-        //        let old = self.to_pod();
-        let engine = &NullEngine::default();
-        let options = TransactOptions {
-            tracing: tracing,
-            vm_tracing: false,
-            check_permission: check_permission,
-            check_quota: check_quota,
+    /// Return both the committed and the uncommitted version of account `a`.
+    /// A dirty entry for a killed account shows as `committed` only.
+    pub fn account_view(&self, a: &Address) -> trie::Result<AccountView> {
+        let a = &self.map_address(a);
+        let db = self.factories
+            .trie
+            .readonly(self.db.as_hashdb(), &self.root)?;
+        let committed = db.get_with(a, Account::from_rlp)?
+            .as_ref()
+            .map(AccountMeta::from);
+        let pending = match self.cache.borrow().get(a) {
+            Some(entry) if entry.is_dirty() => entry.account.as_ref().map(AccountMeta::from),
+            _ => None,
         };
-        let vm_factory = self.factories.vm.clone();
-        let native_factory = self.factories.native.clone();
-        let e = Executive::new(self, env_info, engine, &vm_factory, &native_factory).transact(t, options)?;
-
-        // TODO uncomment once to_pod() works correctly.
-        // trace!("Applied transaction. Diff:\n{}\n", state_diff::diff_pod(&old, &self.to_pod()));
-        let receipt_error = e.exception.and_then(|evm_error| match evm_error {
-            EvmError::OutOfGas => Some(ReceiptError::OutOfGas),
-            EvmError::BadJumpDestination { .. } => Some(ReceiptError::BadJumpDestination),
-            EvmError::BadInstruction { .. } => Some(ReceiptError::BadInstruction),
-            EvmError::StackUnderflow { .. } => Some(ReceiptError::StackUnderflow),
-            EvmError::OutOfStack { .. } => Some(ReceiptError::OutOfStack),
-            EvmError::MutableCallInStaticContext => Some(ReceiptError::MutableCallInStaticContext),
-            EvmError::Internal(_) => Some(ReceiptError::Internal),
-            EvmError::OutOfBounds => Some(ReceiptError::OutOfBounds),
-            EvmError::Reverted => Some(ReceiptError::Reverted),
-        });
-        let receipt = Receipt::new(
-            None,
-            e.cumulative_gas_used,
-            e.logs,
-            receipt_error,
-            t.account_nonce().clone(),
-        );
-        trace!(target: "state", "Transaction receipt: {:?}", receipt);
-        Ok(ApplyOutcome {
-            receipt: receipt,
-            trace: e.trace,
+        Ok(AccountView {
+            committed: committed,
+            pending: pending,
         })
     }
 
-    /// Commit accounts to SecTrieDBMut. This is similar to cpp-ethereum's dev::eth::commit.
-    /// `accounts` is mutable because we may need to commit the code or storage and record that.
-    #[cfg_attr(feature = "dev", allow(match_ref_pats))]
-    #[cfg_attr(feature = "dev", allow(needless_borrow))]
-    fn commit_into(
-        factories: &Factories,
-        db: &mut B,
-        root: &mut H256,
-        accounts: &mut HashMap<Address, AccountEntry>,
-    ) -> Result<(), Error> {
-        // first, commit the sub trees.
-        for (address, ref mut a) in accounts.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
-            if let Some(ref mut account) = a.account {
-                let addr_hash = account.address_hash(address);
-                {
-                    let mut account_db = factories.accountdb.create(db.as_hashdb_mut(), addr_hash);
-                    account.commit_storage(&factories.trie, account_db.as_hashdb_mut())?;
-
-                    account.commit_code(account_db.as_hashdb_mut());
-                    account.commit_abi(account_db.as_hashdb_mut())
-                }
+    /// Follow the redirections returned by `follow`, e.g. the implementation of a proxy,
+    /// from `a` to the account redirecting no further. The code of every account is loaded.
+    /// Returns `None` if an account on the way does not exist, and an error on a cycle
+    /// or more than `MAX_RESOLVE_HOPS` redirections.
+    pub fn resolve_account<F>(&self, a: &Address, follow: F) -> Result<Option<Address>, Error>
+    where
+        F: Fn(&Account) -> Option<Address>,
+    {
+        let mut visited = HashSet::new();
+        let mut current = *a;
+        loop {
+            if !visited.insert(current) {
+                return Err(UtilError::from(format!("cycle resolving account {} at {}", a, current)).into());
             }
-        }
-
-        {
-            let mut trie = factories.trie.from_existing(db.as_hashdb_mut(), root)?;
-            for (address, ref mut a) in accounts.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
-                a.state = AccountState::Committed;
-                match a.account {
-                    Some(ref mut account) => {
-                        trie.insert(address, &account.rlp())?;
-                    }
-                    None => {
-                        trie.remove(address)?;
-                    }
-                }
+            if visited.len() > MAX_RESOLVE_HOPS {
+                return Err(UtilError::from(format!("too many redirections resolving account {}", a)).into());
+            }
+            match self.ensure_cached(&current, RequireCache::Code, false, |account| account.map(|account| follow(account)))? {
+                None => return Ok(None),
+                Some(None) => return Ok(Some(current)),
+                Some(Some(next)) => current = next,
             }
         }
-
-        Ok(())
     }
 
-    /// Commits our cached account changes into the trie.
-    pub fn commit(&mut self) -> Result<(), Error> {
-        assert!(self.checkpoints.borrow().is_empty());
-        Self::commit_into(
-            &self.factories,
-            &mut self.db,
-            &mut self.root,
-            &mut *self.cache.borrow_mut(),
-        )
+    /// Capture the uncommitted changes, to be restored with `restore_dirty`.
+    /// A lighter alternative to `checkpoint` for single-level speculation.
+    pub fn dirty_snapshot(&self) -> DirtySnapshot {
+        DirtySnapshot {
+            accounts: self.cache
+                .borrow()
+                .iter()
+                .filter_map(|(address, entry)| entry.clone_if_dirty().map(|entry| (*address, entry)))
+                .collect(),
+        }
     }
 
-    /// Clear state cache
-    pub fn clear(&mut self) {
-        self.cache.borrow_mut().clear();
+    /// Drop all changes made since `snapshot` was taken.
+    /// NOTE: The state must not have been committed in between.
+    pub fn restore_dirty(&mut self, snapshot: DirtySnapshot) {
+        let limit = self.storage_cache_limit();
+        let cache = self.cache.get_mut();
+        // accounts changed only after the snapshot are reloaded from the trie on demand.
+        cache.retain(|address, entry| !entry.is_dirty() || snapshot.accounts.contains_key(address));
+        for (address, mut entry) in snapshot.accounts {
+            entry.limit_storage_cache(limit);
+            cache.insert(address, entry);
+        }
     }
 
-    // TODO
-    // load required account data from the databases.
-    fn update_account_cache(
-        require: RequireCache,
-        account: &mut Account,
-        //state_db: &B,
-        db: &HashDB,
-    ) {
-        match (account.is_cached(), require) {
-            (false, RequireCache::Code) | (false, RequireCache::CodeSize) => {
-                // if there's already code in the global cache, always cache it
-                // locally.
-                // let hash = account.code_hash();
-                // match state_db.get_cached_code(&hash) {
-                //     Some(code) => account.cache_given_code(code),
-                //     None => {
-                //         match require {
-                //             RequireCache::None => {}
-                //             RequireCache::Code => {
-                //                 if let Some(code) = account.cache_code(db) {
-                //                     // propagate code loaded from the database to
-                //                     // the global code cache.
-                //                     state_db.cache_code(hash, code)
-                //                 }
-                //             }
-                //             RequireCache::CodeSize => {
-                //                 account.cache_code_size(db);
-                //             }
-                //         }
-                //     }
-                // }
-                account.cache_code(db);
-            }
-            _ => {}
-        };
-
-        match (account.is_abi_cached(), require) {
-            (false, RequireCache::Abi) | (false, RequireCache::AbiSize) => {
-                account.cache_abi(db);
+    /// Bring the uncommitted changes of `other` into this state, resolving writes
+    /// made by both states with `policy`. Nothing is merged if an error is returned.
+    /// NOTE: Both states must be based on the same root.
+    pub fn merge_dirty_from<O: Backend>(&mut self, other: &State<O>, policy: &ConflictPolicy) -> Result<(), MergeConflict> {
+        let theirs: Vec<(Address, AccountEntry)> = other
+            .cache
+            .borrow()
+            .iter()
+            .filter_map(|(address, entry)| entry.clone_if_dirty().map(|entry| (*address, entry)))
+            .collect();
+        let mut merged = Vec::with_capacity(theirs.len());
+        {
+            let cache = self.cache.borrow();
+            for (address, entry) in theirs {
+                let entry = match cache.get(&address) {
+                    Some(mine) if mine.is_dirty() => AccountEntry::new_dirty(merge_accounts(
+                        &address,
+                        mine.account.as_ref(),
+                        entry.account,
+                        policy,
+                    )?),
+                    _ => entry,
+                };
+                merged.push((address, entry));
             }
-            _ => {}
         }
+        for (address, entry) in merged {
+            self.insert_cache(&address, entry);
+        }
+        Ok(())
     }
 
-    /// Check caches for required data
-    /// First searches for account in the local, then the shared cache.
-    /// Populates local cache if nothing found.
-    fn ensure_cached<F, U>(&self, a: &Address, require: RequireCache, _: bool, f: F) -> trie::Result<U>
+    /// Run `f` as a single unit of work: its changes are kept if it returns `Ok`
+    /// and reverted if it returns `Err`. Calls may be nested.
+    pub fn with_transaction<F, R>(&mut self, f: F) -> Result<R, Error>
     where
-        F: Fn(Option<&Account>) -> U,
+        F: FnOnce(&mut Self) -> Result<R, Error>,
     {
-        // check local cache first
-        if let Some(ref mut maybe_acc) = self.cache.borrow_mut().get_mut(a) {
-            if let Some(ref mut account) = maybe_acc.account {
-                let accountdb = self.factories
-                    .accountdb
-                    .readonly(self.db.as_hashdb(), account.address_hash(a));
-                Self::update_account_cache(require, account, /* &self.db, */ accountdb.as_hashdb());
-                return Ok(f(Some(account)));
-            }
-            return Ok(f(None));
+        self.checkpoint()?;
+        let result = f(self);
+        match result {
+            Ok(_) => self.discard_checkpoint(),
+            Err(_) => self.revert_to_checkpoint(),
         }
-        // TODO: check global cache
+        result
+    }
 
-        // first check if it is not in database for sure
+    /// Check that the account cache agrees with the checkpoint backups.
+    ///
+    /// Panics if an account that is not `Dirty` still carries uncommitted changes,
+    /// or if a checkpoint holds a backup of an account that is missing from the cache.
+    /// Checks nothing in builds without debug assertions.
+    pub fn verify_cache_consistency(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let cache = self.cache.borrow();
+        for (address, entry) in cache.iter().filter(|&(_, ref entry)| !entry.is_dirty()) {
+            if let Some(ref account) = entry.account {
+                assert!(
+                    !account.has_uncommitted_changes(),
+                    "{:?} account {:?} has uncommitted changes",
+                    entry.state,
+                    address
+                );
+            }
+        }
+        for checkpoint in self.checkpoints.borrow().iter() {
+            for address in checkpoint.keys() {
+                assert!(
+                    cache.contains_key(address),
+                    "checkpointed account {:?} is missing from the cache",
+                    address
+                );
+            }
+        }
+    }
 
-        // not found in the global cache, get from the DB and insert into local
-        let db = self.factories
-            .trie
-            .readonly(self.db.as_hashdb(), &self.root)?;
-        let mut maybe_acc = db.get_with(a, Account::from_rlp)?;
-        if let Some(ref mut account) = maybe_acc.as_mut() {
-            let accountdb = self.factories
-                .accountdb
-                .readonly(self.db.as_hashdb(), account.address_hash(a));
-            Self::update_account_cache(require, account, /* &self.db, */ accountdb.as_hashdb());
+    /// Root accounts missing from the cache are read at.
+    ///
+    /// With `follow_commits` set this is the latest root committed by any state sharing
+    /// the backend. Only meant for readers: accounts already cached are not refreshed unless `refresh`ed,
+    /// so reads may mix roots, and the nodes of a sibling's commit are only visible
+    /// once they have been written to the shared database.
+    fn read_root(&self) -> H256 {
+        if self.follow_commits {
+            self.db.latest_root().unwrap_or(self.root)
+        } else {
+            self.root
         }
-        let r = f(maybe_acc.as_ref());
-        self.insert_cache(a, AccountEntry::new_clean(maybe_acc));
-        Ok(r)
     }
 
-    /// Pull account `a` in our cache from the trie DB.
-    /// `require_code` requires that the code be cached, too.
-    /// `require_abi` requires that the abi be cached, too.
-    fn require<'a>(&'a self, a: &Address, require_code: bool, require_abi: bool) -> trie::Result<RefMut<'a, Account>> {
-        self.require_or_from(
-            a,
-            require_code,
-            require_abi,
-            || Account::new_basic(self.account_start_nonce),
-            |_| {},
-        )
+    /// Load account `a` again from the latest root committed through the backend by any state
+    /// sharing it, regardless of `follow_commits`. Uncommitted changes of `a` are kept.
+    /// Fails if the backend tracks no committed root, e.g. nothing was committed through it yet.
+    pub fn refresh(&self, a: &Address) -> Result<(), Error> {
+        let root = match self.db.latest_root() {
+            Some(root) => root,
+            None => return Err(UtilError::from("the backend tracks no committed root".to_owned()).into()),
+        };
+        let address = self.map_address(a);
+        let dirty = self.cache
+            .borrow()
+            .get(&address)
+            .map_or(false, AccountEntry::is_dirty);
+        if dirty {
+            return Ok(());
+        }
+        let nodes = self.trie_node_db();
+        let db = self.factories.trie.readonly(&nodes, &root)?;
+        let account = db.get_with(&address, Account::from_rlp)?;
+        self.insert_cache(&address, AccountEntry::new_clean(account));
+        Ok(())
     }
 
-    /// Pull account `a` in our cache from the trie DB.
-    /// `require_code` requires that the code be cached, too.
-    /// `require_abi` requires that the abi be cached, too.
-    /// If it doesn't exist, make account equal the evaluation of `default`.
-    fn require_or_from<'a, F, G>(
-        &'a self,
-        a: &Address,
-        require_code: bool,
-        require_abi: bool,
-        default: F,
-        not_default: G,
-    ) -> trie::Result<RefMut<'a, Account>>
-    where
-        F: FnOnce() -> Account,
-        G: FnOnce(&mut Account),
-    {
-        let contains_key = self.cache.borrow().contains_key(a);
-        if !contains_key {
-            let db = self.factories
-                .trie
-                .readonly(self.db.as_hashdb(), &self.root)?;
-            let maybe_acc = AccountEntry::new_clean(db.get_with(a, Account::from_rlp)?);
-            self.insert_cache(a, maybe_acc);
+    /// Address account `a` is stored under, see `address_mapper`.
+    fn map_address(&self, a: &Address) -> Address {
+        match self.address_mapper {
+            Some(ref mapper) => mapper(a),
+            None => *a,
         }
-        self.note_cache(a);
+    }
 
-        // at this point the entry is guaranteed to be in the cache.
-        Ok(RefMut::map(self.cache.borrow_mut(), |c| {
-            let entry = c.get_mut(a)
-                .expect("entry known to exist in the cache; qed");
+    // clean storage slots cached per account, see `storage_cache_items`
+    fn storage_cache_limit(&self) -> usize {
+        self.storage_cache_items.unwrap_or(usize::max_value())
+    }
 
-            match &mut entry.account {
-                &mut Some(ref mut acc) => not_default(acc),
-                slot => *slot = Some(default()),
-            }
-
-            // set the dirty flag after changing account data.
-            entry.state = AccountState::Dirty;
-            match entry.account {
-                Some(ref mut account) => {
-                    if require_code || require_abi {
-                        let addr_hash = account.address_hash(a);
-                        let accountdb = self.factories
-                            .accountdb
-                            .readonly(self.db.as_hashdb(), addr_hash);
-
-                        if require_code {
-                            Self::update_account_cache(
-                                RequireCache::Code,
-                                account,
-                                /* &self.db, */
-                                accountdb.as_hashdb(),
-                            );
-                        }
-
-                        if require_abi {
-                            Self::update_account_cache(
-                                RequireCache::Abi,
-                                account,
-                                /* &self.db, */
-                                accountdb.as_hashdb(),
-                            );
-                        }
-                    }
-
-                    account
+    fn insert_cache(&self, address: &Address, mut account: AccountEntry) {
+        account.limit_storage_cache(self.storage_cache_limit());
+        // Dirty account which is not in the cache means this is a new account.
+        // It goes directly into the checkpoint as there's nothing to rever to.
+        //
+        // In all other cases account is read as clean first, and after that made
+        // dirty in and added to the checkpoint with `note_cache`.
+        if account.is_dirty() {
+            if let Some(ref mut checkpoint) = self.checkpoints.borrow_mut().last_mut() {
+                if !checkpoint.contains_key(address) {
+                    checkpoint.insert(*address, self.cache.borrow_mut().insert(*address, account));
+                    return;
                 }
-                _ => panic!("Required account must always exist; qed"),
             }
-        }))
+        }
+        self.cache.borrow_mut().insert(*address, account);
     }
-}
 
-impl<B: Backend> fmt::Debug for State<B> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.cache.borrow())
+    fn note_cache(&self, address: &Address) {
+        if let Some(ref mut checkpoint) = self.checkpoints.borrow_mut().last_mut() {
+            if !checkpoint.contains_key(address) {
+                checkpoint.insert(
+                    *address,
+                    self.cache
+                        .borrow()
+                        .get(address)
+                        .map(AccountEntry::clone_dirty),
+                );
+            }
+        }
     }
-}
 
-// TODO: cloning for `State` shouldn't be possible in general; Remove this and use
-// checkpoints where possible.
-impl Clone for State<StateDB> {
-    fn clone(&self) -> State<StateDB> {
-        let cache = {
-            let mut cache: HashMap<Address, AccountEntry> = HashMap::new();
-            for (key, val) in self.cache.borrow().iter() {
-                if let Some(entry) = val.clone_if_dirty() {
-                    cache.insert(*key, entry);
-                }
-            }
-            cache
-        };
+    /// Destroy the current object and return root and database.
+    pub fn drop(self) -> (H256, B) {
+        (self.root, self.db)
+    }
 
-        State {
-            db: self.db.boxed_clone(),
-            root: self.root,
-            cache: RefCell::new(cache),
-            checkpoints: RefCell::new(Vec::new()),
-            account_start_nonce: self.account_start_nonce,
-            factories: self.factories.clone(),
-            creators: self.creators.clone(),
-            senders: self.senders.clone(),
-            account_permissions: self.account_permissions.clone(),
-        }
+    pub fn db(self) -> B {
+        self.db
     }
-}
 
-#[cfg(test)]
-mod tests {
-    extern crate libproto;
-    extern crate logger;
-    extern crate rustc_hex;
-    ////////////////////////////////////////////////////////////////////////////////
+    /// Return reference to root
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
 
-    use self::libproto::blockchain;
-    use self::rustc_hex::FromHex;
-    use super::*;
-    use cita_crypto::KeyPair;
-    use env_info::EnvInfo;
-    use std::sync::Arc;
-    use tests::helpers::*;
-    use util::{Address, H256};
-    use util::crypto::CreateKey;
-    use util::hashable::HASH_NAME;
+    /// Root as of the last successful `commit`, or the root the state was opened at.
+    /// Pending changes are ignored, as is a root partly written by a failed commit.
+    pub fn committed_root(&self) -> H256 {
+        self.committed_root
+    }
 
-    #[test]
-    #[ignore]
-    fn should_apply_create_transaction() {
-        /*
-        ~/codes/parity-contract-demo $ cat contracts/AbiTest.sol
-        pragma solidity ^0.4.8;
-        contract AbiTest {
-          uint balance;
-          function AbiTest() {}
-          function setValue(uint value) {
-            balance = value;
-          }
+    /// Create a new contract at address `contract`. If there is already an account at the address
+    /// it will have its code reset, ready for `init_code()`.
+    /// It starts with `default_abi`, if set, which `init_abi` replaces.
+    pub fn new_contract(&mut self, contract: &Address, nonce_offset: U256) {
+        self.note_event(AccountEvent::Created(*contract));
+        let contract = self.map_address(contract);
+        let mut account = Account::new_contract(self.account_start_nonce + nonce_offset);
+        if let Some(ref abi) = self.default_abi {
+            if self.defer_code_hashing {
+                account.init_abi_deferred(abi.clone());
+            } else {
+                account.init_abi(abi.clone());
+            }
         }
-        ~/codes/parity-contract-demo $ solc contracts/AbiTest.sol  --bin-runtime --bin --hash
-        Warning: This is a pre-release compiler version, please do not use it in production.
+        self.insert_cache(&contract, AccountEntry::new_dirty(Some(account)));
+    }
 
-        ======= contracts/AbiTest.sol:AbiTest =======
-        Binary:
-        60606040523415600b57fe5b5b5b5b608e8061001c6000396000f300606060405260003
-        57c0100000000000000000000000000000000000000000000000000000000900463ffff
-        ffff1680635524107714603a575bfe5b3415604157fe5b6055600480803590602001909
-        19050506057565b005b806000819055505b505600a165627a7a7230582079b763be08c2
-        4124c9fa25c78b9d221bdee3e981ca0b2e371628798c41e292ca0029
-        Binary of the runtime part:
-        60606040526000357c01000000000000000000000000000000000000000000000000000
-        00000900463ffffffff1680635524107714603a575bfe5b3415604157fe5b6055600480
-        80359060200190919050506057565b005b806000819055505b505600a165627a7a72305
-        82079b763be08c24124c9fa25c78b9d221bdee3e981ca0b2e371628798c41e292ca0029
-        Function signatures:
-        55241077: setValue(uint256)
-         */
-        logger::silent();
+    /// Address a creation transaction of `sender` applied with `TransactOptions::create_salt`
+    /// set to `salt` deploys code with hash `code_hash` to. The creation fails if that
+    /// address already holds code or a nonce.
+    pub fn predict_contract_address(&self, sender: &Address, salt: H256, code_hash: H256) -> Address {
+        salted_contract_address(sender, &salt, &code_hash)
+    }
 
-        // 1) tx = (to, data(code), nonce, valid_until_block)
-        let mut tx = blockchain::Transaction::new();
-        tx.set_to(String::from(""));
-        let nonce = "haha".to_owned();
-        tx.set_nonce(nonce.clone());
-        let block_limit = 100;
-        tx.set_valid_until_block(block_limit);
-        tx.set_quota(1844673);
-        tx.set_data(
-            "6060604052341561000f57600080fd5b60646000819055507f8fb1356be\
-             6b2a4e49ee94447eb9dcb8783f51c41dcddfe7919f945017d163bf3336064604051808373\
-             ffffffffffffffffffffffffffffffffffffffff1673fffffffffffffffffffffffffffff\
-             fffffffffff1681526020018281526020019250505060405180910390a161017580610092\
-             6000396000f30060606040526000357c01000000000000000000000000000000000000000\
-             00000000000000000900463ffffffff16806360fe47b1146100485780636d4ce63c146100\
-             6b57600080fd5b341561005357600080fd5b6100696004808035906020019091905050610\
-             094565b005b341561007657600080fd5b61007e610140565b604051808281526020019150\
-             5060405180910390f35b7fc6d8c0af6d21f291e7c359603aa97e0ed500f04db6e983b9fce\
-             75a91c6b8da6b816040518082815260200191505060405180910390a1806000819055507f\
-             fd28ec3ec2555238d8ad6f9faf3e4cd10e574ce7e7ef28b73caa53f9512f65b9338260405\
-             1808373ffffffffffffffffffffffffffffffffffffffff1673ffffffffffffffffffffff\
-             ffffffffffffffffff1681526020018281526020019250505060405180910390a150565b6\
-             00080549050905600a165627a7a723058208777d774164b22030e359c5220ad3599f2a294\
-             b4a0ae14b78c4f6a3246525c180029"
-                .from_hex()
-                .unwrap(),
-        );
+    /// Remove an existing account.
+    pub fn kill_account(&mut self, account: &Address) {
+        self.note_event(AccountEvent::Killed(*account));
+        let account = self.map_address(account);
+        self.insert_cache(&account, AccountEntry::new_dirty(None));
+    }
 
-        // 2) stx = (from, content(code, nonce, signature))
-        // TODO: Should get or generate private key that have send transation permission.
-        //       Should work both for ed25519 and secp256k1.
-        let keypair = KeyPair::gen_keypair();
-        let privkey = keypair.privkey();
-        let stx = tx.sign(*privkey);
+    fn note_event(&mut self, event: AccountEvent) {
+        if let Some(ref mut events) = self.account_events {
+            events.push(event);
+        }
+    }
 
-        // 4) signed
-        let mut signed = SignedTransaction::new(&stx).unwrap();
+    /// Remove all `accounts` from the state in a single pass over the cache.
+    /// Fails without removing any if there are more than `max_batch_size`.
+    pub fn kill_accounts(&mut self, accounts: &[Address]) -> Result<(), Error> {
+        self.check_batch_size(accounts.len())?;
+        let accounts: Vec<Address> = accounts.iter().map(|a| self.map_address(a)).collect();
+        let cache = self.cache.get_mut();
+        let mut checkpoint = self.checkpoints.get_mut().last_mut();
+        for address in &accounts {
+            let previous = cache.insert(*address, AccountEntry::new_dirty(None));
+            if let Some(ref mut checkpoint) = checkpoint {
+                checkpoint.entry(*address).or_insert(previous);
+            }
+        }
+        Ok(())
+    }
 
-        // 5)
-        let mut state = get_temp_state();
-        let info = EnvInfo {
-            number: 0,
-            author: Address::default(),
-            timestamp: 0,
-            difficulty: 0.into(),
-            gas_limit: U256::from(u64::max_value()),
-            last_hashes: Arc::new(vec![]),
-            gas_used: 0.into(),
-            account_gas_limit: 1844674.into(),
-        };
-        let contract_address = ::executive::contract_address(&signed.sender(), &U256::from(1));
-        println!("contract_address {:?}", contract_address);
-        let result = state.apply(&info, &mut signed, true, false, false).unwrap();
-        println!(
-            "{:?}",
-            state
-                .code(&contract_address)
-                .expect("result should unwrap.")
-                .expect("option should unwrap")
-        );
-        println!("{:?}", result.trace);
-        assert_eq!(
-            state.code(&contract_address).unwrap().unwrap(),
-            Arc::new(
-                "60606040526000357c010000000000000000000000000000000000000000000000\
-                 0000000000900463ffffffff1680635524107714603a575bfe5b3415604157fe5b\
-                 605560048080359060200190919050506057565b005b806000819055505b505600\
-                 a165627a7a7230582079b763be08c24124c9fa25c78b9d221bdee3e981ca0b2e37\
-                 1628798c41e292ca0029"
-                    .from_hex()
-                    .unwrap()
-            )
-        );
-        assert_eq!(
-            state.abi(&contract_address).unwrap().unwrap(),
-            Arc::new(vec![])
-        );
+    fn check_batch_size(&self, size: usize) -> Result<(), Error> {
+        if size > self.max_batch_size {
+            return Err(Error::BatchTooLarge {
+                size: size,
+                limit: self.max_batch_size,
+            });
+        }
+        Ok(())
     }
 
-    #[test]
-    fn should_work_when_cloned() {
-        // init_log();
+    // TODO: Check it later.
+    /// Determine whether an account exists.
+    pub fn exists(&self, a: &Address) -> trie::Result<bool> {
+        // Bloom filter does not contain empty accounts, so it is important here to
+        // check if account exists in the database directly before EIP-161 is in effect.
+        self.ensure_cached(a, RequireCache::None, false, |a| {
+            a.is_some()
+        })
+    }
 
-        let a = Address::zero();
+    /// Determine whether an account exists and if not empty.
+    pub fn exists_and_not_null(&self, a: &Address) -> trie::Result<bool> {
+        self.ensure_cached(a, RequireCache::None, false, |a| {
+            a.map_or(false, |a| !a.is_null())
+        })
+    }
 
-        let mut state = {
-            let mut state = get_temp_state();
-            assert_eq!(state.exists(&a).unwrap(), false);
-            state.inc_nonce(&a).unwrap();
-            state.commit().unwrap();
-            state.clone()
-        };
+    /// Determine whether an account exists and has code or non-zero nonce.
+    pub fn exists_and_has_code_or_nonce(&self, a: &Address) -> trie::Result<bool> {
+        self.ensure_cached(a, RequireCache::CodeSize, false, |a| {
+            a.map_or(false, |a| {
+                a.code_hash() != HASH_EMPTY || *a.nonce() != self.account_start_nonce
+            })
+        })
+    }
 
-        state.inc_nonce(&a).unwrap();
-        state.commit().unwrap();
+    /// Record `number` as the last touched block of every account mutated from now on.
+    /// Usually set to `EnvInfo.number` before applying the block's transactions.
+    pub fn set_touch_block(&mut self, number: u64) {
+        self.touch_block = Some(number);
     }
 
-    // #[test]
-    // fn should_trace_failed_create_transaction() {
-    //     init_log();
+    /// Get the block number account `a` was last mutated in, if recorded.
+    pub fn last_touched(&self, a: &Address) -> trie::Result<Option<u64>> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref().and_then(|account| account.last_touched())
+        })
+    }
 
-    //     let mut state = get_temp_state();
+    /// Get the nonce of account `a`.
+    pub fn nonce(&self, a: &Address) -> trie::Result<U256> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref()
+                .map_or(self.account_start_nonce, |account| *account.nonce())
+        })
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    /// Get the storage root of account `a`.
+    pub fn storage_root(&self, a: &Address) -> trie::Result<Option<H256>> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref()
+                .and_then(|account| account.storage_root().cloned())
+        })
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Create,
-    //             value: 100.into(),
-    //             data: FromHex::from_hex("5b600056").unwrap(),
-    //         }
-    //         .sign(&secret(), None);
+    // TODO: Add global cache.
+    /// Mutate storage of account `address` so that it is `value` for `key`.
+    pub fn storage_at(&self, address: &Address, key: &H256) -> trie::Result<H256> {
+        // Storage key search and update works like this:
+        // 1. If there's an entry for the account in the local cache check for the key and return it if found.
+        // 2. If there's an entry for the account in the global cache check for the key or load it into that account.
+        // 3. If account is missing in the global cache load it into the local cache and cache the key there.
+        self.note_access(address, false);
+        let address = &self.map_address(address);
 
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Create(trace::Create {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             value: 100.into(),
-    //             gas: 78792.into(),
-    //             init: vec![91, 96, 0, 86],
-    //         }),
-    //         result: trace::Res::FailedCreate(TraceError::OutOfGas),
-    //         subtraces: 0
-    //     }];
+        // check local cache first without updating
+        {
+            let local_cache = self.cache.borrow_mut();
+            let mut local_account = None;
+            if let Some(maybe_acc) = local_cache.get(address) {
+                match maybe_acc.account {
+                    Some(ref account) => {
+                        if let Some(value) = account.cached_storage_at(key) {
+                            return Ok(value);
+                        } else {
+                            local_account = Some(maybe_acc);
+                        }
+                    }
+                    _ => return Ok(H256::new()),
+                }
+            }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+            // otherwise cache the account localy and cache storage key there.
+            if let Some(ref mut acc) = local_account {
+                if let Some(ref account) = acc.account {
+                    let account_db = self.factories
+                        .accountdb
+                        .readonly(self.db.as_hashdb(), account.address_hash(address));
+                    return account.storage_at(&self.factories.trie, account_db.as_hashdb(), key);
+                } else {
+                    return Ok(H256::new());
+                }
+            }
+        }
 
-    // #[test]
-    // fn should_trace_call_transaction() {
-    //     init_log();
+        // TODO: add account bloom. check if the account could exist before any requests to trie
 
-    //     let mut state = get_temp_state();
+        // account is not found in the global cache, get from the DB and insert into local
+        let root = self.read_root();
+        let nodes = self.trie_node_db();
+        let db = self.factories
+            .trie
+            .readonly(&nodes, &root)
+            .expect(SEC_TRIE_DB_UNWRAP_STR);
+        let maybe_acc = db.get_with(address, Account::from_rlp)?;
+        let r = maybe_acc.as_ref().map_or(Ok(H256::new()), |a| {
+            let account_db = self.factories
+                .accountdb
+                .readonly(self.db.as_hashdb(), a.address_hash(address));
+            a.storage_at(&self.factories.trie, account_db.as_hashdb(), key)
+        });
+        self.insert_cache(address, AccountEntry::new_clean(maybe_acc));
+        r
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    /// Follow the storage of account `a` from `start`, reading each value as the next key,
+    /// for debugging contracts that keep linked structures in storage. Returns the visited
+    /// `(key, value)` pairs, ending before a zero value, after `max_steps` pairs, or at the
+    /// first pair whose value points back into the chain, and why the chain ended.
+    pub fn trace_storage_chain(
+        &self,
+        a: &Address,
+        start: H256,
+        max_steps: usize,
+    ) -> trie::Result<(Vec<(H256, H256)>, ChainEnd)> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut key = start;
+        while chain.len() < max_steps {
+            let value = self.storage_at(a, &key)?;
+            if value.is_zero() {
+                return Ok((chain, ChainEnd::Zero));
+            }
+            visited.insert(key);
+            chain.push((key, value));
+            if visited.contains(&value) {
+                return Ok((chain, ChainEnd::Cycle));
+            }
+            key = value;
+        }
+        Ok((chain, ChainEnd::MaxSteps))
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    /// Get code by its hash from the shared code cache or the accounts cached in this state.
+    /// Returns `None` if no code with that hash has been loaded.
+    pub fn code_by_hash(&self, hash: &H256) -> Option<Arc<Bytes>> {
+        if *hash == HASH_EMPTY {
+            return None;
+        }
+        if let Some(code) = self.db.get_cached_code(hash) {
+            return Some(code);
+        }
+        self.cache
+            .borrow()
+            .values()
+            .filter_map(|entry| entry.account.as_ref())
+            .find(|account| account.is_cached() && account.code_hash() == *hash)
+            .and_then(|account| account.code())
+    }
 
-    //     state
-    //         .init_code(&0xa.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(3),
-    //             output: vec![]
-    //         }),
-    //         subtraces: 0,
-    //     }];
+    /// Get accounts' code.
+    pub fn code(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
+        self.ensure_cached(a, RequireCache::Code, true, |a| {
+            a.as_ref().map_or(None, |a| a.code().clone())
+        })
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    /// Get an account's code hash.
+    pub fn code_hash(&self, a: &Address) -> trie::Result<H256> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref().map_or(HASH_EMPTY, |a| a.code_hash())
+        })
+    }
 
-    // #[test]
-    // fn should_trace_basic_call_transaction() {
-    //     init_log();
+    /// Get accounts' code size.
+    pub fn code_size(&self, a: &Address) -> trie::Result<Option<usize>> {
+        self.ensure_cached(a, RequireCache::CodeSize, true, |a| {
+            a.as_ref().and_then(|a| a.code_size())
+        })
+    }
 
-    //     let mut state = get_temp_state();
+    /// Get accounts' ABI.
+    pub fn abi(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
+        self.ensure_cached(a, RequireCache::Abi, true, |a| {
+            a.as_ref().map_or(None, |a| a.abi().clone())
+        })
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    /// Get an account's ABI hash.
+    pub fn abi_hash(&self, a: &Address) -> trie::Result<H256> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref().map_or(HASH_EMPTY, |a| a.abi_hash())
+        })
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    /// Determine whether account `a` has an ABI registered, without loading the ABI.
+    pub fn has_abi(&self, a: &Address) -> trie::Result<bool> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.as_ref().map_or(false, |a| a.abi_hash() != HASH_EMPTY)
+        })
+    }
 
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(0),
-    //             output: vec![]
-    //         }),
-    //         subtraces: 0,
-    //     }];
+    /// Get accounts' ABI size.
+    pub fn abi_size(&self, a: &Address) -> trie::Result<Option<usize>> {
+        self.ensure_cached(a, RequireCache::AbiSize, true, |a| {
+            a.as_ref().and_then(|a| a.abi_size())
+        })
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    /// Hash over the code hash and the abi hash of contract `a`, identifying contracts
+    /// deployed with the same code and abi. `None` if `a` has no code.
+    pub fn contract_identity(&self, a: &Address) -> trie::Result<Option<H256>> {
+        self.ensure_cached(a, RequireCache::None, true, |a| {
+            a.and_then(|account| {
+                let code_hash = account.code_hash();
+                if code_hash == HASH_EMPTY {
+                    return None;
+                }
+                let mut stream = RlpStream::new_list(2);
+                stream.append(&code_hash).append(&account.abi_hash());
+                Some(stream.out().crypt_hash())
+            })
+        })
+    }
 
-    // #[test]
-    // fn should_trace_call_transaction_to_builtin() {
-    //     init_log();
+    /// Increment the nonce of account `a` by 1.
+    pub fn inc_nonce(&mut self, a: &Address) -> trie::Result<()> {
+        self.require(a, false, false).map(|mut x| x.inc_nonce())
+    }
 
-    //     let mut state = get_temp_state();
+    /// Set the nonce of account `a` to `nonce`.
+    pub fn set_nonce(&mut self, a: &Address, nonce: U256) -> trie::Result<()> {
+        self.require(a, false, false).map(|mut x| x.set_nonce(nonce))
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = &*Spec::new_test().engine;
+    /// Reject any further `set_storage` of `key` in the storage of account `a`.
+    /// Reverting the checkpoint open while freezing unfreezes the slot again.
+    pub fn freeze_slot(&mut self, a: &Address, key: H256) {
+        let depth = self.checkpoints.get_mut().len();
+        self.frozen_slots.entry((*a, key)).or_insert(depth);
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0x1.into()),
-    //             value: 0.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    /// Whether contract `a` self-destructed in a transaction applied since the last `commit`.
+    pub fn suicided_within_block(&self, a: &Address) -> bool {
+        self.suicide_within_block.contains(a)
+    }
 
-    //     let result = state.apply(&info, engine, &t, true).unwrap();
+    /// Determine whether `key` in the storage of account `a` is frozen.
+    pub fn is_slot_frozen(&self, a: &Address, key: &H256) -> bool {
+        self.frozen_slots.contains_key(&(*a, *key))
+    }
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: "0000000000000000000000000000000000000001".into(),
-    //             value: 0.into(),
-    //             gas: 79_000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(3000),
-    //             output: vec![]
-    //         }),
-    //         subtraces: 0,
-    //     }];
+    /// Fail with `Error::SlotFrozen` for the first frozen slot of account `a`
+    /// whose value `value` would change.
+    fn check_frozen_slots<F>(&self, a: &Address, value: F) -> Result<(), Error>
+    where
+        F: Fn(&H256) -> trie::Result<H256>,
+    {
+        let keys: Vec<H256> = self.frozen_slots
+            .keys()
+            .filter(|&&(ref address, _)| address == a)
+            .map(|&(_, key)| key)
+            .collect();
+        for key in keys {
+            if value(&key)? != self.storage_at(a, &key)? {
+                return Err(Error::SlotFrozen { address: *a, key: key });
+            }
+        }
+        Ok(())
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    /// Mutate storage of account `a` so that it is `value` for `key`.
+    /// Fails with `Error::SlotFrozen` if the slot is frozen.
+    pub fn set_storage(&mut self, a: &Address, key: H256, value: H256) -> Result<(), Error> {
+        if self.is_slot_frozen(a, &key) {
+            return Err(Error::SlotFrozen { address: *a, key: key });
+        }
+        if self.storage_at(a, &key)? != value {
+            {
+                let mut account = self.require(a, false, false)?;
+                if self.intern_storage_values {
+                    account.enable_storage_interning();
+                }
+                account.set_storage(key, value)
+            }
+            self.note_event(AccountEvent::StorageWritten {
+                address: *a,
+                key: key,
+                value: value,
+            });
+        }
 
-    // #[test]
-    // fn should_not_trace_subcall_transaction_to_builtin() {
-    //     init_log();
+        Ok(())
+    }
 
-    //     let mut state = get_temp_state();
+    /// Whether slot `key` of `a` was read or written by the transaction being applied,
+    /// or listed in `access_list`. Only cold slots cost `cold_sload_gas`.
+    pub fn is_storage_warm(&self, a: &Address, key: &H256) -> bool {
+        self.warm_slots.contains_key(&(*a, *key))
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = &*Spec::new_test().engine;
+    /// Mark slot `key` of `a` as accessed for the rest of the transaction being applied.
+    /// Reverting the checkpoint open while marking makes the slot cold again.
+    pub fn warm_storage(&mut self, a: &Address, key: &H256) {
+        let depth = self.checkpoints.get_mut().len();
+        self.warm_slots.entry((*a, *key)).or_insert(depth);
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 0.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    /// Count an SSTORE of the transaction being applied, returning the SSTOREs counted so far.
+    /// Reverting the checkpoint open while counting drops the SSTORE again.
+    pub fn note_storage_write(&mut self) -> usize {
+        let depth = self.checkpoints.get_mut().len();
+        if self.storage_writes.len() <= depth {
+            self.storage_writes.resize(depth + 1, 0);
+        }
+        self.storage_writes[depth] += 1;
+        self.storage_writes.iter().sum()
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("600060006000600060006001610be0f1").unwrap())
-    //         .unwrap();
-    //     let result = state.apply(&info, engine, &t, true).unwrap();
+    /// Substate for a nested call, emptied and taken from the pool lent by `apply_reuse` if any.
+    pub fn take_substate(&mut self) -> Substate {
+        self.substate_pool
+            .as_mut()
+            .and_then(|pool| pool.pop())
+            .unwrap_or_default()
+    }
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 0.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(28_061),
-    //             output: vec![]
-    //         }),
-    //         subtraces: 0,
-    //     }];
+    /// Return the substate of a finished nested call to the pool lent by `apply_reuse`, if any.
+    pub fn recycle_substate(&mut self, mut substate: Substate) {
+        if let Some(ref mut pool) = self.substate_pool {
+            substate.clear();
+            pool.push(substate);
+        }
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    /// Forget the SSTOREs counted for the previous transaction.
+    pub fn reset_storage_writes(&mut self) {
+        self.storage_writes.clear();
+    }
 
-    // #[test]
-    // fn should_not_trace_callcode() {
-    //     init_log();
+    /// Forget the slots accessed by the previous transaction and pre-warm those of `access_list`,
+    /// which stay warm for the whole transaction.
+    /// Accounts listed without slots have no effect, account accesses are not priced cold.
+    pub fn reset_warm_storage(&mut self) {
+        self.warm_slots.clear();
+        for &(ref address, ref keys) in &self.access_list {
+            self.warm_slots.extend(keys.iter().map(|key| ((*address, *key), 0)));
+        }
+    }
 
-    //     let mut state = get_temp_state();
+    /// Replace the whole storage of account `a` with exactly the entries of `storage`.
+    /// Fails with `Error::SlotFrozen` if the value of a frozen slot would change, and with
+    /// `Error::BatchTooLarge` if there are more than `max_batch_size` entries.
+    pub fn replace_storage(&mut self, a: &Address, storage: HashMap<H256, H256>) -> Result<(), Error> {
+        self.check_batch_size(storage.len())?;
+        self.check_frozen_slots(a, |key| Ok(storage.get(key).cloned().unwrap_or_else(H256::zero)))?;
+        self.require(a, false, false)?.replace_storage(storage);
+        Ok(())
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = &*Spec::new_test().engine;
+    /// Exchange the storage of accounts `a` and `b`, both are marked dirty.
+    /// The storage trie nodes of each account are staged with the other account,
+    /// they are written to its account database on commit and dropped on revert.
+    /// Fails with `Error::SlotFrozen` if the value of a frozen slot of either account would change.
+    pub fn swap_storage(&mut self, a: &Address, b: &Address) -> Result<(), Error> {
+        let (a_mapped, b_mapped) = (self.map_address(a), self.map_address(b));
+        if a_mapped == b_mapped {
+            return Ok(());
+        }
+        self.check_frozen_slots(a, |key| self.storage_at(b, key))?;
+        self.check_frozen_slots(b, |key| self.storage_at(a, key))?;
+        let a_nodes = {
+            let account = self.require(a, false, false)?;
+            self.account_storage_nodes(&a_mapped, &account)?
+        };
+        let b_nodes = {
+            let account = self.require(b, false, false)?;
+            self.account_storage_nodes(&b_mapped, &account)?
+        };
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 0.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+        // both entries were backed up into the checkpoint by `require`
+        let cache = self.cache.get_mut();
+        let mut b_entry = cache.remove(&b_mapped).expect("account required above; qed");
+        {
+            let a_entry = cache.get_mut(&a_mapped).expect("account required above; qed");
+            if let (Some(a_account), Some(b_account)) = (a_entry.account.as_mut(), b_entry.account.as_mut()) {
+                a_account.swap_storage(b_account);
+                a_account.stage_storage_nodes(b_nodes);
+                b_account.stage_storage_nodes(a_nodes);
+            }
+        }
+        cache.insert(b_mapped, b_entry);
+        Ok(())
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006000600b611000f2").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     let result = state.apply(&info, engine, &t, true).unwrap();
+    /// All trie nodes of the storage trie below the base storage root of `account`, staged ones included.
+    fn account_storage_nodes(&self, address: &Address, account: &Account) -> trie::Result<Vec<(H256, Bytes)>> {
+        if *account.base_storage_root() == HASH_NULL_RLP {
+            return Ok(Vec::new());
+        }
+        let account_db = self.factories
+            .accountdb
+            .readonly(self.db.as_hashdb(), account.address_hash(address));
+        trie_nodes(&account.storage_db(account_db.as_hashdb()), account.base_storage_root())
+    }
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 0.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: 64.into(),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xa.into(),
-    //             value: 0.into(),
-    //             gas: 4096.into(),
-    //             input: vec![],
-    //             call_type: CallType::CallCode,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: 3.into(),
-    //             output: vec![],
-    //         }),
-    //     }];
+    /// Remove all storage of account `a`, keeping its nonce, code and abi.
+    /// Fails with `Error::SlotFrozen` if a frozen slot is set.
+    pub fn clear_storage(&mut self, a: &Address) -> Result<(), Error> {
+        self.replace_storage(a, HashMap::new())
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    /// Initialise the code of account `a` so that it is `code`.
+    /// NOTE: Account should have been created with `new_contract`.
+    pub fn init_code(&mut self, a: &Address, code: Bytes) -> trie::Result<()> {
+        {
+            let mut account = self.require_or_from(
+                a,
+                true,
+                false,
+                || Account::new_contract(self.account_start_nonce),
+                |_| {},
+            )?;
+            if self.defer_code_hashing {
+                account.init_code_deferred(code);
+            } else {
+                account.init_code(code);
+            }
+        }
+        self.note_event(AccountEvent::CodeSet(*a));
+        Ok(())
+    }
 
-    // #[test]
-    // fn should_not_trace_delegatecall() {
-    //     init_log();
+    /// Reset the code of account `a` so that it is `code`.
+    pub fn reset_code(&mut self, a: &Address, code: Bytes) -> trie::Result<()> {
+        {
+            let mut account = self.require_or_from(
+                a,
+                true,
+                false,
+                || Account::new_contract(self.account_start_nonce),
+                |_| {},
+            )?;
+            if self.defer_code_hashing {
+                account.init_code_deferred(code);
+            } else {
+                account.reset_code(code);
+            }
+        }
+        self.note_event(AccountEvent::CodeSet(*a));
+        Ok(())
+    }
 
-    //     let mut state = get_temp_state();
+    /// Initialise the ABI of account `a` so that it is `abi`.
+    /// NOTE: Account should have been created with `new_contract`.
+    pub fn init_abi(&mut self, a: &Address, abi: Bytes) -> trie::Result<()> {
+        let mut account = self.require_or_from(
+            a,
+            false,
+            true,
+            || Account::new_contract(self.account_start_nonce),
+            |_| {},
+        )?;
+        if self.defer_code_hashing {
+            account.init_abi_deferred(abi);
+        } else {
+            account.init_abi(abi);
+        }
+        Ok(())
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     info.number = 0x789b0;
-    //     let engine = &*Spec::new_test().engine;
+    /// Reset the abi of account `a` so that it is `abi`.
+    pub fn reset_abi(&mut self, a: &Address, abi: Bytes) -> trie::Result<()> {
+        let mut account = self.require_or_from(
+            a,
+            false,
+            true,
+            || Account::new_contract(self.account_start_nonce),
+            |_| {},
+        )?;
+        if self.defer_code_hashing {
+            account.init_abi_deferred(abi);
+        } else {
+            account.reset_abi(abi);
+        }
+        Ok(())
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 0.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    /// Execute a given transaction.
+    /// This will change the state accordingly.
+    pub fn apply(
+        &mut self,
+        env_info: &EnvInfo,
+        t: &mut SignedTransaction,
+        tracing: bool,
+        check_permission: bool,
+        check_quota: bool,
+    ) -> ApplyResult {
+        let options = TransactOptions {
+            tracing: tracing,
+            vm_tracing: false,
+            check_permission: check_permission,
+            check_quota: check_quota,
+            system_transaction: false,
+            gas_price: None,
+            opcode_profiling: false,
+            account_events: false,
+            state_growth: false,
+            create_salt: None,
+        };
+        self.apply_with_options(env_info, t, options)
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("6000600060006000600b618000f4").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     let result = state.apply(&info, engine, &t, true).unwrap();
+    /// Execute a given transaction like `apply_with_options`, taking its substates and trace
+    /// buffers from `ctx` instead of allocating them anew. The outcome is identical to
+    /// `apply_with_options`.
+    pub fn apply_reuse(
+        &mut self,
+        env_info: &EnvInfo,
+        t: &mut SignedTransaction,
+        options: TransactOptions,
+        ctx: &mut ExecContext,
+    ) -> ApplyResult {
+        self.substate_pool = Some(mem::replace(&mut ctx.substates, Vec::new()));
+        let result = self.apply_in(env_info, t, options, &mut ctx.substate, &ctx.trace_buffers);
+        ctx.substates = self.substate_pool.take().unwrap_or_default();
+        result
+    }
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 0.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(61),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
+    /// Execute a given transaction with explicit `TransactOptions`.
+    /// This will change the state accordingly.
+    ///
+    /// NOTE: `options.system_transaction` skips the sender's nonce increment
+    /// and the permission checks, see `TransactOptions`. Its nonce is then
+    /// handled according to `nonce_policy`.
+    ///
+    /// With `allow_missing_nodes` set, a missing trie node reverts the transaction's
+    /// changes and returns `Error::StateIncomplete` carrying the node hash.
+    pub fn apply_with_options(
+        &mut self,
+        env_info: &EnvInfo,
+        t: &mut SignedTransaction,
+        options: TransactOptions,
+    ) -> ApplyResult {
+        self.apply_in(env_info, t, options, &mut Substate::new(), &TraceBuffers::default())
+    }
+
+    fn apply_in(
+        &mut self,
+        env_info: &EnvInfo,
+        t: &mut SignedTransaction,
+        options: TransactOptions,
+        substate: &mut Substate,
+        trace_buffers: &TraceBuffers,
+    ) -> ApplyResult {
+        if self.check_valid_until_block && t.block_limit < env_info.number {
+            return Err(Error::Execution(ExecutionError::InvalidUntilBlock {
+                valid_until_block: t.block_limit,
+                block_number: env_info.number,
+            }));
+        }
+
+        // `env_info.account_gas_limit` is the quota left to the sender in this block
+        if self.check_quota_budget && options.check_quota && *t.sender() != Address::zero() {
+            let remaining = env_info.account_gas_limit;
+            if remaining.is_zero() || remaining < self.base_quota {
+                return Err(Error::Execution(ExecutionError::InsufficientQuota {
+                    remaining: remaining,
+                    base_quota: self.base_quota,
+                }));
+            }
+        }
+
+        if options.check_permission && !options.system_transaction {
+            if let Action::Call(ref address) = t.action {
+                if self.blacklist.contains(address) {
+                    return Err(Error::Execution(ExecutionError::ContractBlacklisted));
+                }
+            }
+        }
+
+        if self.zero_address_policy == ZeroAddressPolicy::Reject {
+            if let Action::Call(ref address) = t.action {
+                if address.is_zero() {
+                    return Err(Error::Execution(ExecutionError::SendToZeroAddress));
+                }
+            }
+        }
+
+        if self.suicided_call_policy == SuicidedCallPolicy::Reject {
+            if let Action::Call(ref address) = t.action {
+                if self.suicided_within_block(address) {
+                    return Err(Error::Execution(ExecutionError::SuicidedContract(*address)));
+                }
+            }
+        }
+
+        if let (&Action::Create, Some(allowed)) = (&t.action, self.allowed_code_hashes.as_ref()) {
+            if !options.system_transaction && !allowed.contains(&t.data.crypt_hash()) {
+                return Err(Error::Execution(ExecutionError::CodeNotWhitelisted));
+            }
+        }
+
+        let system_nonce = if options.system_transaction && self.nonce_policy != NoncePolicy::Ignore {
+            let expected = self.nonce(t.sender())?;
+            let got = t.nonce
+                .parse::<u64>()
+                .map(U256::from)
+                .map_err(|_| ExecutionError::TransactionMalformed(format!("Invalid nonce {}", t.nonce)))?;
+            let accepted = match self.nonce_policy {
+                NoncePolicy::Strict => got == expected,
+                _ => got >= expected,
+            };
+            if !accepted {
+                return Err(Error::Execution(ExecutionError::InvalidNonce {
+                    expected: expected,
+                    got: got,
+                }));
+            }
+            Some(got)
+        } else {
+            None
+        };
+
+        //        let old = self.to_pod();
+        let engine = self.engine
+            .clone()
+            .unwrap_or_else(|| Arc::new(NullEngine::default()));
+        let vm_factory = self.factories.vm.clone();
+        let native_factory = self.factories.native.clone();
+
+        // Every account touched by the transaction is backed up into this checkpoint.
+        self.checkpoint()?;
+        let mut options = options;
+        options.vm_tracing |= options.opcode_profiling;
+        if options.account_events {
+            self.account_events = Some(Vec::new());
+        }
+        let result = Executive::new(self, env_info, &*engine, &vm_factory, &native_factory)
+            .transact_reusing(t, options, substate, trace_buffers);
+        let account_events = self.account_events.take().unwrap_or_default();
+        if self.allow_missing_nodes {
+            if let Err(ExecutionError::StateIncomplete(missing_node)) = result {
+                // leave the state untouched so the transaction can be retried
+                self.revert_to_checkpoint();
+                return Err(Error::StateIncomplete {
+                    missing_node: missing_node,
+                });
+            }
+        }
+        let state_growth = match result {
+            Ok(_) if options.state_growth => self.checkpoint_state_growth(),
+            _ => Ok(0),
+        };
+        let touched_accounts = self.checkpoints
+            .borrow()
+            .last()
+            .map_or(0, |checkpoint| {
+                checkpoint
+                    .keys()
+                    .filter(|address| *address != t.sender())
+                    .count()
+            });
+        self.discard_checkpoint();
+        let e = result?;
+        let state_growth = state_growth?;
+        self.suicide_within_block.extend(e.suicides.iter().cloned());
+        if let Some(nonce) = system_nonce {
+            self.set_nonce(t.sender(), nonce + U256::one())?;
+        }
+
+        // TODO uncomment once to_pod() works correctly.
+        // trace!("Applied transaction. Diff:\n{}\n", state_diff::diff_pod(&old, &self.to_pod()));
+        let receipt_error = e.exception.and_then(|evm_error| match evm_error {
+            EvmError::OutOfGas => Some(ReceiptError::OutOfGas),
+            EvmError::BadJumpDestination { .. } => Some(ReceiptError::BadJumpDestination),
+            EvmError::BadInstruction { .. } => Some(ReceiptError::BadInstruction),
+            EvmError::StackUnderflow { .. } => Some(ReceiptError::StackUnderflow),
+            EvmError::OutOfStack { .. } => Some(ReceiptError::OutOfStack),
+            EvmError::MutableCallInStaticContext => Some(ReceiptError::MutableCallInStaticContext),
+            EvmError::Internal(_) => Some(ReceiptError::Internal),
+            EvmError::OutOfBounds => Some(ReceiptError::OutOfBounds),
+            EvmError::Reverted => Some(ReceiptError::Reverted),
+            EvmError::TooManyStorageWrites { .. } => Some(ReceiptError::TooManyStorageWrites),
+            EvmError::CodeNotWhitelisted => Some(ReceiptError::CodeNotWhitelisted),
+            EvmError::SuicidedContract => Some(ReceiptError::SuicidedContract),
+        });
+        let receipt = Receipt::new(
+            None,
+            e.cumulative_gas_used,
+            e.logs,
+            receipt_error,
+            t.account_nonce().clone(),
+        );
+        trace!(target: "state", "Transaction receipt: {:?}", receipt);
+        // the executive records the nonce the contract address is derived from
+        let contract_address = match t.action {
+            Action::Create if receipt.error.is_none() => Some(match options.create_salt {
+                Some(ref salt) => salted_contract_address(t.sender(), salt, &t.data.crypt_hash()),
+                None => contract_address(t.sender(), t.account_nonce()),
+            }),
+            _ => None,
+        };
+        Ok(ApplyOutcome {
+            receipt: receipt,
+            trace: e.trace,
+            contracts_created: e.contracts_created,
+            contract_address: contract_address,
+            touched_accounts: touched_accounts,
+            storage_gas: e.storage_gas,
+            opcode_profile: if options.opcode_profiling {
+                // transactions that run no code have no VM trace
+                Some(e.vm_trace.as_ref().map_or_else(OpcodeProfile::default, OpcodeProfile::from_vm_trace))
+            } else {
+                None
+            },
+            account_events: account_events,
+            state_growth: state_growth,
+        })
+    }
+
+    /// Execute a given transaction like `apply`, additionally returning
+    /// the logs it emitted for bloom indexing.
+    /// The logs are moved out of the receipt, which keeps its `log_bloom`
+    /// but is left with empty `logs`.
+    pub fn apply_collect_logs(
+        &mut self,
+        env_info: &EnvInfo,
+        t: &mut SignedTransaction,
+        tracing: bool,
+        check_permission: bool,
+        check_quota: bool,
+    ) -> Result<(ApplyOutcome, Vec<LogEntry>), Error> {
+        let mut outcome = self.apply(env_info, t, tracing, check_permission, check_quota)?;
+        let logs = mem::replace(&mut outcome.receipt.logs, Vec::new());
+        Ok((outcome, logs))
+    }
+
+    /// Commit the storage of the dirty accounts on up to `threads` threads.
+    /// Each thread writes the storage tries of its accounts to an own overlay,
+    /// the overlays are then written to `db` one after the other.
+    fn commit_storage_parallel(
+        factories: &Factories,
+        db: &mut HashDB,
+        accounts: &mut HashMap<Address, AccountEntry>,
+        threads: usize,
+    ) -> Result<(), Error> {
+        let mut pending = Vec::new();
+        for (address, entry) in accounts.iter_mut() {
+            if !entry.is_dirty() {
+                continue;
+            }
+            if let Some(ref mut account) = entry.account {
+                if account.dirty_fields().contains(DirtyFields::STORAGE) {
+                    pending.push((account.address_hash(address), account));
+                }
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let chunk_size = (pending.len() + threads - 1) / threads;
+        let overlays: Vec<trie::Result<MemoryDB>> = {
+            let base: &HashDB = db;
+            let trie_factory = &factories.trie;
+            let accountdb_factory = &factories.accountdb;
+            crossbeam::scope(|scope| {
+                let handles: Vec<_> = pending
+                    .chunks_mut(chunk_size)
+                    .map(|chunk| {
+                        scope.spawn(move || -> trie::Result<MemoryDB> {
+                            let mut overlay = JournalDB::new(base);
+                            for &mut (address_hash, ref mut account) in chunk.iter_mut() {
+                                let mut account_db = accountdb_factory.create(&mut overlay, address_hash);
+                                account.commit_storage(trie_factory, account_db.as_hashdb_mut())?;
+                            }
+                            Ok(overlay.overlay)
+                        })
+                    })
+                    .collect();
+                handles.into_iter().map(|handle| handle.join()).collect()
+            })
+        };
+        for overlay in overlays {
+            for (key, (value, rc)) in overlay?.drain() {
+                for _ in 0..rc {
+                    db.emplace(key, value.clone());
+                }
+                for _ in rc..0 {
+                    db.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit accounts to SecTrieDBMut. This is similar to cpp-ethereum's dev::eth::commit.
+    /// `accounts` is mutable because we may need to commit the code or storage and record that.
+    #[cfg_attr(feature = "dev", allow(match_ref_pats))]
+    #[cfg_attr(feature = "dev", allow(needless_borrow))]
+    fn commit_into(
+        factories: &Factories,
+        db: &mut HashDB,
+        root: &mut H256,
+        accounts: &mut HashMap<Address, AccountEntry>,
+        mut events: Option<&mut Vec<CommitEvent>>,
+        blob_store: Option<&BlobStore>,
+        prune_killed_storage: bool,
+        commit_threads: usize,
+    ) -> Result<(), Error> {
+        // first, commit the sub trees.
+        for (address, ref mut a) in accounts.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
+            if let Some(ref mut account) = a.account {
+                if let Some(ref mut events) = events {
+                    for (key, value) in account.storage_changes() {
+                        events.push(CommitEvent::StorageChanged {
+                            address: *address,
+                            key: *key,
+                            value: *value,
+                        });
+                    }
+                }
+                // only the nonce changed: the account RLP below is all there is to write.
+                let dirty = account.dirty_fields();
+                if !dirty.contains(DirtyFields::STORAGE) && !dirty.contains(DirtyFields::CODE)
+                    && !dirty.contains(DirtyFields::ABI)
+                {
+                    continue;
+                }
+                let addr_hash = account.address_hash(address);
+                {
+                    let mut account_db = factories.accountdb.create(db, addr_hash);
+                    if dirty.contains(DirtyFields::STORAGE) && commit_threads <= 1 {
+                        account.commit_storage(&factories.trie, account_db.as_hashdb_mut())?;
+                    }
+
+                    match blob_store {
+                        Some(store) => {
+                            let mut blob_db = BlobDB::new(store);
+                            if dirty.contains(DirtyFields::CODE) {
+                                account.commit_code(&mut blob_db);
+                            }
+                            if dirty.contains(DirtyFields::ABI) {
+                                account.commit_abi(&mut blob_db);
+                            }
+                        }
+                        None => {
+                            if dirty.contains(DirtyFields::CODE) {
+                                account.commit_code(account_db.as_hashdb_mut());
+                            }
+                            if dirty.contains(DirtyFields::ABI) {
+                                account.commit_abi(account_db.as_hashdb_mut());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if commit_threads > 1 {
+            Self::commit_storage_parallel(factories, db, accounts, commit_threads)?;
+        }
+
+        let mut killed = Vec::new();
+        {
+            let mut trie = factories.trie.from_existing(db, root)?;
+            for (address, ref mut a) in accounts.iter_mut().filter(|&(_, ref a)| a.is_dirty()) {
+                a.state = AccountState::Committed;
+                let event = match a.account {
+                    Some(ref mut account) => {
+                        account.clear_dirty_fields();
+                        let rlp = account.rlp();
+                        match trie.insert(address, &rlp)? {
+                            None => Some(CommitEvent::AccountCreated(*address)),
+                            Some(ref old) if &old[..] != &rlp[..] => Some(CommitEvent::AccountUpdated(*address)),
+                            Some(_) => None,
+                        }
+                    }
+                    None => {
+                        let old = trie.remove(address)?;
+                        if let (true, Some(old)) = (prune_killed_storage, old.as_ref()) {
+                            killed.push((*address, Account::from_rlp(old)));
+                        }
+                        old.map(|_| CommitEvent::AccountDeleted(*address))
+                    }
+                };
+                if let (Some(ref mut events), Some(event)) = (events.as_mut(), event) {
+                    events.push(event);
+                }
+            }
+        }
+
+        for (address, account) in killed {
+            if *account.base_storage_root() == HASH_NULL_RLP {
+                continue;
+            }
+            let mut account_db = factories.accountdb.create(db, account.address_hash(&address));
+            let nodes = trie_nodes(account_db.as_hashdb(), account.base_storage_root())?;
+            for (hash, _) in nodes {
+                account_db.remove(&hash);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Commits our cached account changes into the trie.
+    /// Closes the block for `suicided_call_policy`.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        assert!(self.checkpoints.borrow().is_empty());
+        let mut accounts = mem::replace(self.cache.get_mut(), HashMap::new());
+        let result = self.commit_entries(&mut accounts);
+        *self.cache.get_mut() = accounts;
+        // a commit closes the block
+        self.suicide_within_block.clear();
+        result
+    }
+
+    /// Debug and test utility: commit, then commit again without changes and fail with
+    /// `Error::CommitNotIdempotent` if the second commit moved the root or wrote to the database.
+    /// Compares every key of the backing database, so it is far too slow for production use.
+    pub fn assert_commit_idempotent(&mut self) -> Result<(), Error> {
+        self.commit()?;
+        let root = self.root;
+        let before = self.db.as_hashdb().keys();
+        self.commit()?;
+        let after = self.db.as_hashdb().keys();
+        let node_writes = after.iter().filter(|&(key, rc)| before.get(key) != Some(rc)).count()
+            + before.keys().filter(|key| !after.contains_key(key)).count();
+        if self.root != root || node_writes != 0 {
+            return Err(Error::CommitNotIdempotent {
+                root: root,
+                recommitted_root: self.root,
+                node_writes: node_writes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Commits the changes of the dirty accounts among `addrs` into the trie.
+    /// The other dirty accounts stay pending for a later commit.
+    /// Fails with `Error::BatchTooLarge` if there are more than `max_batch_size` addresses.
+    pub fn commit_accounts(&mut self, addrs: &[Address]) -> Result<(), Error> {
+        assert!(self.checkpoints.borrow().is_empty());
+        self.check_batch_size(addrs.len())?;
+        let addrs: Vec<_> = addrs.iter().map(|a| self.map_address(a)).collect();
+        let mut accounts = HashMap::new();
+        {
+            let cache = self.cache.get_mut();
+            for address in addrs {
+                if cache.get(&address).map_or(false, AccountEntry::is_dirty) {
+                    let entry = cache.remove(&address).expect("entry checked above; qed");
+                    accounts.insert(address, entry);
+                }
+            }
+        }
+        let result = self.commit_entries(&mut accounts);
+        self.cache.get_mut().extend(accounts);
+        result
+    }
+
+    fn commit_entries(&mut self, accounts: &mut HashMap<Address, AccountEntry>) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnlyState);
+        }
+        self.trie_nodes
+            .get_mut()
+            .expect("trie node cache lock poisoned")
+            .clear();
+        if let Some(ref bloom) = self.account_bloom {
+            let mut bloom = bloom.lock().expect("account bloom lock poisoned");
+            for (address, entry) in accounts.iter() {
+                if entry.is_dirty() && entry.account.is_some() {
+                    bloom.set(&*address.crypt_hash());
+                }
+            }
+        }
+        let mut events = Vec::new();
+        Self::commit_into(
+            &self.factories,
+            self.db.as_hashdb_mut(),
+            &mut self.root,
+            accounts,
+            if self.commit_sink.is_some() {
+                Some(&mut events)
+            } else {
+                None
+            },
+            self.blob_store.as_ref().map(|store| &**store),
+            self.prune_killed_storage,
+            self.commit_threads,
+        )?;
+        self.committed_root = self.root;
+        self.db.note_root(self.root);
+        if let Some(ref sink) = self.commit_sink {
+            events.sort();
+            for event in events {
+                // a disconnected receiver is not an error for the state
+                let _ = sink.send(event);
+            }
+        }
+        Ok(())
+    }
+
+    /// Operations `commit` performs, in order, without performing them.
+    /// Storage, code and ABI of all accounts are written before the state trie is updated,
+    /// each phase in address order.
+    pub fn commit_plan(&self) -> Vec<CommitStep> {
+        let cache = self.cache.borrow();
+        let mut dirty: Vec<_> = cache.iter().filter(|&(_, entry)| entry.is_dirty()).collect();
+        dirty.sort_by_key(|&(address, _)| address);
+
+        let mut plan = Vec::new();
+        for &(address, entry) in &dirty {
+            if let Some(ref account) = entry.account {
+                let fields = account.dirty_fields();
+                if fields.contains(DirtyFields::STORAGE) {
+                    let mut changes: Vec<_> = account
+                        .storage_changes()
+                        .map(|(key, value)| (*key, *value))
+                        .collect();
+                    changes.sort();
+                    plan.push(CommitStep::CommitStorage {
+                        address: *address,
+                        changes: changes,
+                    });
+                }
+                if fields.contains(DirtyFields::CODE) {
+                    plan.push(CommitStep::CommitCode(*address));
+                }
+                if fields.contains(DirtyFields::ABI) {
+                    plan.push(CommitStep::CommitAbi(*address));
+                }
+            }
+        }
+        for &(address, entry) in &dirty {
+            plan.push(match entry.account {
+                Some(_) => CommitStep::InsertAccount(*address),
+                None => CommitStep::RemoveAccount(*address),
+            });
+        }
+        plan
+    }
+
+    /// Root and node writes `commit` would produce, without touching the backend or the cache.
+    pub fn simulate_commit(&self) -> Result<(H256, CommitJournal), Error> {
+        let mut accounts: HashMap<Address, AccountEntry> = self.cache
+            .borrow()
+            .iter()
+            .filter(|&(_, entry)| entry.is_dirty())
+            .map(|(address, entry)| (*address, entry.clone_dirty()))
+            .collect();
+        let mut root = self.root;
+        let mut db = JournalDB::new(self.db.as_hashdb());
+        Self::commit_into(
+            &self.factories,
+            &mut db,
+            &mut root,
+            &mut accounts,
+            None,
+            self.blob_store.as_ref().map(|_| &DiscardBlobStore as &BlobStore),
+            self.prune_killed_storage,
+            self.commit_threads,
+        )?;
+        Ok((root, db.into_journal()))
+    }
+
+    /// Root the account trie would have after setting each account of `changes` to the given
+    /// RLP, `None` removing it, computed in a scratch trie at the current root. Neither the
+    /// backend nor the cache is touched, and uncommitted changes in the cache are ignored.
+    /// The RLP is inserted as is: storage, code and abi it refers to are not checked.
+    /// Fails with `Error::BatchTooLarge` if there are more than `max_batch_size` changes.
+    pub fn incremental_root(&self, changes: &[(Address, Option<Bytes>)]) -> Result<H256, Error> {
+        self.check_batch_size(changes.len())?;
+        let mut root = self.root;
+        let mut db = JournalDB::new(self.db.as_hashdb());
+        {
+            let mut trie = self.factories.trie.from_existing(&mut db, &mut root)?;
+            for &(ref address, ref rlp) in changes {
+                let address = self.map_address(address);
+                match *rlp {
+                    Some(ref rlp) => {
+                        trie.insert(&address, rlp)?;
+                    }
+                    None => {
+                        trie.remove(&address)?;
+                    }
+                }
+            }
+        }
+        Ok(root)
+    }
+
+    /// Apply the transactions read from `input` in order, for replaying a recorded transaction log.
+    /// Each transaction is its RLP prefixed with its length as a big endian `u32`; the stream
+    /// ends cleanly between two transactions. Returns the result of every transaction, a
+    /// truncated or malformed entry, or one longer than 4 MiB, fails the whole replay with its
+    /// byte offset.
+    pub fn apply_stream<R: Read>(
+        &mut self,
+        env_info: &EnvInfo,
+        input: &mut R,
+        options: TransactOptions,
+    ) -> Result<Vec<ApplyResult>, Error> {
+        let mut results = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut prefix = [0u8; 4];
+            match read_stream_entry(input, &mut prefix)? {
+                0 => break,
+                4 => {}
+                _ => return Err(UtilError::from(format!("truncated transaction length at offset {}", offset)).into()),
+            }
+            let len = BigEndian::read_u32(&prefix) as usize;
+            if len > MAX_STREAM_TRANSACTION_SIZE {
+                return Err(UtilError::from(format!(
+                    "malformed transaction at offset {}: length {} exceeds {}",
+                    offset, len, MAX_STREAM_TRANSACTION_SIZE
+                )).into());
+            }
+            let mut bytes = vec![0u8; len];
+            if read_stream_entry(input, &mut bytes)? != len {
+                return Err(UtilError::from(format!("truncated transaction at offset {}", offset)).into());
+            }
+            let mut t: SignedTransaction = UntrustedRlp::new(&bytes)
+                .as_val()
+                .map_err(|err| UtilError::from(format!("malformed transaction at offset {}: {}", offset, err)))?;
+            results.push(self.apply_with_options(env_info, &mut t, options));
+            offset += prefix.len() + len;
+        }
+        Ok(results)
+    }
+
+    /// Write the clean cache entries to `out`, so a restarted process can `import_cache` them.
+    /// Dirty entries are skipped, they are not backed by the trie.
+    pub fn export_cache<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        let cache = self.cache.borrow();
+        let mut entries: Vec<_> = cache.iter().filter(|&(_, entry)| !entry.is_dirty()).collect();
+        entries.sort_by_key(|&(address, _)| address);
+
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&self.root);
+        stream.begin_list(entries.len());
+        for (address, entry) in entries {
+            stream.begin_list(2);
+            stream.append(address);
+            match entry.account {
+                Some(ref account) => stream.append(&account.rlp()),
+                None => stream.append_empty_data(),
+            };
+        }
+        out.write_all(&stream.out())?;
+        Ok(())
+    }
+
+    /// Pre-warm the cache with entries written by `export_cache` at the current root.
+    /// Addresses already cached are kept. Returns the number of entries imported.
+    pub fn import_cache<R: Read>(&mut self, input: &mut R) -> Result<usize, Error> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let rlp = UntrustedRlp::new(&bytes);
+        let root: H256 = rlp.val_at(0)?;
+        if root != self.root {
+            return Err(UtilError::from(format!("cache bundle is for root {}, state is at {}", root, self.root)).into());
+        }
+
+        let mut imported = 0;
+        for item in rlp.at(1)?.iter() {
+            let address: Address = item.val_at(0)?;
+            if self.cache.borrow().contains_key(&address) {
+                continue;
+            }
+            let data: Bytes = item.val_at(1)?;
+            let account = if data.is_empty() {
+                None
+            } else {
+                Some(Account::from(UntrustedRlp::new(&data).as_val::<BasicAccount>()?))
+            };
+            self.insert_cache(&address, AccountEntry::new_clean(account));
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// Clear state cache and the account access counts
+    pub fn clear(&mut self) {
+        self.cache.borrow_mut().clear();
+        self.access_counts.borrow_mut().clear();
+    }
+
+    /// Whether `sender` may deploy contracts, checked the way `apply` does with
+    /// `check_permission` set: the zero address, `creators` and accounts granted the
+    /// create resource may.
+    /// NOTE: An empty `creators` does not open deployment to everyone.
+    pub fn can_create(&self, sender: &Address) -> bool {
+        let create_contract_cont = Address::from(0x2);
+        let create_contract_func = vec![0; 4];
+        *sender == Address::zero() || self.creators.contains(sender)
+            || contains_resource(
+                &self.account_permissions,
+                sender,
+                create_contract_cont,
+                create_contract_func,
+            )
+    }
+
+    /// Whether `sender` may send transactions, checked the way `apply` does with
+    /// `check_permission` set: the zero address, `senders`, `creators` and accounts
+    /// granted the send resource may.
+    /// Calls additionally need the permission for the called function.
+    /// NOTE: An empty `senders` does not open sending to everyone.
+    pub fn can_send(&self, sender: &Address) -> bool {
+        let send_tx_cont = Address::from(0x1);
+        let send_tx_func = vec![0; 4];
+        *sender == Address::zero() || self.senders.contains(sender) || self.creators.contains(sender)
+            || contains_resource(&self.account_permissions, sender, send_tx_cont, send_tx_func)
+    }
+
+    /// Replace `senders`, `creators` and `account_permissions` with the authorizations
+    /// held in the storage of the authorization contract at `permission_contract`.
+    ///
+    /// The storage layout of `authorization.sol` and `permission.sol` is assumed:
+    /// accounts are listed in `address[] all_accounts` at slot 15, the permissions of
+    /// an account in `mapping(address => address[]) permissions` at slot 13, and the
+    /// resources of a permission contract in `Resource[] resources` at its slot 1,
+    /// each element packing the 4-byte `func` right above the 20-byte `cont`.
+    /// Holders of the send transaction permission at `0x1` become `senders`,
+    /// holders of the create contract permission at `0x2` become `creators`.
+    /// Fails without changing the permissions if an array is longer than 65536 elements.
+    pub fn load_permissions_from_contract(&mut self, permission_contract: &Address) -> Result<(), Error> {
+        let send_tx_permission = Address::from(0x1);
+        let create_contract_permission = Address::from(0x2);
+        let mut senders = HashSet::new();
+        let mut creators = HashSet::new();
+        let mut account_permissions = HashMap::new();
+
+        let all_accounts = self.storage_array(permission_contract, &H256::from(AUTHORIZATION_ALL_ACCOUNTS_SLOT))?;
+        for account in all_accounts.iter().map(|word| Address::from(*word)) {
+            let permissions_slot = mapping_slot(&account, AUTHORIZATION_PERMISSIONS_SLOT);
+            let mut resources = Vec::new();
+            for permission in self.storage_array(permission_contract, &permissions_slot)? {
+                let permission = Address::from(permission);
+                if permission == send_tx_permission {
+                    senders.insert(account);
+                }
+                if permission == create_contract_permission {
+                    creators.insert(account);
+                }
+                for word in self.storage_array(&permission, &H256::from(PERMISSION_RESOURCES_SLOT))? {
+                    resources.push(Resource::new(Address::from(word), word[8..12].to_vec()));
+                }
+            }
+            account_permissions.insert(account, resources);
+        }
+
+        self.senders = senders;
+        self.creators = creators;
+        self.account_permissions = account_permissions;
+        Ok(())
+    }
+
+    /// Elements of the dynamic array at storage `slot` of `contract`, one word each.
+    fn storage_array(&self, contract: &Address, slot: &H256) -> Result<Vec<H256>, Error> {
+        let len = U256::from(&*self.storage_at(contract, slot)?);
+        if len > U256::from(MAX_PERMISSION_ARRAY_LEN) {
+            return Err(UtilError::from(format!(
+                "array at slot {:?} of {:?} has {} elements, more than {}",
+                slot, contract, len, MAX_PERMISSION_ARRAY_LEN
+            )).into());
+        }
+        let len = len.low_u64();
+        let first = U256::from(&*sha3(&slot[..]));
+        (0..len)
+            .map(|index| {
+                let (element, _) = first.overflowing_add(U256::from(index));
+                Ok(self.storage_at(contract, &H256::from(element))?)
+            })
+            .collect()
+    }
+
+    /// Commitment over `senders`, `creators` and `account_permissions`.
+    /// Independent of insertion order, so nodes holding the same permissions agree on it.
+    pub fn permissions_root(&self) -> H256 {
+        let mut senders: Vec<_> = self.senders.iter().collect();
+        senders.sort();
+        let mut creators: Vec<_> = self.creators.iter().collect();
+        creators.sort();
+        let mut accounts: Vec<_> = self.account_permissions.iter().collect();
+        accounts.sort_by_key(|&(address, _)| address);
+
+        let mut stream = RlpStream::new_list(3);
+        stream.begin_list(senders.len());
+        for address in senders {
+            stream.append(address);
+        }
+        stream.begin_list(creators.len());
+        for address in creators {
+            stream.append(address);
+        }
+        stream.begin_list(accounts.len());
+        for (address, resources) in accounts {
+            let mut resources: Vec<_> = resources.iter().map(|r| (r.cont, &r.func)).collect();
+            resources.sort();
+            stream.begin_list(2).append(address).begin_list(resources.len());
+            for (cont, func) in resources {
+                stream.begin_list(2).append(&cont).append(func);
+            }
+        }
+        stream.out().crypt_hash()
+    }
+
+    /// Changes of `senders`, `creators` and `account_permissions` in this state relative to `other`.
+    pub fn permissions_diff(&self, other: &State<B>) -> PermissionDiff {
+        fn sorted_difference(a: &HashSet<Address>, b: &HashSet<Address>) -> Vec<Address> {
+            let mut difference: Vec<_> = a.difference(b).cloned().collect();
+            difference.sort();
+            difference
+        }
+        // resources of each account in `a` that its entry in `b` lacks
+        fn missing_resources(
+            a: &HashMap<Address, Vec<Resource>>,
+            b: &HashMap<Address, Vec<Resource>>,
+        ) -> BTreeMap<Address, Vec<Resource>> {
+            let none = Vec::new();
+            a.iter()
+                .filter_map(|(account, resources)| {
+                    let others = b.get(account).unwrap_or(&none);
+                    let missing: Vec<_> = resources.iter().filter(|r| !others.contains(r)).cloned().collect();
+                    if missing.is_empty() {
+                        None
+                    } else {
+                        Some((*account, missing))
+                    }
+                })
+                .collect()
+        }
+
+        PermissionDiff {
+            added_senders: sorted_difference(&self.senders, &other.senders),
+            removed_senders: sorted_difference(&other.senders, &self.senders),
+            added_creators: sorted_difference(&self.creators, &other.creators),
+            removed_creators: sorted_difference(&other.creators, &self.creators),
+            added_resources: missing_resources(&self.account_permissions, &other.account_permissions),
+            removed_resources: missing_resources(&other.account_permissions, &self.account_permissions),
+        }
+    }
+
+    /// Digest of the uncommitted account changes, independent of the order they were made in.
+    /// States based on the same root with equal fingerprints commit to the same root.
+    pub fn cache_fingerprint(&self) -> H256 {
+        let cache = self.cache.borrow();
+        let mut dirty: Vec<_> = cache.iter().filter(|&(_, entry)| entry.is_dirty()).collect();
+        dirty.sort_by_key(|&(address, _)| address);
+
+        let mut stream = RlpStream::new_list(dirty.len());
+        for (address, entry) in dirty {
+            stream.begin_list(2).append(address);
+            match entry.account {
+                Some(ref account) => {
+                    let mut storage: Vec<_> = account.storage_changes().collect();
+                    storage.sort_by_key(|&(key, _)| key);
+                    stream
+                        .begin_list(4)
+                        .append(account.nonce())
+                        .append(&account.code_hash())
+                        .append(&account.abi_hash())
+                        .begin_list(storage.len());
+                    for (key, value) in storage {
+                        stream.begin_list(2).append(key).append(value);
+                    }
+                }
+                // killed account
+                None => {
+                    stream.begin_list(0);
+                }
+            }
+        }
+        stream.out().crypt_hash()
+    }
+
+    /// Account-DB reads per `RequireCache` level since the state was created.
+    /// Only collected while `collect_metrics` is set.
+    pub fn require_stats(&self) -> RequireStats {
+        self.require_stats.get()
+    }
+
+    /// Reads and writes of every accessed account since the last `clear`, as `(reads, writes)`.
+    /// Only counted while `meter_account_access` is set.
+    pub fn account_access_counts(&self) -> HashMap<Address, (u64, u64)> {
+        self.access_counts.borrow().clone()
+    }
+
+    fn note_access(&self, a: &Address, write: bool) {
+        if self.meter_account_access {
+            let mut counts = self.access_counts.borrow_mut();
+            let count = counts.entry(*a).or_insert((0, 0));
+            if write {
+                count.1 += 1;
+            } else {
+                count.0 += 1;
+            }
+        }
+    }
+
+    /// Number of account entries held in the local cache.
+    pub fn cache_len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    /// Estimated memory footprint of the local cache in bytes.
+    /// Batch executors may use this to decide when to `commit` and `clear`.
+    pub fn cache_memory_estimate(&self) -> usize {
+        self.cache
+            .borrow()
+            .values()
+            .map(|entry| {
+                mem::size_of::<AccountEntry>()
+                    + entry
+                        .account
+                        .as_ref()
+                        .map_or(0, Account::cache_memory_estimate)
+            })
+            .sum()
+    }
+
+    // TODO
+    // load required account data from the databases.
+    fn update_account_cache(
+        &self,
+        require: RequireCache,
+        account: &mut Account,
+        //state_db: &B,
+        db: &HashDB,
+    ) {
+        // code and abi live in the blob store if one is configured
+        let blob_db = self.blob_store.as_ref().map(|store| BlobDB::new(&**store));
+        let db = match blob_db {
+            Some(ref blob_db) => blob_db as &HashDB,
+            None => db,
+        };
+        match (account.is_cached(), require) {
+            (false, RequireCache::Code) | (false, RequireCache::CodeSize) | (false, RequireCache::CodeAndAbi) => {
+                // if there's already code in the global cache, always cache it
+                // locally.
+                let hash = account.code_hash();
+                match self.db.get_cached_code(&hash) {
+                    Some(code) => account.cache_given_code(code),
+                    None => {
+                        if let Some(code) = account.cache_code(db) {
+                            // propagate code loaded from the database to
+                            // the global code cache.
+                            self.db.cache_code(hash, code);
+                        }
+                        if self.collect_metrics {
+                            let mut stats = self.require_stats.get();
+                            match require {
+                                RequireCache::CodeSize => stats.code_size += 1,
+                                _ => stats.code += 1,
+                            }
+                            self.require_stats.set(stats);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        };
+
+        match (account.is_abi_cached(), require) {
+            (false, RequireCache::Abi) | (false, RequireCache::AbiSize) | (false, RequireCache::CodeAndAbi) => {
+                account.cache_abi(db);
+                if self.collect_metrics {
+                    let mut stats = self.require_stats.get();
+                    match require {
+                        RequireCache::AbiSize => stats.abi_size += 1,
+                        _ => stats.abi += 1,
+                    }
+                    self.require_stats.set(stats);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Check caches for required data
+    /// First searches for account in the local, then the shared cache.
+    /// Populates local cache if nothing found.
+    fn ensure_cached<F, U>(&self, a: &Address, require: RequireCache, _: bool, f: F) -> trie::Result<U>
+    where
+        F: Fn(Option<&Account>) -> U,
+    {
+        self.note_access(a, false);
+        let a = &self.map_address(a);
+        // check local cache first
+        if let Some(ref mut maybe_acc) = self.cache.borrow_mut().get_mut(a) {
+            if let Some(ref mut account) = maybe_acc.account {
+                let accountdb = self.factories
+                    .accountdb
+                    .readonly(self.db.as_hashdb(), account.address_hash(a));
+                self.update_account_cache(require, account, /* &self.db, */ accountdb.as_hashdb());
+                return Ok(f(Some(account)));
+            }
+            return Ok(f(None));
+        }
+        // TODO: check global cache
+
+        // first check if it is not in database for sure
+
+        // not found in the global cache, get from the DB and insert into local
+        let root = self.read_root();
+        let nodes = self.trie_node_db();
+        let db = self.factories.trie.readonly(&nodes, &root)?;
+        let mut maybe_acc = db.get_with(a, Account::from_rlp)?;
+        if let Some(ref mut account) = maybe_acc.as_mut() {
+            let accountdb = self.factories
+                .accountdb
+                .readonly(self.db.as_hashdb(), account.address_hash(a));
+            self.update_account_cache(require, account, /* &self.db, */ accountdb.as_hashdb());
+        }
+        let r = f(maybe_acc.as_ref());
+        self.insert_cache(a, AccountEntry::new_clean(maybe_acc));
+        Ok(r)
+    }
+
+    /// Pull account `a` in our cache from the trie DB.
+    /// `require_code` requires that the code be cached, too.
+    /// `require_abi` requires that the abi be cached, too.
+    fn require<'a>(&'a self, a: &Address, require_code: bool, require_abi: bool) -> trie::Result<RefMut<'a, Account>> {
+        self.require_or_from(
+            a,
+            require_code,
+            require_abi,
+            || Account::new_basic(self.account_start_nonce),
+            |_| {},
+        )
+    }
+
+    /// Pull account `a` in our cache from the trie DB.
+    /// `require_code` requires that the code be cached, too.
+    /// `require_abi` requires that the abi be cached, too.
+    /// If it doesn't exist, make account equal the evaluation of `default`.
+    fn require_or_from<'a, F, G>(
+        &'a self,
+        a: &Address,
+        require_code: bool,
+        require_abi: bool,
+        default: F,
+        not_default: G,
+    ) -> trie::Result<RefMut<'a, Account>>
+    where
+        F: FnOnce() -> Account,
+        G: FnOnce(&mut Account),
+    {
+        self.try_require_or_from(a, require_code, require_abi, default, not_default)
+            .map_err(|err| {
+                Box::new(match err {
+                    RequireError::MissingStateRoot(root) => TrieError::InvalidStateRoot(root),
+                    RequireError::TrieRead(err) => err,
+                })
+            })
+    }
+
+    /// Pull account `a` in our cache like `require_or_from` and mark it dirty,
+    /// telling a missing state root apart from a failed trie read.
+    /// Accounts missing from the cache are read at the latest committed root if `follow_commits` is set.
+    pub fn try_require_or_from<'a, F, G>(
+        &'a self,
+        a: &Address,
+        require_code: bool,
+        require_abi: bool,
+        default: F,
+        not_default: G,
+    ) -> Result<RefMut<'a, Account>, RequireError>
+    where
+        F: FnOnce() -> Account,
+        G: FnOnce(&mut Account),
+    {
+        self.note_access(a, true);
+        let a = &self.map_address(a);
+        let contains_key = self.cache.borrow().contains_key(a);
+        if !contains_key {
+            let root = self.read_root();
+            let nodes = self.trie_node_db();
+            let db = self.factories
+                .trie
+                .readonly(&nodes, &root)
+                .map_err(|err| match *err {
+                    TrieError::InvalidStateRoot(root) => RequireError::MissingStateRoot(root),
+                    err => RequireError::TrieRead(err),
+                })?;
+            let maybe_acc = db.get_with(a, Account::from_rlp)
+                .map_err(|err| RequireError::TrieRead(*err))?;
+            self.insert_cache(a, AccountEntry::new_clean(maybe_acc));
+        }
+        self.note_cache(a);
+
+        // at this point the entry is guaranteed to be in the cache.
+        Ok(RefMut::map(self.cache.borrow_mut(), |c| {
+            let entry = c.get_mut(a)
+                .expect("entry known to exist in the cache; qed");
+
+            match &mut entry.account {
+                &mut Some(ref mut acc) => not_default(acc),
+                slot => *slot = Some(default()),
+            }
+
+            // set the dirty flag after changing account data.
+            entry.state = AccountState::Dirty;
+            match entry.account {
+                Some(ref mut account) => {
+                    if let Some(number) = self.touch_block {
+                        account.touch(number);
+                    }
+                    let require = match (require_code, require_abi) {
+                        (true, true) => Some(RequireCache::CodeAndAbi),
+                        (true, false) => Some(RequireCache::Code),
+                        (false, true) => Some(RequireCache::Abi),
+                        (false, false) => None,
+                    };
+                    if let Some(require) = require {
+                        let addr_hash = account.address_hash(a);
+                        let accountdb = self.factories
+                            .accountdb
+                            .readonly(self.db.as_hashdb(), addr_hash);
+                        self.update_account_cache(require, account, /* &self.db, */ accountdb.as_hashdb());
+                    }
+
+                    account
+                }
+                _ => panic!("Required account must always exist; qed"),
+            }
+        }))
+    }
+}
+
+/// Read the storage value of account `a` at `key` against an arbitrary state `root`
+/// without building a `State`. Missing accounts and slots read as zero.
+pub fn storage_at_root<B: Backend>(
+    db: &B,
+    factories: &Factories,
+    root: H256,
+    a: &Address,
+    key: &H256,
+) -> trie::Result<H256> {
+    let trie = factories.trie.readonly(db.as_hashdb(), &root)?;
+    match trie.get_with(a, Account::from_rlp)? {
+        Some(account) => {
+            let account_db = factories
+                .accountdb
+                .readonly(db.as_hashdb(), account.address_hash(a));
+            account.storage_at(&factories.trie, account_db.as_hashdb(), key)
+        }
+        None => Ok(H256::new()),
+    }
+}
+
+/// Address hash of the account at `key` of the account trie.
+/// Secure tries are keyed by the address hash, the others by the address itself.
+fn key_address_hash(key: &[u8]) -> H256 {
+    if key.len() == 32 {
+        H256::from_slice(key)
+    } else {
+        Address::from_slice(key).crypt_hash()
+    }
+}
+
+/// Storage slot of `key` in a solidity `mapping(address => _)` declared at `slot`.
+fn mapping_slot(key: &Address, slot: u64) -> H256 {
+    let mut preimage = H256::from(*key).to_vec();
+    preimage.extend_from_slice(&H256::from(slot)[..]);
+    sha3(&preimage[..])
+}
+
+/// Whether `code` contains a `PUSH20` of `target`. Operands of other pushes are skipped,
+/// so address bytes embedded in them do not match.
+fn pushes_address(code: &[u8], target: &Address) -> bool {
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        pc += 1;
+        // PUSH1 to PUSH32
+        if opcode >= 0x60 && opcode <= 0x7f {
+            let size = (opcode - 0x60 + 1) as usize;
+            // PUSH20
+            if opcode == 0x73 && code.len() >= pc + size && &code[pc..pc + size] == &target[..] {
+                return true;
+            }
+            pc += size;
+        }
+    }
+    false
+}
+
+/// Read the code of account `a` against an arbitrary state `root`
+/// without building a `State`. Accounts without code and missing accounts read as `None`.
+/// Pass the `State::blob_store` the state was committed with, if any, as `blob_store`.
+pub fn code_at_root<B: Backend>(
+    db: &B,
+    factories: &Factories,
+    blob_store: Option<&BlobStore>,
+    root: H256,
+    a: &Address,
+) -> trie::Result<Option<Arc<Bytes>>> {
+    let trie = factories.trie.readonly(db.as_hashdb(), &root)?;
+    Ok(trie.get_with(a, Account::from_rlp)?.and_then(|mut account| {
+        if account.code_hash() == HASH_EMPTY {
+            return None;
+        }
+        if let Some(store) = blob_store {
+            return account.cache_code(&BlobDB::new(store));
+        }
+        let account_db = factories
+            .accountdb
+            .readonly(db.as_hashdb(), account.address_hash(a));
+        account.cache_code(account_db.as_hashdb())
+    }))
+}
+
+fn merge_accounts(
+    address: &Address,
+    mine: Option<&Account>,
+    theirs: Option<Account>,
+    policy: &ConflictPolicy,
+) -> Result<Option<Account>, MergeConflict> {
+    let (mine, theirs) = match (mine, theirs) {
+        (Some(mine), Some(theirs)) => (mine, theirs),
+        (None, None) => return Ok(None),
+        (mine, theirs) => {
+            return Ok(if policy.prefer_other(address, None)? {
+                theirs
+            } else {
+                mine.map(Account::clone_dirty)
+            })
+        }
+    };
+    let fields_differ =
+        mine.nonce() != theirs.nonce() || mine.code_hash() != theirs.code_hash() || mine.abi_hash() != theirs.abi_hash();
+    let other_wins = fields_differ && policy.prefer_other(address, None)?;
+    // start from the account whose fields win and add the other side's slots to it
+    let (mut merged, loser, loser_is_other) = if other_wins || !fields_differ {
+        (theirs, mine.clone_dirty(), false)
+    } else {
+        (mine.clone_dirty(), theirs, true)
+    };
+    for (key, value) in loser.storage_changes() {
+        let take = match merged.storage_change(key) {
+            None => true,
+            Some(current) if current == *value => false,
+            Some(_) => policy.prefer_other(address, Some(key))? == loser_is_other,
+        };
+        if take {
+            merged.set_storage(*key, *value);
+        }
+    }
+    Ok(Some(merged))
+}
+
+/// Root of an empty trie under the hash algorithm the crate is built with.
+/// Compare storage roots against this instead of a hardcoded hash.
+pub fn empty_trie_root() -> H256 {
+    HASH_NULL_RLP
+}
+
+/// Root of the trie with root `root` rebuilt from its items, differs from `root` if a node is corrupted.
+fn rebuild_trie_root(db: &HashDB, root: &H256) -> trie::Result<H256> {
+    let trie = trie::TrieDB::new(db, root)?;
+    let mut rebuilt_db = MemoryDB::new();
+    let mut rebuilt_root = H256::new();
+    {
+        let mut rebuilt = trie::TrieDBMut::new(&mut rebuilt_db, &mut rebuilt_root);
+        for item in trie.iter()? {
+            let (key, value) = item?;
+            rebuilt.insert(&key, &value)?;
+        }
+    }
+    Ok(rebuilt_root)
+}
+
+/// Fill `buf` from `input`, returning fewer bytes than its length only at the end of the input.
+fn read_stream_entry<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// All nodes of the trie with root `root`, by hash.
+fn trie_nodes(db: &HashDB, root: &H256) -> trie::Result<Vec<(H256, Bytes)>> {
+    // walk the raw trie, the keys it yields are the paths of the storage slots
+    let storage = trie::TrieDB::new(db, root)?;
+    let mut recorder = trie::Recorder::new();
+    for item in storage.iter()? {
+        let (path, _) = item?;
+        storage.get_with(&path, &mut recorder)?;
+    }
+    let mut seen = HashSet::new();
+    Ok(recorder
+        .drain()
+        .into_iter()
+        .filter(|record| seen.insert(record.hash))
+        .map(|record| (record.hash, record.data))
+        .collect())
+}
+
+/// Slots whose value differs between two `State::storage_snapshot`s,
+/// mapped to their `(before, after)` values. Empty slots are `None`.
+pub fn storage_diff(
+    before: &BTreeMap<H256, H256>,
+    after: &BTreeMap<H256, H256>,
+) -> BTreeMap<H256, (Option<H256>, Option<H256>)> {
+    let mut diff = BTreeMap::new();
+    for (key, value) in before {
+        if after.get(key) != Some(value) {
+            diff.insert(*key, (Some(*value), after.get(key).cloned()));
+        }
+    }
+    for (key, value) in after {
+        if !before.contains_key(key) {
+            diff.insert(*key, (None, Some(*value)));
+        }
+    }
+    diff
+}
+
+impl<B: Backend> fmt::Debug for State<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.cache.borrow())
+    }
+}
+
+// TODO: cloning for `State` shouldn't be possible in general; Remove this and use
+// checkpoints where possible.
+impl Clone for State<StateDB> {
+    fn clone(&self) -> State<StateDB> {
+        let cache = {
+            let mut cache: HashMap<Address, AccountEntry> = HashMap::new();
+            for (key, val) in self.cache.borrow().iter() {
+                if let Some(entry) = val.clone_if_dirty() {
+                    cache.insert(*key, entry);
+                }
+            }
+            cache
+        };
+
+        State {
+            db: self.db.boxed_clone(),
+            root: self.root,
+            committed_root: self.committed_root,
+            cache: RefCell::new(cache),
+            checkpoints: RefCell::new(Vec::new()),
+            account_start_nonce: self.account_start_nonce,
+            factories: self.factories.clone(),
+            creators: self.creators.clone(),
+            senders: self.senders.clone(),
+            blacklist: self.blacklist.clone(),
+            base_quota: self.base_quota,
+            check_valid_until_block: self.check_valid_until_block,
+            check_quota_budget: self.check_quota_budget,
+            account_permissions: self.account_permissions.clone(),
+            defer_code_hashing: self.defer_code_hashing,
+            intern_storage_values: self.intern_storage_values,
+            allow_missing_nodes: self.allow_missing_nodes,
+            touch_block: self.touch_block,
+            collect_metrics: self.collect_metrics,
+            commit_sink: None,
+            address_mapper: self.address_mapper.clone(),
+            engine: self.engine.clone(),
+            storage_cache_items: self.storage_cache_items,
+            zero_address_policy: self.zero_address_policy,
+            nonce_policy: self.nonce_policy,
+            suicided_call_policy: self.suicided_call_policy,
+            max_storage_writes_per_tx: self.max_storage_writes_per_tx,
+            default_abi: self.default_abi.clone(),
+            cold_sload_gas: self.cold_sload_gas,
+            access_list: self.access_list.clone(),
+            blob_store: self.blob_store.clone(),
+            follow_commits: self.follow_commits,
+            max_checkpoint_depth: self.max_checkpoint_depth,
+            prune_killed_storage: self.prune_killed_storage,
+            max_batch_size: self.max_batch_size,
+            cache_trie_nodes: self.cache_trie_nodes,
+            trie_nodes: Mutex::new(TrieNodeCache::default()),
+            suicide_within_block: self.suicide_within_block.clone(),
+            warm_slots: self.warm_slots.keys().map(|slot| (*slot, 0)).collect(),
+            // clones start without checkpoints
+            storage_writes: vec![self.storage_writes.iter().sum()],
+            commit_threads: self.commit_threads,
+            meter_account_access: self.meter_account_access,
+            allowed_code_hashes: self.allowed_code_hashes.clone(),
+            account_bloom: self.account_bloom.clone(),
+            require_stats: Cell::new(RequireStats::default()),
+            // clones start without checkpoints
+            frozen_slots: self.frozen_slots.keys().map(|slot| (*slot, 0)).collect(),
+            savepoints: Vec::new(),
+            access_counts: RefCell::new(HashMap::new()),
+            account_events: None,
+            substate_pool: None,
+            read_only: false,
+        }
+    }
+}
+
+impl State<StateDB> {
+    /// Capture an owned, thread-safe read-only handle to the current root.
+    /// Only committed changes are visible through the handle.
+    pub fn snapshot_handle(&self) -> StateSnapshot {
+        StateSnapshot::new(
+            self.db.boxed_clone(),
+            self.root,
+            self.account_start_nonce,
+            self.factories.trie.clone(),
+            self.factories.accountdb.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate libproto;
+    extern crate logger;
+    extern crate mktemp;
+    extern crate rustc_hex;
+    ////////////////////////////////////////////////////////////////////////////////
+
+    use self::libproto::blockchain;
+    use self::rustc_hex::FromHex;
+    use super::*;
+    use account_db;
+    use builtin::Builtin;
+    use cita_crypto::KeyPair;
+    use env_info::EnvInfo;
+    use executed::CallType;
+    use log_entry::LogEntry;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tests::helpers::*;
+    use types::transaction::{Action, Transaction};
+    use util::{Address, H256, KeyValueDB};
+    use util::crypto::CreateKey;
+    use util::hashable::HASH_NAME;
+
+    #[test]
+    #[ignore]
+    fn should_apply_create_transaction() {
+        /*
+        ~/codes/parity-contract-demo $ cat contracts/AbiTest.sol
+        pragma solidity ^0.4.8;
+        contract AbiTest {
+          uint balance;
+          function AbiTest() {}
+          function setValue(uint value) {
+            balance = value;
+          }
+        }
+        ~/codes/parity-contract-demo $ solc contracts/AbiTest.sol  --bin-runtime --bin --hash
+        Warning: This is a pre-release compiler version, please do not use it in production.
+
+        ======= contracts/AbiTest.sol:AbiTest =======
+        Binary:
+        60606040523415600b57fe5b5b5b5b608e8061001c6000396000f300606060405260003
+        57c0100000000000000000000000000000000000000000000000000000000900463ffff
+        ffff1680635524107714603a575bfe5b3415604157fe5b6055600480803590602001909
+        19050506057565b005b806000819055505b505600a165627a7a7230582079b763be08c2
+        4124c9fa25c78b9d221bdee3e981ca0b2e371628798c41e292ca0029
+        Binary of the runtime part:
+        60606040526000357c01000000000000000000000000000000000000000000000000000
+        00000900463ffffffff1680635524107714603a575bfe5b3415604157fe5b6055600480
+        80359060200190919050506057565b005b806000819055505b505600a165627a7a72305
+        82079b763be08c24124c9fa25c78b9d221bdee3e981ca0b2e371628798c41e292ca0029
+        Function signatures:
+        55241077: setValue(uint256)
+         */
+        logger::silent();
+
+        // 1) tx = (to, data(code), nonce, valid_until_block)
+        let mut tx = blockchain::Transaction::new();
+        tx.set_to(String::from(""));
+        let nonce = "haha".to_owned();
+        tx.set_nonce(nonce.clone());
+        let block_limit = 100;
+        tx.set_valid_until_block(block_limit);
+        tx.set_quota(1844673);
+        tx.set_data(
+            "6060604052341561000f57600080fd5b60646000819055507f8fb1356be\
+             6b2a4e49ee94447eb9dcb8783f51c41dcddfe7919f945017d163bf3336064604051808373\
+             ffffffffffffffffffffffffffffffffffffffff1673fffffffffffffffffffffffffffff\
+             fffffffffff1681526020018281526020019250505060405180910390a161017580610092\
+             6000396000f30060606040526000357c01000000000000000000000000000000000000000\
+             00000000000000000900463ffffffff16806360fe47b1146100485780636d4ce63c146100\
+             6b57600080fd5b341561005357600080fd5b6100696004808035906020019091905050610\
+             094565b005b341561007657600080fd5b61007e610140565b604051808281526020019150\
+             5060405180910390f35b7fc6d8c0af6d21f291e7c359603aa97e0ed500f04db6e983b9fce\
+             75a91c6b8da6b816040518082815260200191505060405180910390a1806000819055507f\
+             fd28ec3ec2555238d8ad6f9faf3e4cd10e574ce7e7ef28b73caa53f9512f65b9338260405\
+             1808373ffffffffffffffffffffffffffffffffffffffff1673ffffffffffffffffffffff\
+             ffffffffffffffffff1681526020018281526020019250505060405180910390a150565b6\
+             00080549050905600a165627a7a723058208777d774164b22030e359c5220ad3599f2a294\
+             b4a0ae14b78c4f6a3246525c180029"
+                .from_hex()
+                .unwrap(),
+        );
+
+        // 2) stx = (from, content(code, nonce, signature))
+        // TODO: Should get or generate private key that have send transation permission.
+        //       Should work both for ed25519 and secp256k1.
+        let keypair = KeyPair::gen_keypair();
+        let privkey = keypair.privkey();
+        let stx = tx.sign(*privkey);
+
+        // 4) signed
+        let mut signed = SignedTransaction::new(&stx).unwrap();
+
+        // 5)
+        let mut state = get_temp_state();
+        let info = EnvInfo {
+            number: 0,
+            author: Address::default(),
+            timestamp: 0,
+            difficulty: 0.into(),
+            gas_limit: U256::from(u64::max_value()),
+            last_hashes: Arc::new(vec![]),
+            gas_used: 0.into(),
+            account_gas_limit: 1844674.into(),
+        };
+        let contract_address = ::executive::contract_address(&signed.sender(), &U256::from(1));
+        println!("contract_address {:?}", contract_address);
+        let result = state.apply(&info, &mut signed, true, false, false).unwrap();
+        println!(
+            "{:?}",
+            state
+                .code(&contract_address)
+                .expect("result should unwrap.")
+                .expect("option should unwrap")
+        );
+        println!("{:?}", result.trace);
+        assert_eq!(
+            state.code(&contract_address).unwrap().unwrap(),
+            Arc::new(
+                "60606040526000357c010000000000000000000000000000000000000000000000\
+                 0000000000900463ffffffff1680635524107714603a575bfe5b3415604157fe5b\
+                 605560048080359060200190919050506057565b005b806000819055505b505600\
+                 a165627a7a7230582079b763be08c24124c9fa25c78b9d221bdee3e981ca0b2e37\
+                 1628798c41e292ca0029"
+                    .from_hex()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            state.abi(&contract_address).unwrap().unwrap(),
+            Arc::new(vec![])
+        );
+    }
+
+    #[test]
+    fn apply_outcome_rlp_roundtrip() {
+        let receipt = Receipt::new(
+            None,
+            0x40cae.into(),
+            vec![
+                LogEntry {
+                    address: 0xa.into(),
+                    topics: vec![0xb.into(), 0xc.into()],
+                    data: vec![1, 2, 3],
+                },
+            ],
+            Some(ReceiptError::Reverted),
+            1.into(),
+        );
+        let trace = vec![
+            FlatTrace {
+                action: ::trace::trace::Action::Call(::trace::trace::Call {
+                    from: 0xa.into(),
+                    to: 0xb.into(),
+                    value: 0.into(),
+                    gas: 79000.into(),
+                    input: vec![0x55, 0x24, 0x10, 0x77],
+                    call_type: CallType::Call,
+                }),
+                result: ::trace::trace::Res::Call(::trace::trace::CallResult {
+                    gas_used: 69.into(),
+                    output: vec![],
+                }),
+                subtraces: 1,
+                trace_address: Default::default(),
+            },
+            FlatTrace {
+                action: ::trace::trace::Action::Call(::trace::trace::Call {
+                    from: 0xb.into(),
+                    to: 0xc.into(),
+                    value: 0.into(),
+                    gas: 78934.into(),
+                    input: vec![],
+                    call_type: CallType::Call,
+                }),
+                result: ::trace::trace::Res::FailedCall(::trace::TraceError::OutOfGas),
+                subtraces: 0,
+                trace_address: vec![0].into_iter().collect(),
+            },
+        ];
+        let outcome = ApplyOutcome {
+            receipt: receipt,
+            trace: trace,
+            contracts_created: vec![0xd.into()],
+            contract_address: Some(0xe.into()),
+            touched_accounts: 2,
+            storage_gas: 20000.into(),
+            opcode_profile: Some(OpcodeProfile {
+                counts: vec![(0x01, 2), (0x60, 4)].into_iter().collect(),
+                gas: vec![(0x01, 6.into()), (0x60, 12.into())].into_iter().collect(),
+            }),
+            account_events: vec![
+                AccountEvent::Created(0xe.into()),
+                AccountEvent::StorageWritten {
+                    address: 0xe.into(),
+                    key: 1.into(),
+                    value: 2.into(),
+                },
+                AccountEvent::CodeSet(0xe.into()),
+                AccountEvent::Killed(0xf.into()),
+            ],
+            state_growth: -64,
+        };
+
+        let encoded = ::rlp::encode(&outcome);
+        let decoded: ApplyOutcome = ::rlp::decode(&encoded);
+        assert_eq!(decoded, outcome);
+
+        for &growth in &[0, 64, i64::max_value(), i64::min_value()] {
+            let outcome = ApplyOutcome {
+                state_growth: growth,
+                ..outcome.clone()
+            };
+            let decoded: ApplyOutcome = ::rlp::decode(&::rlp::encode(&outcome));
+            assert_eq!(decoded.state_growth_bytes(), growth);
+        }
+        assert!(decode_signed(0, 1 << 63).is_err());
+        assert!(decode_signed(2, 0).is_err());
+    }
+
+    #[test]
+    fn apply_reports_created_contract_address() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        // PUSH1 0x00 PUSH1 0x00 RETURN
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: vec![0x60, 0x00, 0x60, 0x00, 0xf3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let expected = ::executive::contract_address(&sender, &state.nonce(&sender).unwrap());
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.contract_address, Some(expected));
+        assert!(state.exists(&expected).unwrap());
+
+        let mut t = Transaction {
+            action: Action::Call(expected),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.contract_address, None);
+    }
+
+    #[test]
+    fn apply_collects_opcode_profile() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        // PUSH1 0x20 (JUMPDEST PUSH1 0x01 SWAP1 SUB DUP1 PUSH1 0x02 JUMPI)* STOP
+        let code = vec![0x60, 0x20, 0x5b, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x02, 0x57, 0x00];
+        state.init_code(&contract, code).unwrap();
+        state.commit().unwrap();
+
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.opcode_profile, None);
+
+        let mut options = TransactOptions::default();
+        options.opcode_profiling = true;
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        let profile = outcome.opcode_profile.unwrap();
+        assert_eq!(profile.counts[&0x57], 32);
+        assert_eq!(profile.counts[&0x60], 65);
+        assert_eq!(profile.dominant_opcode(), Some(0x57));
+    }
+
+    #[test]
+    fn apply_at_simulated_block() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        // NUMBER PUSH1 0x00 SSTORE TIMESTAMP PUSH1 0x01 SSTORE
+        state
+            .init_code(&contract, vec![0x43, 0x60, 0x00, 0x55, 0x42, 0x60, 0x01, 0x55])
+            .unwrap();
+        state.commit().unwrap();
+
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::at_block(1_000_000, 1_900_000_000), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.storage_at(&contract, &0.into()).unwrap(), H256::from(1_000_000));
+        assert_eq!(state.storage_at(&contract, &1.into()).unwrap(), H256::from(1_900_000_000));
+    }
+
+    #[test]
+    fn apply_records_account_events() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        // PUSH1 0x2a PUSH1 0x01 SSTORE PUSH1 0x00 PUSH1 0x00 RETURN
+        let code = vec![0x60, 0x2a, 0x60, 0x01, 0x55, 0x60, 0x00, 0x60, 0x00, 0xf3];
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: code.clone(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert!(outcome.account_events.is_empty());
+
+        let mut options = TransactOptions::default();
+        options.account_events = true;
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: code,
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        let contract = outcome.contract_address.unwrap();
+        assert_eq!(
+            outcome.account_events,
+            vec![
+                AccountEvent::Created(contract),
+                AccountEvent::StorageWritten {
+                    address: contract,
+                    key: 1.into(),
+                    value: 0x2a.into(),
+                },
+                AccountEvent::CodeSet(contract),
+            ]
+        );
+    }
+
+    #[test]
+    fn salted_creation_lands_on_predicted_address() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        // PUSH1 0x01 PUSH1 0x00 RETURN, deploys a single STOP
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0xf3];
+        let salt = H256::from(7);
+        let predicted = state.predict_contract_address(&sender, salt, code.crypt_hash());
+        assert!(predicted != state.predict_contract_address(&sender, H256::from(8), code.crypt_hash()));
+
+        let mut options = TransactOptions::default();
+        options.create_salt = Some(salt);
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: code.clone(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(outcome.contract_address, Some(predicted));
+        assert!(state.exists(&predicted).unwrap());
+
+        // deploying the same code with the same salt again collides
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: code,
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply_with_options(&EnvInfo::default(), &mut t, options) {
+            Err(Error::Execution(ExecutionError::ContractAddressCollision(address))) => assert_eq!(address, predicted),
+            other => panic!("expected ContractAddressCollision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn apply_stream_replays_transactions() {
+        let mut input = Vec::new();
+        let mut offsets = Vec::new();
+        for data in &[vec![1u8, 2, 3], vec![4u8]] {
+            offsets.push(input.len());
+            let t = Transaction {
+                action: Action::Store,
+                gas: 100_000.into(),
+                data: data.clone(),
+                ..Default::default()
+            }.fake_sign(Address::from(0x1234));
+            let encoded = ::rlp::encode(&t);
+            let mut prefix = [0u8; 4];
+            BigEndian::write_u32(&mut prefix, encoded.len() as u32);
+            input.extend_from_slice(&prefix);
+            input.extend_from_slice(&encoded);
+        }
+        // the sender is recovered from the encoded public key
+        let sender = *::rlp::decode::<SignedTransaction>(&input[4..]).sender();
+
+        let mut state = get_temp_state();
+        let results = state
+            .apply_stream(&EnvInfo::default(), &mut &input[..], TransactOptions::default())
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        for result in results {
+            assert_eq!(result.unwrap().receipt.error, None);
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(2));
+
+        let mut truncated = input.clone();
+        truncated.pop();
+        match state.apply_stream(&EnvInfo::default(), &mut &truncated[..], TransactOptions::default()) {
+            Err(err) => assert!(format!("{}", err).contains(&format!("offset {}", offsets[1]))),
+            Ok(_) => panic!("expected a truncated stream to fail"),
+        }
+
+        let mut oversized = input.clone();
+        BigEndian::write_u32(&mut oversized[offsets[1]..], u32::max_value());
+        match state.apply_stream(&EnvInfo::default(), &mut &oversized[..], TransactOptions::default()) {
+            Err(err) => assert!(format!("{}", err).contains(&format!("malformed transaction at offset {}", offsets[1]))),
+            Ok(_) => panic!("expected an oversized transaction to fail"),
+        }
+    }
+
+    #[test]
+    fn apply_system_transaction_keeps_nonce() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let options = TransactOptions {
+            tracing: false,
+            vm_tracing: false,
+            check_permission: true,
+            check_quota: false,
+            system_transaction: true,
+            gas_price: None,
+            opcode_profiling: false,
+            account_events: false,
+            state_growth: false,
+            create_salt: None,
+        };
+
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn state_growth_of_transactions() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let allocate = Address::from(0xa);
+        let clear = Address::from(0xb);
+        // PUSH1 0x01 PUSH1 0x00 SSTORE
+        state.init_code(&allocate, vec![0x60, 0x01, 0x60, 0x00, 0x55]).unwrap();
+        // PUSH1 0x00 PUSH1 0x00 SSTORE
+        state.init_code(&clear, vec![0x60, 0x00, 0x60, 0x00, 0x55]).unwrap();
+        state.set_storage(&clear, H256::zero(), H256::from(1)).unwrap();
+        state.commit().unwrap();
+
+        let options = TransactOptions {
+            tracing: false,
+            vm_tracing: false,
+            check_permission: false,
+            check_quota: false,
+            system_transaction: false,
+            gas_price: None,
+            opcode_profiling: false,
+            account_events: false,
+            state_growth: true,
+            create_salt: None,
+        };
+        let call = |state: &mut State<StateDB>, contract: Address| {
+            let mut t = Transaction {
+                action: Action::Call(contract),
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            state
+                .apply_with_options(&EnvInfo::default(), &mut t, options)
+                .unwrap()
+        };
+        assert_eq!(call(&mut state, allocate).state_growth_bytes(), STORAGE_SLOT_BYTES);
+        // the slot is already set
+        assert_eq!(call(&mut state, allocate).state_growth_bytes(), 0);
+        assert_eq!(call(&mut state, clear).state_growth_bytes(), -STORAGE_SLOT_BYTES);
+        // not measured unless asked for
+        state.set_storage(&allocate, H256::zero(), H256::zero()).unwrap();
+        let mut t = Transaction {
+            action: Action::Call(allocate),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.state_growth_bytes(), 0);
+
+        // PUSH1 0x01 PUSH1 0x00 SSTORE PUSH1 0x00 PUSH1 0x00 RETURN,
+        // a constructor allocating a slot and deploying no code
+        let mut create = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xf3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut create, options)
+            .unwrap();
+        assert_eq!(outcome.state_growth_bytes(), STORAGE_SLOT_BYTES);
+    }
+
+    #[test]
+    fn committed_root_ignores_pending_changes() {
+        let mut state = get_temp_state();
+        let a = Address::from(0xa);
+        let opened = state.committed_root();
+        assert_eq!(opened, *state.root());
+
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&a, H256::from(1), H256::from(1)).unwrap();
+        assert_eq!(state.committed_root(), opened);
+
+        state.commit().unwrap();
+        assert_ne!(state.committed_root(), opened);
+        assert_eq!(state.committed_root(), *state.root());
+
+        let committed = state.committed_root();
+        state.set_storage(&a, H256::from(1), H256::from(2)).unwrap();
+        assert_eq!(state.committed_root(), committed);
+    }
+
+    #[test]
+    fn access_list_prewarms_storage() {
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0x2000);
+        let mut state = get_temp_state();
+        state.cold_sload_gas = 2_000;
+        // PUSH1 0x00 SLOAD PUSH1 0x00 SLOAD, only the first load of the slot is cold
+        state
+            .init_code(&contract, vec![0x60, 0x00, 0x54, 0x60, 0x00, 0x54])
+            .unwrap();
+        state.commit().unwrap();
+
+        let gas_used = |state: &mut State<StateDB>, access_list: Vec<(Address, Vec<H256>)>| {
+            let mut t = Transaction {
+                action: Action::Call(contract),
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            state.access_list = access_list;
+            let outcome = state
+                .apply_with_options(&EnvInfo::default(), &mut t, TransactOptions::default())
+                .unwrap();
+            assert_eq!(outcome.receipt.error, None);
+            outcome.receipt.gas_used
+        };
+        let cold = gas_used(&mut state, vec![]);
+        let warm = gas_used(&mut state, vec![(contract, vec![H256::zero()])]);
+        assert_eq!(cold - warm, 2_000.into());
+        // warmth does not carry over to the next transaction
+        assert_eq!(gas_used(&mut state, vec![]), cold);
+        // listing other slots or accounts changes nothing
+        assert_eq!(gas_used(&mut state, vec![(contract, vec![H256::from(1)]), (sender, vec![])]), cold);
+
+        // slots first accessed in a reverted call are cold again, listed ones stay warm
+        state.access_list = vec![(contract, vec![H256::zero()])];
+        state.reset_warm_storage();
+        state.checkpoint().unwrap();
+        state.warm_storage(&contract, &H256::zero());
+        state.warm_storage(&contract, &H256::from(1));
+        state.checkpoint().unwrap();
+        state.warm_storage(&contract, &H256::from(2));
+        state.discard_checkpoint();
+        state.revert_to_checkpoint();
+        assert!(state.is_storage_warm(&contract, &H256::zero()));
+        assert!(!state.is_storage_warm(&contract, &H256::from(1)));
+        assert!(!state.is_storage_warm(&contract, &H256::from(2)));
+        state.checkpoint().unwrap();
+        state.warm_storage(&contract, &H256::from(1));
+        state.discard_checkpoint();
+        assert!(state.is_storage_warm(&contract, &H256::from(1)));
+    }
+
+    #[test]
+    fn commit_is_idempotent() {
+        let mut state = get_temp_state();
+        let a = Address::from(0xa);
+        state.assert_commit_idempotent().unwrap();
+
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&a, H256::from(1), H256::from(1)).unwrap();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+        let root = *state.root();
+        state.assert_commit_idempotent().unwrap();
+        assert_eq!(*state.root(), root);
+    }
+
+    #[test]
+    fn default_abi_of_created_contracts() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        state.default_abi = Some(b"fallback".to_vec());
+        // PUSH1 0x00 PUSH1 0x00 RETURN
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: vec![0x60, 0x00, 0x60, 0x00, 0xf3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        let contract = outcome.contract_address.unwrap();
+        assert_eq!(state.abi(&contract).unwrap(), Some(Arc::new(b"fallback".to_vec())));
+        state.commit().unwrap();
+        assert_eq!(state.abi(&contract).unwrap(), Some(Arc::new(b"fallback".to_vec())));
+
+        // a registered abi replaces the default
+        let mut data = contract.to_vec();
+        data.extend_from_slice(b"abi");
+        let mut t = Transaction {
+            action: Action::AbiStore,
+            gas: 100_000.into(),
+            data: data,
+            ..Default::default()
+        }.fake_sign(sender);
+        state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(state.abi(&contract).unwrap(), Some(Arc::new(b"abi".to_vec())));
+    }
+
+    #[test]
+    fn max_storage_writes_per_tx() {
+        let sender = Address::from(0x1234);
+        let (writer, reverter, caller) = (Address::from(0x2000), Address::from(0x2001), Address::from(0x2002));
+        // PUSH1 0x01 PUSH1 <slot> SSTORE for slots 0, 1 and 2
+        let writes = vec![
+            0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x01, 0x60, 0x01, 0x55, 0x60, 0x01, 0x60, 0x02, 0x55,
+        ];
+        let mut reverting = writes.clone();
+        // PUSH1 0x00 PUSH1 0x00 REVERT
+        reverting.extend_from_slice(&[0x60, 0x00, 0x60, 0x00, 0xfd]);
+        // CALL the reverter with all gas, POP, then PUSH1 0x01 PUSH1 0x03 SSTORE
+        let calling = vec![
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x61, 0x20, 0x01, 0x5a, 0xf1, 0x50, 0x60,
+            0x01, 0x60, 0x03, 0x55,
+        ];
+        let mut state = get_temp_state();
+        state.init_code(&writer, writes).unwrap();
+        state.init_code(&reverter, reverting).unwrap();
+        state.init_code(&caller, calling).unwrap();
+        state.commit().unwrap();
+        state.max_storage_writes_per_tx = Some(2);
+
+        let call = |state: &mut State<StateDB>, contract: Address| {
+            let mut t = Transaction {
+                action: Action::Call(contract),
+                gas: 200_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            state.apply(&EnvInfo::default(), &mut t, false, false, false)
+        };
+        // the third SSTORE halts the call, which is charged like any other exceptional halt
+        let outcome = call(&mut state, writer).unwrap();
+        assert_eq!(outcome.receipt.error, Some(ReceiptError::TooManyStorageWrites));
+        assert_eq!(outcome.receipt.gas_used, U256::from(200_000));
+        assert_eq!(state.storage_at(&writer, &H256::zero()).unwrap(), H256::zero());
+        assert_eq!(state.nonce(&sender).unwrap(), U256::one());
+
+        // the count starts over with every transaction
+        state.max_storage_writes_per_tx = Some(3);
+        let outcome = call(&mut state, reverter).unwrap();
+        assert_eq!(outcome.receipt.error, Some(ReceiptError::Reverted));
+
+        // writes of reverted calls do not count
+        assert_eq!(call(&mut state, caller).unwrap().receipt.error, None);
+        assert_eq!(state.storage_at(&caller, &H256::from(3)).unwrap(), H256::from(1));
+        assert_eq!(state.storage_at(&reverter, &H256::zero()).unwrap(), H256::zero());
+
+        assert_eq!(call(&mut state, writer).unwrap().receipt.error, None);
+        assert_eq!(state.storage_at(&writer, &H256::from(2)).unwrap(), H256::from(1));
+    }
+
+    #[test]
+    fn apply_reuse_matches_apply() {
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0x2000);
+        let proxy = Address::from(0x2001);
+        let setup = || {
+            let mut state = get_temp_state();
+            // PUSH1 0x01 NUMBER SSTORE PUSH1 0x00 PUSH1 0x00 LOG0
+            state
+                .init_code(&contract, vec![0x60, 0x01, 0x43, 0x55, 0x60, 0x00, 0x60, 0x00, 0xa0])
+                .unwrap();
+            // CALL the contract with all gas, POP, then PUSH1 0x00 PUSH1 0x00 LOG0
+            state
+                .init_code(
+                    &proxy,
+                    vec![
+                        0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x61, 0x20, 0x00, 0x5a, 0xf1,
+                        0x50, 0x60, 0x00, 0x60, 0x00, 0xa0,
+                    ],
+                )
+                .unwrap();
+            state.commit().unwrap();
+            state
+        };
+        let transactions: Vec<_> = (0..16u64)
+            .map(|i| {
+                Transaction {
+                    action: match i % 4 {
+                        3 => Action::Store,
+                        2 => Action::Call(proxy),
+                        _ => Action::Call(contract),
+                    },
+                    gas: if i % 5 == 4 { 10_000.into() } else { 100_000.into() },
+                    ..Default::default()
+                }.fake_sign(sender)
+            })
+            .collect();
+        let options = TransactOptions {
+            tracing: true,
+            vm_tracing: false,
+            check_permission: false,
+            check_quota: false,
+            system_transaction: false,
+            gas_price: None,
+            opcode_profiling: false,
+            account_events: false,
+            state_growth: false,
+            create_salt: None,
+        };
+
+        let (mut fresh, mut reusing) = (setup(), setup());
+        let mut ctx = ExecContext::default();
+        for (number, t) in transactions.iter().enumerate() {
+            let mut env_info = EnvInfo::default();
+            env_info.number = number as u64;
+            let expected = fresh
+                .apply_with_options(&env_info, &mut t.clone(), options)
+                .unwrap();
+            let outcome = reusing
+                .apply_reuse(&env_info, &mut t.clone(), options, &mut ctx)
+                .unwrap();
+            assert_eq!(outcome, expected);
+            ctx.recycle(outcome);
+        }
+        // the buffers of the last transactions are kept
+        assert!(ctx.substate.logs.capacity() > 0);
+        assert!(!ctx.substates.is_empty());
+        fresh.commit().unwrap();
+        reusing.commit().unwrap();
+        assert_eq!(reusing.root(), fresh.root());
+    }
+
+    #[test]
+    fn storage_node_count() {
+        let mut state = get_temp_state();
+        let (empty, single, many) = (Address::from(0xa), Address::from(0xb), Address::from(0xc));
+        state.inc_nonce(&empty).unwrap();
+        state.set_storage(&single, H256::from(1), H256::from(1)).unwrap();
+        for slot in 1..17u64 {
+            state.set_storage(&many, H256::from(slot), H256::from(slot)).unwrap();
+        }
+        assert_eq!(state.storage_node_count(&many).unwrap(), 0);
+        state.commit().unwrap();
+
+        assert_eq!(state.storage_node_count(&Address::from(0xd)).unwrap(), 0);
+        assert_eq!(state.storage_node_count(&empty).unwrap(), 0);
+        // a lone slot is a leaf at the root
+        assert_eq!(state.storage_node_count(&single).unwrap(), 1);
+        // every leaf plus at least the branch above them
+        assert!(state.storage_node_count(&many).unwrap() > 16);
+    }
+
+    #[test]
+    fn trie_node_cache_spares_node_loads() {
+        let loads = |cache_trie_nodes: bool| {
+            let mut state = get_temp_state();
+            for i in 0..256u64 {
+                state.inc_nonce(&Address::from(0x100 + i)).unwrap();
+            }
+            state.commit().unwrap();
+            state.clear();
+            state.cache_trie_nodes = cache_trie_nodes;
+
+            let before = state.trie_node_loads();
+            for i in 0..16u64 {
+                let mut t = Transaction {
+                    action: Action::Call(Address::from(0x100 + i)),
+                    gas: 100_000.into(),
+                    ..Default::default()
+                }.fake_sign(Address::from(0x1234));
+                state
+                    .apply(&EnvInfo::default(), &mut t, false, false, false)
+                    .unwrap();
+            }
+            let used = state.trie_node_loads() - before;
+
+            // the kept nodes are dropped on commit
+            state.commit().unwrap();
+            state.clear();
+            let before = state.trie_node_loads();
+            state.nonce(&Address::from(0x100)).unwrap();
+            assert!(state.trie_node_loads() > before);
+            used
+        };
+
+        let uncached = loads(false);
+        let cached = loads(true);
+        assert!(cached < uncached, "{} node loads with the cache, {} without", cached, uncached);
+    }
+
+    #[test]
+    fn nonce_policy_for_system_transactions() {
+        let sender = Address::from(0x1234);
+        let options = TransactOptions {
+            system_transaction: true,
+            ..Default::default()
+        };
+        let apply = |state: &mut State<StateDB>, nonce: &str| {
+            let mut t = Transaction {
+                action: Action::Store,
+                gas: 100_000.into(),
+                nonce: nonce.to_owned(),
+                ..Default::default()
+            }.fake_sign(sender);
+            state.apply_with_options(&EnvInfo::default(), &mut t, options)
+        };
+        let rejected = |result: ApplyResult, expected: u64, got: u64| match result {
+            Err(Error::Execution(ExecutionError::InvalidNonce {
+                expected: e,
+                got: g,
+            })) => assert_eq!((e, g), (U256::from(expected), U256::from(got))),
+            other => panic!("expected InvalidNonce, got {:?}", other),
+        };
+
+        let mut state = get_temp_state();
+        state.nonce_policy = NoncePolicy::Strict;
+        rejected(apply(&mut state, "1"), 0, 1);
+        apply(&mut state, "0").unwrap();
+        rejected(apply(&mut state, "0"), 1, 0);
+        apply(&mut state, "1").unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(2));
+        match apply(&mut state, "two") {
+            Err(Error::Execution(ExecutionError::TransactionMalformed(_))) => {}
+            other => panic!("expected TransactionMalformed, got {:?}", other),
+        }
+
+        let mut state = get_temp_state();
+        state.nonce_policy = NoncePolicy::AllowGaps;
+        apply(&mut state, "5").unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(6));
+        rejected(apply(&mut state, "3"), 6, 3);
+        rejected(apply(&mut state, "5"), 6, 5);
+        apply(&mut state, "6").unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(7));
+
+        let mut state = get_temp_state();
+        assert_eq!(state.nonce_policy, NoncePolicy::Ignore);
+        apply(&mut state, "5").unwrap();
+        apply(&mut state, "3").unwrap();
+        apply(&mut state, "3").unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn apply_with_configured_gas_price() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        // GASPRICE PUSH1 0x00 SSTORE
+        state.init_code(&contract, vec![0x3a, 0x60, 0x00, 0x55]).unwrap();
+        state.commit().unwrap();
+
+        let mut options = TransactOptions::default();
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        assert_eq!(state.storage_at(&contract, &H256::zero()).unwrap(), H256::zero());
+
+        options.gas_price = Some(7.into());
+        let outcome = state
+            .apply_with_options(&EnvInfo::default(), &mut t, options)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.storage_at(&contract, &H256::zero()).unwrap(), H256::from(7));
+    }
+
+    #[test]
+    fn nonce_only_transaction_is_noop() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        state.init_code(&contract, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert!(outcome.is_noop());
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(1));
+
+        let mut data = contract.to_vec();
+        data.extend_from_slice(b"abi");
+        let mut t = Transaction {
+            action: Action::AbiStore,
+            gas: 100_000.into(),
+            data: data,
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert!(!outcome.is_noop());
+        assert_eq!(outcome.touched_accounts, 1);
+    }
+
+    #[test]
+    fn apply_collect_logs_matches_receipt() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        // PUSH1 0x00 PUSH1 0x00 LOG0
+        state.init_code(&contract, vec![0x60, 0x00, 0x60, 0x00, 0xa0]).unwrap();
+        state.commit().unwrap();
+
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let (outcome, logs) = state
+            .apply_collect_logs(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].address, contract);
+        assert!(outcome.receipt.logs.is_empty());
+        assert_eq!(outcome.receipt.log_bloom, logs[0].bloom());
+    }
+
+    #[test]
+    fn missing_node_is_recoverable() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        state.inc_nonce(&sender).unwrap();
+        state.commit().unwrap();
+        state.clear();
+        state.allow_missing_nodes = true;
+
+        let root = *state.root();
+        let node = state.db.as_hashdb().get(&root).unwrap();
+        state.db.as_hashdb_mut().remove(&root);
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&EnvInfo::default(), &mut t, false, false, false) {
+            Err(Error::StateIncomplete { missing_node }) => assert_eq!(missing_node, root),
+            other => panic!("expected StateIncomplete, got {:?}", other),
+        }
+
+        state.db.as_hashdb_mut().emplace(root, node);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(2));
+    }
+
+    #[test]
+    fn apply_updates_last_touched() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        state.inc_nonce(&sender).unwrap();
+        state.commit().unwrap();
+        assert_eq!(state.last_touched(&sender).unwrap(), None);
+
+        let mut env_info = EnvInfo::default();
+        env_info.number = 7;
+        state.set_touch_block(env_info.number);
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        state.apply(&env_info, &mut t, false, false, false).unwrap();
+        assert_eq!(state.last_touched(&sender).unwrap(), Some(7));
+
+        state.commit().unwrap();
+        state.clear();
+        assert_eq!(state.last_touched(&sender).unwrap(), Some(7));
+        assert_eq!(state.last_touched(&0xb.into()).unwrap(), None);
+    }
+
+    #[test]
+    fn can_create_matches_apply() {
+        let mut state = get_temp_state();
+        let creator = Address::from(0x1234);
+        let granted = Address::from(0x1235);
+        let other = Address::from(0x1236);
+        let create = |sender: Address| {
+            Transaction {
+                action: Action::Create,
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender)
+        };
+        let apply_allowed = |state: &mut State<StateDB>, sender: Address| {
+            match state.apply(&EnvInfo::default(), &mut create(sender), false, true, false) {
+                Ok(_) => true,
+                Err(Error::Execution(ExecutionError::NoContractPermission)) => false,
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        };
+
+        // no creators configured, only the zero address may deploy
+        assert!(state.can_create(&Address::zero()));
+        assert!(!state.can_create(&other));
+        assert!(!apply_allowed(&mut state, other));
+
+        state.creators.insert(creator);
+        state
+            .account_permissions
+            .insert(granted, vec![Resource::new(Address::from(0x2), vec![0; 4])]);
+        for &(sender, allowed) in &[(creator, true), (granted, true), (other, false)] {
+            assert_eq!(state.can_create(&sender), allowed);
+            assert_eq!(apply_allowed(&mut state, sender), allowed);
+        }
+    }
+
+    #[test]
+    fn can_send_matches_apply() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let creator = Address::from(0x1235);
+        let granted = Address::from(0x1236);
+        let other = Address::from(0x1237);
+        let apply_allowed = |state: &mut State<StateDB>, sender: Address| {
+            let mut t = Transaction {
+                action: Action::Store,
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            match state.apply(&EnvInfo::default(), &mut t, false, true, false) {
+                Ok(_) => true,
+                Err(Error::Execution(ExecutionError::NoTransactionPermission)) => false,
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        };
+
+        // no senders configured, only the zero address may send
+        assert!(state.can_send(&Address::zero()));
+        assert!(!state.can_send(&other));
+        assert!(!apply_allowed(&mut state, other));
+
+        state.senders.insert(sender);
+        state.creators.insert(creator);
+        state
+            .account_permissions
+            .insert(granted, vec![Resource::new(Address::from(0x1), vec![0; 4])]);
+        for &(sender, allowed) in &[(sender, true), (creator, true), (granted, true), (other, false)] {
+            assert_eq!(state.can_send(&sender), allowed);
+            assert_eq!(apply_allowed(&mut state, sender), allowed);
+        }
+    }
+
+    #[test]
+    fn load_permissions_from_contract() {
+        let mut state = get_temp_state();
+        let authorization = Address::from(0x13241b4);
+        let send_tx = Address::from(0x1);
+        let create_contract = Address::from(0x2);
+        let custom = Address::from(0xabcd);
+        let (sender, creator) = (Address::from(0x1234), Address::from(0x1235));
+        let set_array = |state: &mut State<StateDB>, contract: &Address, slot: H256, words: Vec<H256>| {
+            state
+                .set_storage(contract, slot, H256::from(words.len() as u64))
+                .unwrap();
+            let first = U256::from(&*sha3(&slot[..]));
+            for (index, word) in words.into_iter().enumerate() {
+                state
+                    .set_storage(contract, H256::from(first + U256::from(index)), word)
+                    .unwrap();
+            }
+        };
+        let resource = |cont: Address, func: [u8; 4]| {
+            let mut word = H256::from(cont);
+            word[8..12].copy_from_slice(&func);
+            word
+        };
+
+        set_array(
+            &mut state,
+            &authorization,
+            H256::from(AUTHORIZATION_ALL_ACCOUNTS_SLOT),
+            vec![H256::from(sender), H256::from(creator)],
+        );
+        set_array(
+            &mut state,
+            &authorization,
+            mapping_slot(&sender, AUTHORIZATION_PERMISSIONS_SLOT),
+            vec![H256::from(send_tx), H256::from(custom)],
+        );
+        set_array(
+            &mut state,
+            &authorization,
+            mapping_slot(&creator, AUTHORIZATION_PERMISSIONS_SLOT),
+            vec![H256::from(create_contract)],
+        );
+        for &(permission, func) in &[(send_tx, [0; 4]), (create_contract, [0; 4]), (custom, [0x12, 0x34, 0x56, 0x78])] {
+            let cont = if permission == custom { Address::from(0xc) } else { permission };
+            set_array(
+                &mut state,
+                &permission,
+                H256::from(PERMISSION_RESOURCES_SLOT),
+                vec![resource(cont, func)],
+            );
+        }
+        state.commit().unwrap();
+        state.senders.insert(Address::from(0x9999));
+
+        state.load_permissions_from_contract(&authorization).unwrap();
+        assert_eq!(state.senders, vec![sender].into_iter().collect());
+        assert_eq!(state.creators, vec![creator].into_iter().collect());
+        assert_eq!(
+            state.account_permissions[&sender],
+            vec![
+                Resource::new(send_tx, vec![0; 4]),
+                Resource::new(Address::from(0xc), vec![0x12, 0x34, 0x56, 0x78]),
+            ]
+        );
+        assert_eq!(
+            state.account_permissions[&creator],
+            vec![Resource::new(create_contract, vec![0; 4])]
+        );
+        assert!(state.can_send(&sender) && !state.can_create(&sender));
+        assert!(state.can_create(&creator));
+
+        // a contract-controlled length is not trusted
+        state
+            .set_storage(&authorization, H256::from(AUTHORIZATION_ALL_ACCOUNTS_SLOT), H256::from(U256::max_value()))
+            .unwrap();
+        assert!(state.load_permissions_from_contract(&authorization).is_err());
+        assert_eq!(state.creators, vec![creator].into_iter().collect());
+    }
+
+    #[test]
+    fn blacklisted_contract_call_is_rejected() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let blocked = Address::from(0xabcd);
+        let allowed = Address::from(0xabce);
+        state.init_code(&blocked, vec![0x60, 0x00]).unwrap();
+        state.init_code(&allowed, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+        // the sender may call both contracts, only the blacklist tells them apart
+        state.senders.insert(sender);
+        state.account_permissions.insert(
+            sender,
+            vec![
+                Resource::new(blocked, vec![0; 4]),
+                Resource::new(allowed, vec![0; 4]),
+            ],
+        );
+        state.blacklist.insert(blocked);
+
+        let mut t = Transaction {
+            action: Action::Call(blocked),
+            gas: 100_000.into(),
+            data: vec![0; 4],
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&EnvInfo::default(), &mut t, false, true, false) {
+            Err(Error::Execution(ExecutionError::ContractBlacklisted)) => {}
+            other => panic!("expected ContractBlacklisted, got {:?}", other),
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+
+        let mut t = Transaction {
+            action: Action::Call(allowed),
+            gas: 100_000.into(),
+            data: vec![0; 4],
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, true, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+    }
+
+    #[test]
+    fn creation_of_code_not_whitelisted_is_rejected() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let approved = vec![0x60, 0x00];
+        let mut allowed = HashSet::new();
+        allowed.insert(approved.crypt_hash());
+        state.allowed_code_hashes = Some(allowed);
+
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: vec![0x60, 0x01],
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&EnvInfo::default(), &mut t, false, false, false) {
+            Err(Error::Execution(ExecutionError::CodeNotWhitelisted)) => {}
+            other => panic!("expected CodeNotWhitelisted, got {:?}", other),
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+
+        let mut t = Transaction {
+            action: Action::Create,
+            gas: 100_000.into(),
+            data: approved,
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert!(outcome.contract_address.is_some());
+    }
+
+    #[test]
+    fn nested_creation_of_code_not_whitelisted_fails() {
+        let sender = Address::from(0x1234);
+        let factory = Address::from(0x2000);
+        let mut state = get_temp_state();
+        // PUSH2 0x6000 PUSH1 0x00 MSTORE, CREATE from the 2 bytes `PUSH1 0x00` at offset 30,
+        // then PUSH1 0x00 SSTORE the created address
+        state
+            .init_code(
+                &factory,
+                vec![
+                    0x61, 0x60, 0x00, 0x60, 0x00, 0x52, 0x60, 0x02, 0x60, 0x1e, 0x60, 0x00, 0xf0, 0x60, 0x00, 0x55,
+                ],
+            )
+            .unwrap();
+        state.commit().unwrap();
+        let created = |state: &mut State<StateDB>, approved: Vec<u8>| {
+            let mut allowed = HashSet::new();
+            allowed.insert(approved.crypt_hash());
+            state.allowed_code_hashes = Some(allowed);
+            let mut t = Transaction {
+                action: Action::Call(factory),
+                gas: 200_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            let outcome = state
+                .apply(&EnvInfo::default(), &mut t, false, false, false)
+                .unwrap();
+            assert_eq!(outcome.receipt.error, None);
+            state.storage_at(&factory, &H256::zero()).unwrap()
+        };
+        // the factory goes on after its CREATE failed
+        assert_eq!(created(&mut state, vec![0x60, 0x01]), H256::zero());
+        assert!(!created(&mut state, vec![0x60, 0x00]).is_zero());
+    }
+
+    #[test]
+    fn apply_reports_storage_gas() {
+        let contract = Address::from(0xabcd);
+        let mut state = get_temp_state();
+        // PUSH1 1 PUSH1 k SSTORE for k in 0..3, STOP
+        let code = "60016000556001600155600160025500".from_hex().unwrap();
+        state.init_code(&contract, code).unwrap();
+        state.commit().unwrap();
+
+        let mut t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(Address::from(0x1234));
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.storage_at(&contract, &2.into()).unwrap(), 1.into());
+        assert!(!outcome.storage_gas().is_zero());
+        assert!(outcome.storage_gas() <= outcome.receipt.gas_used);
+    }
+
+    #[test]
+    fn apply_uses_custom_engine() {
+        struct CountingEngine {
+            builtins: BTreeMap<Address, Builtin>,
+            builtin_queries: AtomicUsize,
+        }
+
+        impl Engine for CountingEngine {
+            fn name(&self) -> &str {
+                "CountingEngine"
+            }
+
+            fn builtins(&self) -> &BTreeMap<Address, Builtin> {
+                &self.builtins
+            }
+
+            fn is_builtin(&self, a: &Address) -> bool {
+                self.builtin_queries.fetch_add(1, Ordering::SeqCst);
+                self.builtins.contains_key(a)
+            }
+        }
+
+        let engine = Arc::new(CountingEngine {
+            builtins: BTreeMap::new(),
+            builtin_queries: AtomicUsize::new(0),
+        });
+        let mut state = get_temp_state();
+        state.engine = Some(engine.clone());
+
+        let mut t = Transaction {
+            action: Action::Call(Address::from(0xabcd)),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(Address::from(0x1234));
+        state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert!(engine.builtin_queries.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn suicided_call_policy() {
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        let call = |state: &mut State<StateDB>| {
+            let mut t = Transaction {
+                action: Action::Call(contract),
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender);
+            state.apply(&EnvInfo::default(), &mut t, false, false, false)
+        };
+        let new_state = |policy: SuicidedCallPolicy| {
+            let mut state = get_temp_state();
+            // PUSH1 0x00 SUICIDE
+            state.init_code(&contract, vec![0x60, 0x00, 0xff]).unwrap();
+            state.commit().unwrap();
+            state.suicided_call_policy = policy;
+            state
+        };
+
+        let mut state = new_state(SuicidedCallPolicy::CallEmpty);
+        assert_eq!(call(&mut state).unwrap().receipt.error, None);
+        assert!(!state.exists(&contract).unwrap());
+        assert_eq!(call(&mut state).unwrap().receipt.error, None);
+
+        let mut state = new_state(SuicidedCallPolicy::Reject);
+        assert_eq!(call(&mut state).unwrap().receipt.error, None);
+        match call(&mut state) {
+            Err(Error::Execution(ExecutionError::SuicidedContract(address))) => assert_eq!(address, contract),
+            other => panic!("expected SuicidedContract, got {:?}", other),
+        }
+        // the next block calls the empty account
+        state.commit().unwrap();
+        assert_eq!(call(&mut state).unwrap().receipt.error, None);
+
+        // calls from contracts fail, the caller goes on
+        let mut state = new_state(SuicidedCallPolicy::Reject);
+        let caller = Address::from(0xabce);
+        // CALL the contract with all gas, then PUSH1 0x00 SSTORE whether it succeeded
+        state
+            .init_code(
+                &caller,
+                vec![
+                    0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x61, 0xab, 0xcd, 0x5a, 0xf1, 0x60,
+                    0x00, 0x55,
+                ],
+            )
+            .unwrap();
+        state.commit().unwrap();
+        assert_eq!(call(&mut state).unwrap().receipt.error, None);
+        let mut t = Transaction {
+            action: Action::Call(caller),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut t, false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.storage_at(&caller, &H256::zero()).unwrap(), H256::zero());
+    }
+
+    #[test]
+    fn zero_address_policy() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        let call_zero = || {
+            Transaction {
+                action: Action::Call(Address::zero()),
+                gas: 100_000.into(),
+                ..Default::default()
+            }.fake_sign(sender)
+        };
+
+        let outcome = state
+            .apply(&EnvInfo::default(), &mut call_zero(), false, false, false)
+            .unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(1));
+
+        state.zero_address_policy = ZeroAddressPolicy::Reject;
+        match state.apply(&EnvInfo::default(), &mut call_zero(), false, false, false) {
+            Err(Error::Execution(ExecutionError::SendToZeroAddress)) => {}
+            other => panic!("expected SendToZeroAddress, got {:?}", other),
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(1));
+    }
+
+    #[test]
+    fn base_quota_is_charged() {
+        let mut state = get_temp_state();
+        let sender = Address::from(0x1234);
+        state.base_quota = 1_000.into();
+        let mut env_info = EnvInfo::default();
+        env_info.account_gas_limit = 1_000_000.into();
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 999.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&env_info, &mut t, false, false, true) {
+            Err(Error::Execution(ExecutionError::NotEnoughBaseQuota { required, got })) => {
+                assert_eq!(required, 1_000.into());
+                assert_eq!(got, 999.into());
+            }
+            other => panic!("expected NotEnoughBaseQuota, got {:?}", other),
+        }
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        let outcome = state.apply(&env_info, &mut t, false, false, true).unwrap();
+        assert_eq!(outcome.receipt.error, None);
+        assert_eq!(outcome.receipt.gas_used, 1_000.into());
+    }
+
+    #[test]
+    fn exhausted_quota_budget_is_rejected() {
+        let mut state = get_temp_state();
+        state.check_quota_budget = true;
+        state.base_quota = 1_000.into();
+        let sender = Address::from(0x1234);
+        let mut env_info = EnvInfo::default();
+        env_info.account_gas_limit = 999.into();
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 999.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&env_info, &mut t, false, false, true) {
+            Err(Error::Execution(ExecutionError::InsufficientQuota {
+                remaining,
+                base_quota,
+            })) => {
+                assert_eq!(remaining, U256::from(999));
+                assert_eq!(base_quota, U256::from(1_000));
+            }
+            other => panic!("expected InsufficientQuota, got {:?}", other),
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+        assert!(!state.exists(&sender).unwrap());
+
+        // without quota checks the budget is not consulted
+        state.apply(&env_info, &mut t, false, false, false).unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(1));
+    }
+
+    #[test]
+    fn expired_transaction_is_rejected() {
+        let mut state = get_temp_state();
+        state.check_valid_until_block = true;
+        let sender = Address::from(0x1234);
+        let mut env_info = EnvInfo::default();
+        env_info.number = 101;
+
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            block_limit: 100,
+            ..Default::default()
+        }.fake_sign(sender);
+        match state.apply(&env_info, &mut t, false, false, false) {
+            Err(Error::Execution(ExecutionError::InvalidUntilBlock {
+                valid_until_block,
+                block_number,
+            })) => {
+                assert_eq!(valid_until_block, 100);
+                assert_eq!(block_number, 101);
+            }
+            other => panic!("expected InvalidUntilBlock, got {:?}", other),
+        }
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+
+        env_info.number = 100;
+        state.apply(&env_info, &mut t, false, false, false).unwrap();
+        assert_eq!(state.nonce(&sender).unwrap(), U256::from(1));
+    }
+
+    #[test]
+    fn should_work_when_cloned() {
+        // init_log();
+
+        let a = Address::zero();
+
+        let mut state = {
+            let mut state = get_temp_state();
+            assert_eq!(state.exists(&a).unwrap(), false);
+            state.inc_nonce(&a).unwrap();
+            state.commit().unwrap();
+            state.clone()
+        };
+
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+    }
+
+    // #[test]
+    // fn should_trace_failed_create_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Create,
+    //             value: 100.into(),
+    //             data: FromHex::from_hex("5b600056").unwrap(),
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Create(trace::Create {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             value: 100.into(),
+    //             gas: 78792.into(),
+    //             init: vec![91, 96, 0, 86],
+    //         }),
+    //         result: trace::Res::FailedCreate(TraceError::OutOfGas),
+    //         subtraces: 0
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_call_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(3),
+    //             output: vec![]
+    //         }),
+    //         subtraces: 0,
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_basic_call_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(0),
+    //             output: vec![]
+    //         }),
+    //         subtraces: 0,
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_call_transaction_to_builtin() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = &*Spec::new_test().engine;
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0x1.into()),
+    //             value: 0.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     let result = state.apply(&info, engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: "0000000000000000000000000000000000000001".into(),
+    //             value: 0.into(),
+    //             gas: 79_000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(3000),
+    //             output: vec![]
+    //         }),
+    //         subtraces: 0,
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_not_trace_subcall_transaction_to_builtin() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = &*Spec::new_test().engine;
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 0.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("600060006000600060006001610be0f1").unwrap())
+    //         .unwrap();
+    //     let result = state.apply(&info, engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 0.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(28_061),
+    //             output: vec![]
+    //         }),
+    //         subtraces: 0,
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_not_trace_callcode() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = &*Spec::new_test().engine;
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 0.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006000600b611000f2").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     let result = state.apply(&info, engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 0.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: 64.into(),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xa.into(),
+    //             value: 0.into(),
+    //             gas: 4096.into(),
+    //             input: vec![],
+    //             call_type: CallType::CallCode,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: 3.into(),
+    //             output: vec![],
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_not_trace_delegatecall() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     info.number = 0x789b0;
+    //     let engine = &*Spec::new_test().engine;
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 0.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("6000600060006000600b618000f4").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     let result = state.apply(&info, engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 0.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(61),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 0.into(),
+    //             gas: 32768.into(),
+    //             input: vec![],
+    //             call_type: CallType::DelegateCall,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: 3.into(),
+    //             output: vec![],
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_failed_call_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(), FromHex::from_hex("5b600056").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
+    //         subtraces: 0,
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_call_with_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(69),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xb.into(),
+    //             value: 0.into(),
+    //             gas: 78934.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(3),
+    //             output: vec![]
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_call_with_basic_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006045600b6000f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(31761),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xb.into(),
+    //             value: 69.into(),
+    //             gas: 2300.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult::default()),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_not_trace_call_with_invalid_basic_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("600060006000600060ff600b6000f1").unwrap())
+    //         .unwrap(); // not enough funds.
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(31761),
+    //             output: vec![]
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_failed_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![], //600480600b6000396000f35b600056
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(), FromHex::from_hex("5b600056").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(79_000),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xb.into(),
+    //             value: 0.into(),
+    //             gas: 78934.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_call_with_subcall_with_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(),
+    //                    FromHex::from_hex("60006000600060006000600c602b5a03f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xc.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(135),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xb.into(),
     //             value: 0.into(),
-    //             gas: 32768.into(),
+    //             gas: 78934.into(),
     //             input: vec![],
-    //             call_type: CallType::DelegateCall,
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(69),
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0, 0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xb.into(),
+    //             to: 0xc.into(),
+    //             value: 0.into(),
+    //             gas: 78868.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(3),
+    //             output: vec![]
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_failed_subcall_with_subcall_transaction() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![], //600480600b6000396000f35b600056
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xb.into(),
+    //                    FromHex::from_hex("60006000600060006000600c602b5a03f1505b601256").unwrap())
+    //         .unwrap();
+    //     state
+    //         .init_code(&0xc.into(), FromHex::from_hex("6000").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(79_000),
+    //             output: vec![]
+    //         })
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 1,
+    //             action: trace::Action::Call(trace::Call {
+    //             from: 0xa.into(),
+    //             to: 0xb.into(),
+    //             value: 0.into(),
+    //             gas: 78934.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
+    //         }),
+    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
+    //     }, FlatTrace {
+    //         trace_address: vec![0, 0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: 0xb.into(),
+    //             to: 0xc.into(),
+    //             value: 0.into(),
+    //             gas: 78868.into(),
+    //             call_type: CallType::Call,
+    //             input: vec![],
+    //         }),
+    //         result: trace::Res::Call(trace::CallResult {
+    //             gas_used: U256::from(3),
+    //             output: vec![]
+    //         }),
+    //     }];
+
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    // #[test]
+    // fn should_trace_suicide() {
+    //     init_log();
+
+    //     let mut state = get_temp_state();
+
+    //     let mut info = EnvInfo::default();
+    //     info.gas_limit = 1_000_000.into();
+    //     let engine = TestEngine::new(5);
+
+    //     let t = Transaction {
+    //             nonce: 0.into(),
+    //             gas_price: 0.into(),
+    //             gas: 100_000.into(),
+    //             action: Action::Call(0xa.into()),
+    //             value: 100.into(),
+    //             data: vec![],
+    //         }
+    //         .sign(&secret(), None);
+
+    //     state
+    //         .init_code(&0xa.into(),
+    //                    FromHex::from_hex("73000000000000000000000000000000000000000bff").unwrap())
+    //         .unwrap();
+    //     state
+    //         .add_balance(&0xa.into(), &50.into(), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     state
+    //         .add_balance(&t.sender(), &100.into(), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    //     let expected_trace = vec![FlatTrace {
+    //         trace_address: Default::default(),
+    //         subtraces: 1,
+    //         action: trace::Action::Call(trace::Call {
+    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
+    //             to: 0xa.into(),
+    //             value: 100.into(),
+    //             gas: 79000.into(),
+    //             input: vec![],
+    //             call_type: CallType::Call,
     //         }),
     //         result: trace::Res::Call(trace::CallResult {
     //             gas_used: 3.into(),
-    //             output: vec![],
+    //             output: vec![]
+    //         }),
+    //     }, FlatTrace {
+    //         trace_address: vec![0].into_iter().collect(),
+    //         subtraces: 0,
+    //         action: trace::Action::Suicide(trace::Suicide {
+    //             address: 0xa.into(),
+    //             refund_address: 0xb.into(),
+    //             balance: 150.into(),
     //         }),
+    //         result: trace::Res::None,
     //     }];
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    //     assert_eq!(result.trace, expected_trace);
+    // }
+
+    #[test]
+    fn code_from_database() {
+        let a = Address::zero();
+        let (root, db) = {
+            let mut state = get_temp_state();
+            state
+                .require_or_from(&a, false, false, || Account::new_contract(0.into()), |_| {})
+                .unwrap();
+            state.init_code(&a, vec![1, 2, 3]).unwrap();
+            assert_eq!(
+                state.code(&a).unwrap(),
+                Some(Arc::new([1u8, 2, 3].to_vec()))
+            );
+
+            state.commit().unwrap();
+            assert_eq!(
+                state.code(&a).unwrap(),
+                Some(Arc::new([1u8, 2, 3].to_vec()))
+            );
+            state.drop()
+        };
+
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+
+        assert_eq!(
+            state.code(&a).unwrap(),
+            Some(Arc::new([1u8, 2, 3].to_vec()))
+        );
+    }
+
+    #[test]
+    fn abi_from_database() {
+        let a = Address::zero();
+        let (root, db) = {
+            let mut state = get_temp_state();
+            state
+                .require_or_from(&a, false, false, || Account::new_contract(0.into()), |_| {})
+                .unwrap();
+            state.init_abi(&a, vec![1, 2, 3]).unwrap();
+            assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
+
+            state.commit().unwrap();
+            assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
+            state.drop()
+        };
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+
+        assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
+    }
+
+    #[test]
+    fn storage_at_from_database() {
+        let a = Address::zero();
+        let (root, db) = {
+            let mut state = get_temp_state();
+            state
+                .set_storage(&a, H256::from(1u64), H256::from(69u64))
+                .unwrap();
+            state.commit().unwrap();
+            state.drop()
+        };
+
+        let s = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert_eq!(
+            s.storage_at(&a, &H256::from(1u64)).unwrap(),
+            H256::from(69u64)
+        );
+    }
+
+    // Flush the journal overlay of `state` so that nodes of earlier roots stay readable.
+    fn journal(state: &mut State<StateDB>, kvdb: &Arc<KeyValueDB>, era: u64) {
+        let mut batch = kvdb.transaction();
+        let root = *state.root();
+        state.db.journal_under(&mut batch, era, &root).unwrap();
+        kvdb.write(batch).unwrap();
+    }
+
+    #[test]
+    fn rebuild_account_bloom() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let absent: Address = 0xdead.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.commit().unwrap();
+        assert!(!state.definitely_absent(&absent));
+
+        state.rebuild_bloom().unwrap();
+        assert!(!state.definitely_absent(&a));
+        assert!(!state.definitely_absent(&b));
+        assert!(state.definitely_absent(&c));
+        assert!(state.definitely_absent(&absent));
+
+        state.inc_nonce(&c).unwrap();
+        state.commit().unwrap();
+        assert!(!state.definitely_absent(&c));
+
+        state.clear_bloom();
+        assert!(!state.definitely_absent(&absent));
+        state.rebuild_bloom().unwrap();
+        assert!(!state.definitely_absent(&c));
+        assert!(state.definitely_absent(&absent));
+    }
+
+    #[test]
+    fn follower_reads_latest_commit() {
+        let a = Address::zero();
+        let (mut writer, kvdb) = get_temp_state_with_kvdb();
+        writer.commit().unwrap();
+        journal(&mut writer, &kvdb, 0);
+
+        let open = |writer: &State<StateDB>| {
+            State::from_existing(writer.db.boxed_clone(), *writer.root(), U256::zero(), Default::default()).unwrap()
+        };
+        let mut follower = open(&writer);
+        follower.follow_commits = true;
+        let pinned = open(&writer);
+
+        writer.inc_nonce(&a).unwrap();
+        writer.commit().unwrap();
+        journal(&mut writer, &kvdb, 1);
+
+        assert_eq!(follower.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(pinned.nonce(&a).unwrap(), U256::zero());
+    }
+
+    #[test]
+    fn refresh_reloads_stale_account() {
+        let a = Address::zero();
+        let (mut writer, kvdb) = get_temp_state_with_kvdb();
+        writer.commit().unwrap();
+        journal(&mut writer, &kvdb, 0);
+
+        let reader =
+            State::from_existing(writer.db.boxed_clone(), *writer.root(), U256::zero(), Default::default()).unwrap();
+        assert!(!reader.follow_commits);
+        assert_eq!(reader.nonce(&a).unwrap(), U256::zero());
+
+        writer.inc_nonce(&a).unwrap();
+        writer.commit().unwrap();
+        journal(&mut writer, &kvdb, 1);
+
+        // the cached entry is stale until refreshed
+        assert_eq!(reader.nonce(&a).unwrap(), U256::zero());
+        reader.refresh(&a).unwrap();
+        assert_eq!(reader.nonce(&a).unwrap(), U256::from(1));
+
+        // nothing committed through this backend yet
+        assert!(get_temp_state().refresh(&a).is_err());
+    }
+
+    #[test]
+    fn storage_at_historical_root() {
+        let a = Address::zero();
+        let key = H256::from(1u64);
+        let (mut state, kvdb) = get_temp_state_with_kvdb();
+        let mut roots = Vec::new();
+        for v in 1u64..4 {
+            state.set_storage(&a, key, H256::from(v)).unwrap();
+            state.commit().unwrap();
+            journal(&mut state, &kvdb, v);
+            roots.push(*state.root());
+        }
+
+        for (i, root) in roots.into_iter().enumerate() {
+            assert_eq!(
+                storage_at_root(&state.db, &state.factories, root, &a, &key).unwrap(),
+                H256::from(i as u64 + 1)
+            );
+            assert_eq!(
+                storage_at_root(&state.db, &state.factories, root, &a, &H256::from(2u64)).unwrap(),
+                H256::new()
+            );
+            assert_eq!(
+                storage_at_root(&state.db, &state.factories, root, &0xff.into(), &key).unwrap(),
+                H256::new()
+            );
+        }
+    }
+
+    #[test]
+    fn code_at_historical_root() {
+        let a: Address = 0xa.into();
+        let eoa: Address = 0xb.into();
+        let (mut state, kvdb) = get_temp_state_with_kvdb();
+        state.inc_nonce(&eoa).unwrap();
+        let mut roots = Vec::new();
+        for (era, code) in vec![vec![0x60, 0x00], vec![0x60, 0x01]].into_iter().enumerate() {
+            state.reset_code(&a, code).unwrap();
+            state.commit().unwrap();
+            journal(&mut state, &kvdb, era as u64);
+            roots.push(*state.root());
+        }
+
+        assert_eq!(
+            code_at_root(&state.db, &state.factories, None, roots[0], &a).unwrap(),
+            Some(Arc::new(vec![0x60, 0x00]))
+        );
+        assert_eq!(
+            code_at_root(&state.db, &state.factories, None, roots[1], &a).unwrap(),
+            Some(Arc::new(vec![0x60, 0x01]))
+        );
+        assert_eq!(code_at_root(&state.db, &state.factories, None, roots[1], &eoa).unwrap(), None);
+        assert_eq!(code_at_root(&state.db, &state.factories, None, roots[1], &0xff.into()).unwrap(), None);
+    }
+
+    #[test]
+    fn code_at_root_reads_blob_store() {
+        let a: Address = 0xa.into();
+        let store = Arc::new(blob_store::MemoryBlobStore::default());
+        let mut state = get_temp_state();
+        state.blob_store = Some(store.clone());
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+
+        let root = *state.root();
+        assert_eq!(code_at_root(&state.db, &state.factories, None, root, &a).unwrap(), None);
+        assert_eq!(
+            code_at_root(&state.db, &state.factories, Some(&*store), root, &a).unwrap(),
+            Some(Arc::new(vec![0x60, 0x00]))
+        );
+    }
+
+    #[test]
+    fn cache_memory_estimate_grows_with_code() {
+        let mut state = get_temp_state();
+        assert_eq!(state.cache_len(), 0);
+        assert_eq!(state.cache_memory_estimate(), 0);
+
+        state.inc_nonce(&0xa.into()).unwrap();
+        let small = state.cache_memory_estimate();
+        assert_eq!(state.cache_len(), 1);
+
+        state.init_code(&0xb.into(), vec![0x60; 24 * 1024]).unwrap();
+        assert_eq!(state.cache_len(), 2);
+        assert!(state.cache_memory_estimate() >= small + 24 * 1024);
+    }
+
+    #[test]
+    fn deferred_code_hashing_hashes_once() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.defer_code_hashing = true;
+
+        let before = account::HASH_COUNT.with(|count| count.get());
+        state.reset_code(&a, vec![0x60, 0x00]).unwrap();
+        state.reset_code(&a, vec![0x60, 0x01]).unwrap();
+        state.commit().unwrap();
+        assert_eq!(account::HASH_COUNT.with(|count| count.get()) - before, 1);
+
+        assert_eq!(state.code_hash(&a).unwrap(), vec![0x60u8, 0x01].crypt_hash());
+        assert_eq!(state.code(&a).unwrap(), Some(Arc::new(vec![0x60u8, 0x01])));
+    }
+
+    #[test]
+    fn storage_snapshot_survives_code_reset() {
+        let a: Address = 0xa.into();
+        let (root, db) = get_temp_state().drop();
+        let mut state =
+            State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.set_storage(&a, 3.into(), 4.into()).unwrap();
+        state.commit().unwrap();
+        let before = state.storage_snapshot(&a).unwrap().unwrap();
+        assert_eq!(before.len(), 2);
+
+        state.reset_code(&a, vec![0x60, 0x01]).unwrap();
+        assert_eq!(state.storage_snapshot(&a).unwrap().unwrap(), before);
+        state.commit().unwrap();
+        let after = state.storage_snapshot(&a).unwrap().unwrap();
+        assert_eq!(after, before);
+        assert!(storage_diff(&before, &after).is_empty());
+
+        state.set_storage(&a, 1.into(), H256::zero()).unwrap();
+        state.set_storage(&a, 5.into(), 6.into()).unwrap();
+        let changed = state.storage_snapshot(&a).unwrap().unwrap();
+        let diff = storage_diff(&before, &changed);
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[&H256::from(1)], (Some(2.into()), None));
+        assert_eq!(diff[&H256::from(5)], (None, Some(6.into())));
+
+        assert_eq!(get_temp_state().storage_snapshot(&a).unwrap(), None);
+    }
+
+    #[test]
+    fn fat_trie_enumerates_addresses() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let (root, db) = get_temp_state().drop();
+
+        let mut state =
+            State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+        assert_eq!(state.committed_addresses().unwrap(), Some(vec![]));
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&b, 1.into(), 2.into()).unwrap();
+        state.commit().unwrap();
+
+        let mut addresses = state.committed_addresses().unwrap().unwrap();
+        addresses.sort();
+        assert_eq!(addresses, vec![a, b]);
+        assert_eq!(get_temp_state().committed_addresses().unwrap(), None);
+    }
+
+    #[test]
+    fn subtree_roots_are_stable() {
+        let addresses: Vec<Address> = vec![
+            "1000000000000000000000000000000000000001".into(),
+            "1000000000000000000000000000000000000002".into(),
+            "f000000000000000000000000000000000000001".into(),
+            "f000000000000000000000000000000000000002".into(),
+        ];
+        let low = |a: &Address| a[0] < 0x80;
+        let high = |a: &Address| a[0] >= 0x80;
+        let shard_roots = |order: &[usize]| {
+            let (root, db) = get_temp_state().drop();
+            let mut state =
+                State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+            for &i in order {
+                state.inc_nonce(&addresses[i]).unwrap();
+                state.commit().unwrap();
+            }
+            assert_eq!(state.subtree_root(|_| true).unwrap(), *state.root());
+            (state.subtree_root(&low).unwrap(), state.subtree_root(&high).unwrap())
+        };
+
+        let (low_root, high_root) = shard_roots(&[0, 1, 2, 3]);
+        assert_ne!(low_root, high_root);
+        assert_eq!(shard_roots(&[3, 1, 2, 0]), (low_root, high_root));
+
+        // secure tries are keyed by address hash and cannot be partitioned
+        let mut state = get_temp_state();
+        state.inc_nonce(&addresses[0]).unwrap();
+        state.commit().unwrap();
+        assert!(state.subtree_root(&low).is_err());
+    }
+
+    #[test]
+    fn interned_storage_values_bound_memory() {
+        let a: Address = 0xa.into();
+        let value: H256 = 0x45.into();
+        let mut plain = get_temp_state();
+        let mut interned = get_temp_state();
+        interned.intern_storage_values = true;
+
+        for i in 0..1000u64 {
+            plain.set_storage(&a, i.into(), value).unwrap();
+            interned.set_storage(&a, i.into(), value).unwrap();
+        }
+        // plain keeps every value inline, interned keeps a pointer per slot and one shared value
+        let saved = plain.cache_memory_estimate() - interned.cache_memory_estimate();
+        let pointer_saving = ::std::mem::size_of::<H256>() - ::std::mem::size_of::<Arc<H256>>();
+        assert_eq!(saved, 1000 * pointer_saving - ::std::mem::size_of::<H256>());
+
+        interned.commit().unwrap();
+        plain.commit().unwrap();
+        assert_eq!(interned.root(), plain.root());
+        assert_eq!(interned.storage_at(&a, &999.into()).unwrap(), value);
+    }
+
+    #[test]
+    fn total_storage_slots_counts_all_contracts() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        assert_eq!(state.total_storage_slots().unwrap(), 0);
+
+        for key in 1..4u64 {
+            state.set_storage(&a, key.into(), 0x45.into()).unwrap();
+        }
+        for key in 1..3u64 {
+            state.set_storage(&b, key.into(), 0x46.into()).unwrap();
+        }
+        // uncommitted slots are not counted
+        assert_eq!(state.total_storage_slots().unwrap(), 0);
+        state.commit().unwrap();
+
+        let mut walked = Vec::new();
+        let slots = state
+            .total_storage_slots_with_progress(|accounts| walked.push(accounts))
+            .unwrap();
+        assert_eq!(slots, 5);
+        assert_eq!(walked, vec![1, 2]);
+    }
+
+    #[test]
+    fn require_code_and_abi_opens_account_db_once() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&a, vec![0x01, 0x02]).unwrap();
+        state.commit().unwrap();
+        state.clear();
+
+        let before = account_db::READONLY_OPENS.with(|opens| opens.get());
+        {
+            let account = state.require(&a, true, true).unwrap();
+            assert_eq!(account.code(), Some(Arc::new(vec![0x60, 0x00])));
+            assert_eq!(account.abi(), Some(Arc::new(vec![0x01, 0x02])));
+        }
+        assert_eq!(account_db::READONLY_OPENS.with(|opens| opens.get()) - before, 1);
+    }
+
+    #[test]
+    fn has_abi_does_not_load_abi() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&a, vec![0x01, 0x02]).unwrap();
+        state.init_code(&b, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+        state.clear();
+
+        assert!(state.has_abi(&a).unwrap());
+        assert!(!state.has_abi(&b).unwrap());
+        assert!(!state.has_abi(&0xc.into()).unwrap());
+
+        let cache = state.cache.borrow();
+        let account = cache[&a].account.as_ref().unwrap();
+        assert!(!account.is_abi_cached());
+    }
+
+    #[test]
+    fn require_stats_count_code_and_abi_loads() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&a, vec![0x01, 0x02]).unwrap();
+        state.commit().unwrap();
+        state.clear();
+        state.collect_metrics = true;
+
+        state.code(&a).unwrap();
+        assert_eq!(
+            state.require_stats(),
+            RequireStats {
+                code: 1,
+                ..Default::default()
+            }
+        );
+        // already cached, no further reads
+        state.code(&a).unwrap();
+        state.abi_size(&a).unwrap();
+        assert_eq!(
+            state.require_stats(),
+            RequireStats {
+                code: 1,
+                abi_size: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn replace_storage_drops_old_slots() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        for key in 1..4u64 {
+            state.set_storage(&a, key.into(), 0x45.into()).unwrap();
+        }
+        state.commit().unwrap();
+
+        let mut storage = HashMap::new();
+        storage.insert(H256::from(3), H256::from(0x46));
+        storage.insert(H256::from(4), H256::from(0x47));
+        state.checkpoint().unwrap();
+        state.replace_storage(&a, storage.clone()).unwrap();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), H256::new());
+        state.revert_to_checkpoint();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 0x45.into());
+
+        state.replace_storage(&a, storage.clone()).unwrap();
+        state.commit().unwrap();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), H256::new());
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), H256::new());
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 0x46.into());
+        assert_eq!(state.storage_at(&a, &4.into()).unwrap(), 0x47.into());
+
+        let mut expected = get_temp_state();
+        for (key, value) in storage {
+            expected.set_storage(&a, key, value).unwrap();
+        }
+        expected.commit().unwrap();
+        assert_eq!(state.root(), expected.root());
+    }
+
+    #[test]
+    fn clear_storage_keeps_account() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.set_storage(&a, 1.into(), 0x45.into()).unwrap();
+        state.set_storage(&a, 2.into(), 0x46.into()).unwrap();
+        state.commit().unwrap();
+
+        state.checkpoint().unwrap();
+        state.clear_storage(&a).unwrap();
+        state.revert_to_checkpoint();
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 0x46.into());
+
+        state.clear_storage(&a).unwrap();
+        state.commit().unwrap();
+        assert_eq!(state.storage_root(&a).unwrap(), Some(HASH_NULL_RLP));
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), H256::new());
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(state.code(&a).unwrap(), Some(Arc::new(vec![0x60, 0x00])));
+    }
+
+    #[test]
+    fn kill_accounts_in_one_call() {
+        let accounts: Vec<Address> = vec![0xa.into(), 0xb.into(), 0xc.into()];
+        let mut state = get_temp_state();
+        for a in &accounts {
+            state.inc_nonce(a).unwrap();
+        }
+        state.commit().unwrap();
+
+        state.checkpoint().unwrap();
+        state.kill_accounts(&accounts).unwrap();
+        assert!(accounts.iter().all(|a| !state.exists(a).unwrap()));
+        state.revert_to_checkpoint();
+        assert!(accounts.iter().all(|a| state.exists(a).unwrap()));
+
+        state.kill_accounts(&accounts).unwrap();
+        state.commit().unwrap();
+        state.clear();
+        assert!(accounts.iter().all(|a| !state.exists(a).unwrap()));
+        assert_eq!(*state.root(), HASH_NULL_RLP);
+    }
+
+    #[test]
+    fn reject_oversized_batches() {
+        let accounts: Vec<Address> = vec![0xa.into(), 0xb.into(), 0xc.into()];
+        let mut state = get_temp_state();
+        for a in &accounts {
+            state.inc_nonce(a).unwrap();
+        }
+        state.max_batch_size = 2;
+        match state.kill_accounts(&accounts) {
+            Err(Error::BatchTooLarge { size, limit }) => assert_eq!((size, limit), (3, 2)),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(accounts.iter().all(|a| state.exists(a).unwrap()));
+        state.kill_accounts(&accounts[..2]).unwrap();
+        assert!(!state.exists(&accounts[1]).unwrap());
+
+        let too_large = |result: Result<(), Error>| match result {
+            Err(Error::BatchTooLarge { size, limit }) => (size, limit) == (3, 2),
+            _ => false,
+        };
+        let keys: Vec<H256> = vec![1.into(), 2.into(), 3.into()];
+        assert!(too_large(state.storage_multiproof(&accounts[2], &keys).map(|_| ())));
+        let storage: HashMap<H256, H256> = keys.iter().map(|key| (*key, 1.into())).collect();
+        assert!(too_large(state.replace_storage(&accounts[2], storage)));
+        assert_eq!(state.storage_at(&accounts[2], &1.into()).unwrap(), H256::zero());
+        let changes: Vec<(Address, Option<Bytes>)> = accounts.iter().map(|a| (*a, None)).collect();
+        assert!(too_large(state.incremental_root(&changes).map(|_| ())));
+        assert!(too_large(state.commit_accounts(&accounts)));
+        state.commit_accounts(&accounts[..2]).unwrap();
+    }
+
+    #[test]
+    fn commit_sink_receives_committed_changes() {
+        use std::sync::mpsc::channel;
+
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.commit().unwrap();
+
+        let (sender, receiver) = channel();
+        state.commit_sink = Some(sender);
+        state.inc_nonce(&a).unwrap();
+        state.kill_account(&b);
+        state.set_storage(&c, 1.into(), 2.into()).unwrap();
+        // reverted and unchanged accounts are not reported
+        state.checkpoint().unwrap();
+        state.inc_nonce(&0xd.into()).unwrap();
+        state.revert_to_checkpoint();
+        state.kill_account(&0xe.into());
+        state.commit().unwrap();
+
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                CommitEvent::AccountCreated(c),
+                CommitEvent::AccountUpdated(a),
+                CommitEvent::AccountDeleted(b),
+                CommitEvent::StorageChanged {
+                    address: c,
+                    key: 1.into(),
+                    value: 2.into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_account_storage_detects_missing_nodes() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.inc_nonce(&0xb.into()).unwrap();
+        state.commit().unwrap();
+        assert!(state.validate_account_storage(&a).unwrap());
+        assert!(state.validate_account_storage(&0xb.into()).unwrap());
+        assert!(state.validate_account_storage(&0xc.into()).unwrap());
+
+        // drop the storage nodes as an incomplete import would
+        let storage_root = state.storage_root(&a).unwrap().unwrap();
+        let address_hash = a.crypt_hash();
+        state
+            .factories
+            .accountdb
+            .create(state.db.as_hashdb_mut(), address_hash)
+            .remove(&storage_root);
+        assert!(!state.validate_account_storage(&a).unwrap());
+    }
+
+    #[test]
+    fn code_is_shared_between_states() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
+
+        let first = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        let second = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        let first_code = first.code(&a).unwrap().unwrap();
+        let second_code = second.code(&a).unwrap().unwrap();
+        assert_eq!(*first_code, vec![0x60, 0x00]);
+        assert!(Arc::ptr_eq(&first_code, &second_code));
+    }
+
+    #[test]
+    fn code_and_abi_are_committed_to_blob_store() {
+        let a: Address = 0xa.into();
+        let code = vec![0x60, 0x00];
+        let abi = vec![0x01, 0x02, 0x03];
+        let store = Arc::new(blob_store::MemoryBlobStore::default());
+        let mut state = get_temp_state();
+        state.blob_store = Some(store.clone());
+        state.init_code(&a, code.clone()).unwrap();
+        state.init_abi(&a, abi.clone()).unwrap();
+        state.commit().unwrap();
+        assert_eq!(store.len(), 2);
+
+        let code_hash = state.code_hash(&a).unwrap();
+        let account_db = state
+            .factories
+            .accountdb
+            .readonly(state.db.as_hashdb(), a.crypt_hash());
+        assert!(!account_db.contains(&code_hash));
+
+        let mut reader = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        reader.blob_store = Some(store);
+        assert_eq!(*reader.code(&a).unwrap().unwrap(), code);
+        assert_eq!(*reader.abi(&a).unwrap().unwrap(), abi);
+    }
+
+    #[test]
+    fn code_by_hash_reads_cached_code() {
+        let a: Address = 0xa.into();
+        let code = vec![0x60, 0x01];
+        let mut state = get_temp_state();
+        state.init_code(&a, code.clone()).unwrap();
+        state.commit().unwrap();
+        let hash = state.code_hash(&a).unwrap();
+        assert_eq!(*state.code_by_hash(&hash).unwrap(), code);
+
+        let other = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        assert!(other.code_by_hash(&hash).is_none());
+        // loading the code through any state makes it available by hash
+        other.code(&a).unwrap();
+        let fresh = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(*fresh.code_by_hash(&hash).unwrap(), code);
+        assert!(fresh.code_by_hash(&H256::from(0x1234)).is_none());
+    }
+
+    #[test]
+    fn cache_fingerprint_is_order_independent() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut first = get_temp_state();
+        first.inc_nonce(&a).unwrap();
+        first.set_storage(&b, 1.into(), 2.into()).unwrap();
+        first.set_storage(&b, 3.into(), 4.into()).unwrap();
+
+        let mut second = get_temp_state();
+        second.set_storage(&b, 3.into(), 4.into()).unwrap();
+        second.set_storage(&b, 1.into(), 2.into()).unwrap();
+        second.inc_nonce(&a).unwrap();
+        assert_eq!(first.cache_fingerprint(), second.cache_fingerprint());
+
+        second.set_storage(&b, 1.into(), 5.into()).unwrap();
+        assert!(first.cache_fingerprint() != second.cache_fingerprint());
+        second.set_storage(&b, 1.into(), 2.into()).unwrap();
+        assert_eq!(first.cache_fingerprint(), second.cache_fingerprint());
+        second.kill_account(&a);
+        assert!(first.cache_fingerprint() != second.cache_fingerprint());
+    }
+
+    #[test]
+    fn storage_multiproof_proves_all_keys() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        for key in 1..33u64 {
+            state.set_storage(&a, key.into(), (key + 100).into()).unwrap();
+        }
+        state.commit().unwrap();
+        let storage_root = state.storage_root(&a).unwrap().unwrap();
+
+        let keys: Vec<H256> = vec![3.into(), 17.into(), 31.into()];
+        let proof = state.storage_multiproof(&a, &keys).unwrap();
+        let mut seen = HashSet::new();
+        assert!(proof.iter().all(|node| seen.insert(node.crypt_hash())));
+
+        let mut db = MemoryDB::new();
+        for node in &proof {
+            db.insert(node);
+        }
+        let storage = state.factories.trie.readonly(&db, &storage_root).unwrap();
+        for key in &keys {
+            let value: U256 = storage.get_with(key, ::rlp::decode).unwrap().unwrap();
+            assert_eq!(value, U256::from(&**key) + U256::from(100));
+        }
+    }
+
+    #[test]
+    fn permissions_root_is_order_independent() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let read = Resource::new(0x1.into(), vec![0; 4]);
+        let write = Resource::new(0x2.into(), vec![1; 4]);
+
+        let mut first = get_temp_state();
+        first.senders.insert(a);
+        first.senders.insert(b);
+        first.creators.insert(b);
+        first.account_permissions.insert(a, vec![read.clone(), write.clone()]);
+        first.account_permissions.insert(b, vec![read.clone()]);
+
+        let mut second = get_temp_state();
+        second.account_permissions.insert(b, vec![read.clone()]);
+        second.account_permissions.insert(a, vec![write.clone(), read.clone()]);
+        second.creators.insert(b);
+        second.senders.insert(b);
+        second.senders.insert(a);
+        assert_eq!(first.permissions_root(), second.permissions_root());
+
+        let root = first.permissions_root();
+        first.creators.insert(a);
+        assert!(first.permissions_root() != root);
+    }
+
+    #[test]
+    fn require_reports_trie_read_errors() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.inc_nonce(&0xb.into()).unwrap();
+        state.commit().unwrap();
+        state.clear();
+
+        // break the backend by dropping every node below the state root
+        let root = *state.root();
+        let keys: Vec<H256> = state
+            .db
+            .as_hashdb()
+            .keys()
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| *key != root)
+            .collect();
+        for key in keys {
+            state.db.as_hashdb_mut().remove(&key);
+        }
+        match state.try_require_or_from(&a, false, false, || Account::new_basic(0.into()), |_| {}) {
+            Err(RequireError::TrieRead(TrieError::IncompleteDatabase(_))) => {}
+            other => panic!("expected TrieRead, got {:?}", other.map(|_| ())),
+        }
+        assert!(state.inc_nonce(&a).is_err());
+
+        state.db.as_hashdb_mut().remove(&root);
+        match state.try_require_or_from(&a, false, false, || Account::new_basic(0.into()), |_| {}) {
+            Err(RequireError::MissingStateRoot(missing)) => assert_eq!(missing, root),
+            other => panic!("expected MissingStateRoot, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn get_from_database() {
+        let a = Address::zero();
+        let (root, db) = {
+            let mut state = get_temp_state();
+            state.inc_nonce(&a).unwrap();
+            state.commit().unwrap();
+            assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+            state.drop()
+        };
+
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+    }
 
-    // #[test]
-    // fn should_trace_failed_call_transaction() {
-    //     init_log();
+    #[test]
+    fn remove() {
+        let a = Address::zero();
+        let mut state = get_temp_state();
+        assert_eq!(state.exists(&a).unwrap(), false);
+        assert_eq!(state.exists_and_not_null(&a).unwrap(), false);
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.exists(&a).unwrap(), true);
+        assert_eq!(state.exists_and_not_null(&a).unwrap(), true);
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+        state.kill_account(&a);
+        assert_eq!(state.exists(&a).unwrap(), false);
+        assert_eq!(state.exists_and_not_null(&a).unwrap(), false);
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+    }
 
-    //     let mut state = get_temp_state();
+    #[test]
+    fn empty_account_is_not_created() {
+        let a = Address::zero();
+        let db = get_temp_state_db();
+        let (root, db) = {
+            let mut state = State::new(db, U256::from(0u8), Default::default());
+            state.commit().unwrap();
+            state.drop()
+        };
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert!(!state.exists(&a).unwrap());
+        assert!(!state.exists_and_not_null(&a).unwrap());
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    #[test]
+    fn empty_account_exists_when_creation_forced() {
+        let a = Address::zero();
+        let db = get_temp_state_db();
+        let (root, db) = {
+            let mut state = State::new(db, U256::from(0u8), Default::default());
+            state.require(&a, false, false).unwrap();
+            state.commit().unwrap();
+            state.drop()
+        };
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert!(state.exists(&a).unwrap());
+        assert!(!state.exists_and_not_null(&a).unwrap());
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn remove_from_database() {
+        let a = Address::zero();
+        let (root, db) = {
+            let mut state = get_temp_state();
+            state.inc_nonce(&a).unwrap();
+            state.commit().unwrap();
+            assert_eq!(state.exists(&a).unwrap(), true);
+            assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+            state.drop()
+        };
 
-    //     state
-    //         .init_code(&0xa.into(), FromHex::from_hex("5b600056").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
-    //         subtraces: 0,
-    //     }];
+        let (root, db) = {
+            let mut state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+            assert_eq!(state.exists(&a).unwrap(), true);
+            assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+            state.kill_account(&a);
+            state.commit().unwrap();
+            assert_eq!(state.exists(&a).unwrap(), false);
+            assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+            state.drop()
+        };
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert_eq!(state.exists(&a).unwrap(), false);
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+    }
 
-    // #[test]
-    // fn should_trace_call_with_subcall_transaction() {
-    //     init_log();
+    #[test]
+    fn alter_nonce() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2u64));
+        state.commit().unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2u64));
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(3u64));
+        state.commit().unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(3u64));
+    }
 
-    //     let mut state = get_temp_state();
+    #[test]
+    fn nonce() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+        state.commit().unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    #[test]
+    fn ensure_cached() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+        state.require(&a, false, false).unwrap();
+        state.commit().unwrap();
+        if HASH_NAME == "sha3" {
+            assert_eq!(
+                state.root().hex(),
+                "98560ba094af6f0874e6a965207d24e049b76fcb8b94bee33d219a21d1636f83"
+            );
+        } else if HASH_NAME == "blake2b" {
+            assert_eq!(
+                state.root().hex(),
+                "1d20d29c3bef1ce4b24e171b3d94371176ccf6a5a624e184bc48f3c3be98e083"
+            );
+        }
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn checkpoint_basic() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+        state.checkpoint().unwrap();
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        state.discard_checkpoint();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        state.checkpoint().unwrap();
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2));
+        state.revert_to_checkpoint();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        state.verify_cache_consistency();
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+    #[test]
+    fn checkpoint_nested_cache_consistency() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+        let b: Address = 0xb.into();
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        state.verify_cache_consistency();
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(69),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xb.into(),
-    //             value: 0.into(),
-    //             gas: 78934.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(3),
-    //             output: vec![]
-    //         }),
-    //     }];
+        state.checkpoint().unwrap();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.verify_cache_consistency();
 
-    //     assert_eq!(result.trace, expected_trace);
+        state.checkpoint().unwrap();
+        state.init_code(&b, vec![0x60, 0x00]).unwrap();
+        state.set_storage(&a, 1.into(), 3.into()).unwrap();
+        state.verify_cache_consistency();
+        state.revert_to_checkpoint();
+        state.verify_cache_consistency();
+        assert!(!state.exists(&b).unwrap());
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 2.into());
+
+        state.checkpoint().unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.verify_cache_consistency();
+        state.discard_checkpoint();
+        state.verify_cache_consistency();
+
+        state.revert_to_checkpoint();
+        state.verify_cache_consistency();
+        assert!(!state.exists(&b).unwrap());
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), H256::new());
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+    }
+
+    // #[test]
+    // fn checkpoint_nested() {
+    //     let mut state = get_temp_state();
+    //     let a = Address::zero();
+    //     state.checkpoint();
+    //     state.checkpoint();
+    //     state
+    //         .add_balance(&a, &U256::from(69u64), CleanupMode::NoEmpty)
+    //         .unwrap();
+    //     assert_eq!(state.balance(&a).unwrap(), U256::from(69u64));
+    //     state.discard_checkpoint();
+    //     assert_eq!(state.balance(&a).unwrap(), U256::from(69u64));
+    //     state.revert_to_checkpoint();
+    //     assert_eq!(state.balance(&a).unwrap(), U256::from(0));
     // }
 
-    // #[test]
-    // fn should_trace_call_with_basic_subcall_transaction() {
-    //     init_log();
-
-    //     let mut state = get_temp_state();
+    #[test]
+    fn with_transaction_rolls_back_on_error() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+        let result: Result<(), Error> = state.with_transaction(|state| {
+            state.inc_nonce(&a)?;
+            state.set_storage(&b, 1.into(), 2.into())?;
+            Err(ExecutionError::Internal("abort".to_owned()).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(state.storage_at(&b, &1.into()).unwrap(), H256::new());
+        assert!(!state.exists(&b).unwrap());
+
+        let result = state.with_transaction(|state| {
+            state.inc_nonce(&a)?;
+            let inner: Result<(), Error> = state.with_transaction(|state| {
+                state.inc_nonce(&b)?;
+                Err(ExecutionError::Internal("abort".to_owned()).into())
+            });
+            assert!(inner.is_err());
+            Ok(state.nonce(&a)?)
+        });
+        assert_eq!(result.unwrap(), U256::from(2));
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2));
+        assert!(!state.exists(&b).unwrap());
+        state.verify_cache_consistency();
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn address_mapper_redirects_reads_and_writes() {
+        let offset = |a: &Address| {
+            let mut mapped = *a;
+            mapped[0] = 0xff;
+            mapped
+        };
+        let logical = Address::from(0x10);
+        let physical = offset(&logical);
+        let mut state = get_temp_state();
+        state.address_mapper = Some(Arc::new(offset));
+        state.inc_nonce(&logical).unwrap();
+        state.set_storage(&logical, 1.into(), 2.into()).unwrap();
+        state.commit().unwrap();
+        assert_eq!(state.nonce(&logical).unwrap(), U256::from(1));
+        assert_eq!(state.storage_at(&logical, &1.into()).unwrap(), 2.into());
+
+        state.address_mapper = None;
+        state.clear();
+        assert!(!state.exists(&logical).unwrap());
+        assert_eq!(state.nonce(&physical).unwrap(), U256::from(1));
+        assert_eq!(state.storage_at(&physical, &1.into()).unwrap(), 2.into());
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006045600b6000f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(31761),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xb.into(),
-    //             value: 69.into(),
-    //             gas: 2300.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult::default()),
-    //     }];
+    fn conflicting_states() -> (State<StateDB>, State<StateDB>) {
+        let a = Address::zero();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        let mut other = state.clone();
+        state.set_storage(&a, 1.into(), 10.into()).unwrap();
+        state.set_storage(&a, 2.into(), 20.into()).unwrap();
+        other.set_storage(&a, 1.into(), 11.into()).unwrap();
+        other.set_storage(&a, 3.into(), 33.into()).unwrap();
+        (state, other)
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    #[test]
+    fn merge_dirty_fails_on_conflict() {
+        let a = Address::zero();
+        let (mut state, other) = conflicting_states();
+        assert_eq!(
+            state.merge_dirty_from(&other, &ConflictPolicy::FailOnConflict),
+            Err(MergeConflict {
+                address: a,
+                key: Some(1.into()),
+            })
+        );
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), H256::zero());
+    }
 
-    // #[test]
-    // fn should_not_trace_call_with_invalid_basic_subcall_transaction() {
-    //     init_log();
+    #[test]
+    fn merge_dirty_prefers_other() {
+        let a = Address::zero();
+        let (mut state, other) = conflicting_states();
+        state.merge_dirty_from(&other, &ConflictPolicy::PreferOther).unwrap();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 11.into());
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 20.into());
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 33.into());
+    }
 
-    //     let mut state = get_temp_state();
+    #[test]
+    fn merge_dirty_prefers_self() {
+        let a = Address::zero();
+        let (mut state, other) = conflicting_states();
+        state.merge_dirty_from(&other, &ConflictPolicy::PreferSelf).unwrap();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 20.into());
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 33.into());
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    #[test]
+    fn merge_dirty_custom_policy_decides_per_slot() {
+        let a = Address::zero();
+        let (mut state, mut other) = conflicting_states();
+        other.inc_nonce(&a).unwrap();
+        // the account of the other state wins, conflicting slots keep our value
+        let policy = ConflictPolicy::CustomFn(Box::new(|_: &Address, key: Option<&H256>| key.is_none()));
+        state.merge_dirty_from(&other, &policy).unwrap();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2));
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 20.into());
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 33.into());
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn account_view_shows_pending_changes() {
+        let a = Address::zero();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        state.inc_nonce(&a).unwrap();
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("600060006000600060ff600b6000f1").unwrap())
-    //         .unwrap(); // not enough funds.
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(31761),
-    //             output: vec![]
-    //         }),
-    //     }];
+        let view = state.account_view(&a).unwrap();
+        assert_eq!(view.committed.unwrap().nonce, U256::from(1));
+        assert_eq!(view.pending.unwrap().nonce, U256::from(2));
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+        state.commit().unwrap();
+        let view = state.account_view(&a).unwrap();
+        assert_eq!(view.committed.unwrap().nonce, U256::from(2));
+        assert_eq!(view.pending, None);
+    }
 
-    // #[test]
-    // fn should_trace_failed_subcall_transaction() {
-    //     init_log();
+    #[test]
+    fn storage_cache_is_limited_per_account() {
+        let a = Address::zero();
+        let mut state = get_temp_state();
+        for key in 1..5u64 {
+            state
+                .set_storage(&a, H256::from(key), H256::from(key + 10))
+                .unwrap();
+        }
+        state.commit().unwrap();
+        state.clear();
 
-    //     let mut state = get_temp_state();
+        state.storage_cache_items = Some(2);
+        state.set_storage(&a, 9.into(), 19.into()).unwrap();
+        for key in 1..5u64 {
+            assert_eq!(state.storage_at(&a, &H256::from(key)).unwrap(), H256::from(key + 10));
+        }
+        {
+            let cache = state.cache.borrow();
+            let account = cache[&a].account.as_ref().unwrap();
+            assert_eq!(account.cached_storage_at(&1.into()), None);
+            assert_eq!(account.cached_storage_at(&2.into()), None);
+            assert_eq!(account.cached_storage_at(&4.into()), Some(14.into()));
+            // modified slots are never evicted
+            assert_eq!(account.cached_storage_at(&9.into()), Some(19.into()));
+        }
+        // evicted slots are read from the trie again
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 11.into());
+
+        // accounts restored from a snapshot keep the limit
+        let snapshot = state.dirty_snapshot();
+        state.set_storage(&a, 9.into(), 20.into()).unwrap();
+        state.restore_dirty(snapshot);
+        for key in 1..5u64 {
+            assert_eq!(state.storage_at(&a, &H256::from(key)).unwrap(), H256::from(key + 10));
+        }
+        let cache = state.cache.borrow();
+        let account = cache[&a].account.as_ref().unwrap();
+        assert_eq!(account.cached_storage_at(&1.into()), None);
+        assert_eq!(account.cached_storage_at(&4.into()), Some(14.into()));
+        assert_eq!(account.cached_storage_at(&9.into()), Some(19.into()));
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    #[test]
+    fn swap_storage_between_accounts() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 10.into()).unwrap();
+        state.set_storage(&a, 2.into(), 20.into()).unwrap();
+        state.set_storage(&b, 3.into(), 30.into()).unwrap();
+        state.commit().unwrap();
+        let nodes = state.db.as_hashdb().keys();
+
+        state.checkpoint().unwrap();
+        state.swap_storage(&a, &b).unwrap();
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 30.into());
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), H256::zero());
+        assert_eq!(state.storage_at(&b, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&b, &2.into()).unwrap(), 20.into());
+        state.revert_to_checkpoint();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&b, &3.into()).unwrap(), 30.into());
+        assert_eq!(state.storage_at(&b, &1.into()).unwrap(), H256::zero());
+        // the copied nodes were only staged and are gone with the reverted accounts
+        assert_eq!(state.db.as_hashdb().keys(), nodes);
+
+        // the swapped storage survives swapping again, a commit and a reload from the trie
+        state.swap_storage(&a, &b).unwrap();
+        state.swap_storage(&a, &b).unwrap();
+        state.swap_storage(&a, &b).unwrap();
+        // read through the staged nodes rather than the swapped storage cache
+        state.cache.get_mut().get_mut(&b).unwrap().account.as_mut().unwrap().set_storage_cache_limit(0);
+        assert_eq!(state.storage_at(&b, &2.into()).unwrap(), 20.into());
+        state.commit().unwrap();
+        state.clear();
+        assert_eq!(state.storage_at(&a, &3.into()).unwrap(), 30.into());
+        assert_eq!(state.storage_at(&b, &1.into()).unwrap(), 10.into());
+        assert_eq!(state.storage_at(&b, &2.into()).unwrap(), 20.into());
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![], //600480600b6000396000f35b600056
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn commit_only_dirty_account_fields() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.new_contract(&a, U256::zero());
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&a, vec![0x01]).unwrap();
+        state.set_storage(&a, 1.into(), 10.into()).unwrap();
+        state.commit().unwrap();
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(), FromHex::from_hex("5b600056").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(79_000),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xb.into(),
-    //             value: 0.into(),
-    //             gas: 78934.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
-    //     }];
+        state.inc_nonce(&a).unwrap();
+        let before = account::COMMIT_COUNT.with(|count| count.get());
+        state.commit().unwrap();
+        assert_eq!(account::COMMIT_COUNT.with(|count| count.get()), before);
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+        state.clear();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(state.code(&a).unwrap(), Some(Arc::new(vec![0x60, 0x00])));
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 10.into());
+    }
 
-    // #[test]
-    // fn should_trace_call_with_subcall_with_subcall_transaction() {
-    //     init_log();
+    #[test]
+    fn simulate_commit_matches_commit() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 10.into()).unwrap();
+        state.commit().unwrap();
 
-    //     let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 11.into()).unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.init_code(&b, vec![0x60, 0x00]).unwrap();
+        let old_root = *state.root();
+        let (root, journal) = state.simulate_commit().unwrap();
+        assert!(!journal.is_empty());
+        assert!(!journal.removes.is_empty());
+        assert_eq!(*state.root(), old_root);
+        for key in journal.inserts.keys() {
+            assert!(!state.db.as_hashdb().contains(key));
+        }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+        // simulating again sees the same dirty cache
+        assert_eq!(state.simulate_commit().unwrap(), (root, journal.clone()));
+        state.commit().unwrap();
+        assert_eq!(*state.root(), root);
+        for (key, value) in &journal.inserts {
+            assert_eq!(&state.db.as_hashdb().get(key).unwrap()[..], &value[..]);
+        }
+        assert!(state.simulate_commit().unwrap().1.is_empty());
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn incremental_root_matches_commit() {
+        let (a, b, c) = (Address::from(0xa), Address::from(0xb), Address::from(0xc));
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&b, 1.into(), 10.into()).unwrap();
+        state.commit().unwrap();
+        let old_root = *state.root();
+
+        let mut expected = state.clone();
+        expected.inc_nonce(&a).unwrap();
+        expected.kill_account(&b);
+        expected.inc_nonce(&c).unwrap();
+        expected.init_code(&c, vec![0x60, 0x00]).unwrap();
+        expected.commit().unwrap();
+        let rlp = |address: &Address| {
+            let trie = expected
+                .factories
+                .trie
+                .readonly(expected.db.as_hashdb(), expected.root())
+                .unwrap();
+            trie.get(address).unwrap().map(|rlp| rlp.to_vec())
+        };
+        let changes = vec![(a, rlp(&a)), (b, None), (c, rlp(&c))];
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(),
-    //                    FromHex::from_hex("60006000600060006000600c602b5a03f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xc.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(135),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xb.into(),
-    //             value: 0.into(),
-    //             gas: 78934.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(69),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0, 0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xb.into(),
-    //             to: 0xc.into(),
-    //             value: 0.into(),
-    //             gas: 78868.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(3),
-    //             output: vec![]
-    //         }),
-    //     }];
+        let nodes = state.db.as_hashdb().keys().len();
+        assert_eq!(state.incremental_root(&changes).unwrap(), *expected.root());
+        assert_eq!(*state.root(), old_root);
+        assert_eq!(state.db.as_hashdb().keys().len(), nodes);
+        assert_eq!(state.incremental_root(&[]).unwrap(), old_root);
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    #[test]
+    fn export_and_import_cache() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        state.clear();
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        assert!(!state.exists(&b).unwrap());
+        state.inc_nonce(&c).unwrap();
 
-    // #[test]
-    // fn should_trace_failed_subcall_with_subcall_transaction() {
-    //     init_log();
+        let mut bundle = Vec::new();
+        state.export_cache(&mut bundle).unwrap();
 
-    //     let mut state = get_temp_state();
+        let mut fresh = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(fresh.import_cache(&mut &bundle[..]).unwrap(), 2);
+        {
+            let cache = fresh.cache.borrow();
+            assert_eq!(cache[&a].account.as_ref().map(|account| *account.nonce()), Some(U256::from(1)));
+            assert!(cache[&b].account.is_none());
+            assert!(!cache.contains_key(&c));
+        }
+        assert_eq!(fresh.nonce(&a).unwrap(), U256::from(1));
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+        // a bundle for another root is rejected
+        fresh.inc_nonce(&a).unwrap();
+        fresh.commit().unwrap();
+        let mut other = State::from_existing(fresh.db.boxed_clone(), *fresh.root(), U256::zero(), Default::default()).unwrap();
+        assert!(other.import_cache(&mut &bundle[..]).is_err());
+    }
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![], //600480600b6000396000f35b600056
-    //         }
-    //         .sign(&secret(), None);
+    #[test]
+    fn report_contracts_without_abi() {
+        let with_abi: Address = 0xa.into();
+        let without_abi: Address = 0xb.into();
+        let plain: Address = 0xc.into();
+        let (root, db) = get_temp_state().drop();
+        let mut state =
+            State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+        state.new_contract(&with_abi, U256::zero());
+        state.init_code(&with_abi, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&with_abi, vec![0x01]).unwrap();
+        state.new_contract(&without_abi, U256::zero());
+        state.init_code(&without_abi, vec![0x60, 0x01]).unwrap();
+        state.inc_nonce(&plain).unwrap();
+        state.commit().unwrap();
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("60006000600060006000600b602b5a03f1").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xb.into(),
-    //                    FromHex::from_hex("60006000600060006000600c602b5a03f1505b601256").unwrap())
-    //         .unwrap();
-    //     state
-    //         .init_code(&0xc.into(), FromHex::from_hex("6000").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &(100.into()), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
+        assert_eq!(state.missing_abi_contracts().unwrap(), Some(vec![without_abi]));
 
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(79_000),
-    //             output: vec![]
-    //         })
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 1,
-    //             action: trace::Action::Call(trace::Call {
-    //             from: 0xa.into(),
-    //             to: 0xb.into(),
-    //             value: 0.into(),
-    //             gas: 78934.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::FailedCall(TraceError::OutOfGas),
-    //     }, FlatTrace {
-    //         trace_address: vec![0, 0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: 0xb.into(),
-    //             to: 0xc.into(),
-    //             value: 0.into(),
-    //             gas: 78868.into(),
-    //             call_type: CallType::Call,
-    //             input: vec![],
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: U256::from(3),
-    //             output: vec![]
-    //         }),
-    //     }];
+        let mut secure = get_temp_state();
+        secure.new_contract(&without_abi, U256::zero());
+        secure.init_code(&without_abi, vec![0x60, 0x01]).unwrap();
+        secure.commit().unwrap();
+        assert_eq!(secure.missing_abi_contracts().unwrap(), None);
+    }
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+    #[test]
+    fn find_contracts_referencing_an_address() {
+        let target: Address = 0x1234.into();
+        let caller: Address = 0xa.into();
+        let data_only: Address = 0xb.into();
+        let plain: Address = 0xc.into();
+        let (root, db) = get_temp_state().drop();
+        let mut state =
+            State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+        // PUSH20 <target> BALANCE
+        let mut code = vec![0x73];
+        code.extend_from_slice(&target);
+        code.push(0x31);
+        state.new_contract(&caller, U256::zero());
+        state.init_code(&caller, code).unwrap();
+        // PUSH32 with the target bytes inside its operand
+        let mut code = vec![0x7f];
+        code.extend_from_slice(&H256::from(target));
+        state.new_contract(&data_only, U256::zero());
+        state.init_code(&data_only, code).unwrap();
+        state.new_contract(&plain, U256::zero());
+        state.init_code(&plain, vec![0x60, 0x00]).unwrap();
+        state.commit().unwrap();
 
-    // #[test]
-    // fn should_trace_suicide() {
-    //     init_log();
+        assert_eq!(state.contracts_referencing(&target).unwrap(), Some(vec![caller]));
+        assert_eq!(state.contracts_referencing(&plain).unwrap(), Some(vec![]));
 
-    //     let mut state = get_temp_state();
+        let mut secure = get_temp_state();
+        secure.new_contract(&caller, U256::zero());
+        secure.init_code(&caller, vec![0x60, 0x00]).unwrap();
+        secure.commit().unwrap();
+        assert_eq!(secure.contracts_referencing(&target).unwrap(), None);
+    }
 
-    //     let mut info = EnvInfo::default();
-    //     info.gas_limit = 1_000_000.into();
-    //     let engine = TestEngine::new(5);
+    #[test]
+    fn revert_to_named_savepoint() {
+        let a = Address::from(0xa);
+        let mut state = get_temp_state();
+        state.savepoint("first").unwrap();
+        state.set_storage(&a, H256::from(1), H256::from(1)).unwrap();
+        state.savepoint("second").unwrap();
+        state.set_storage(&a, H256::from(2), H256::from(2)).unwrap();
+        state.checkpoint().unwrap();
+        state.inc_nonce(&a).unwrap();
+        assert_eq!(state.checkpoint_depth(), 3);
+
+        state.revert_to_savepoint("first").unwrap();
+        assert_eq!(state.checkpoint_depth(), 0);
+        assert_eq!(state.storage_at(&a, &H256::from(1)).unwrap(), H256::zero());
+        assert_eq!(state.storage_at(&a, &H256::from(2)).unwrap(), H256::zero());
+        assert_eq!(state.nonce(&a).unwrap(), U256::zero());
+        // the intervening savepoint went with it
+        assert_eq!(
+            state.revert_to_savepoint("second"),
+            Err(UnknownSavepoint { name: "second".to_owned() })
+        );
 
-    //     let t = Transaction {
-    //             nonce: 0.into(),
-    //             gas_price: 0.into(),
-    //             gas: 100_000.into(),
-    //             action: Action::Call(0xa.into()),
-    //             value: 100.into(),
-    //             data: vec![],
-    //         }
-    //         .sign(&secret(), None);
+        state.savepoint("outer").unwrap();
+        state.set_storage(&a, H256::from(1), H256::from(1)).unwrap();
+        state.savepoint("inner").unwrap();
+        state.set_storage(&a, H256::from(2), H256::from(2)).unwrap();
+        state.revert_to_savepoint("inner").unwrap();
+        assert_eq!(state.checkpoint_depth(), 1);
+        assert_eq!(state.storage_at(&a, &H256::from(1)).unwrap(), H256::from(1));
+        assert_eq!(state.storage_at(&a, &H256::from(2)).unwrap(), H256::zero());
+        // a discarded checkpoint releases its savepoint
+        state.discard_checkpoint();
+        assert!(state.revert_to_savepoint("outer").is_err());
+        assert!(state.revert_to_savepoint("missing").is_err());
+    }
 
-    //     state
-    //         .init_code(&0xa.into(),
-    //                    FromHex::from_hex("73000000000000000000000000000000000000000bff").unwrap())
-    //         .unwrap();
-    //     state
-    //         .add_balance(&0xa.into(), &50.into(), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     state
-    //         .add_balance(&t.sender(), &100.into(), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     let result = state.apply(&info, &engine, &t, true).unwrap();
-    //     let expected_trace = vec![FlatTrace {
-    //         trace_address: Default::default(),
-    //         subtraces: 1,
-    //         action: trace::Action::Call(trace::Call {
-    //             from: "9cce34f7ab185c7aba1b7c8140d620b4bda941d6".into(),
-    //             to: 0xa.into(),
-    //             value: 100.into(),
-    //             gas: 79000.into(),
-    //             input: vec![],
-    //             call_type: CallType::Call,
-    //         }),
-    //         result: trace::Res::Call(trace::CallResult {
-    //             gas_used: 3.into(),
-    //             output: vec![]
-    //         }),
-    //     }, FlatTrace {
-    //         trace_address: vec![0].into_iter().collect(),
-    //         subtraces: 0,
-    //         action: trace::Action::Suicide(trace::Suicide {
-    //             address: 0xa.into(),
-    //             refund_address: 0xb.into(),
-    //             balance: 150.into(),
-    //         }),
-    //         result: trace::Res::None,
-    //     }];
+    #[test]
+    fn diff_permissions() {
+        let (kept, granted, revoked) = (Address::from(0xa), Address::from(0xb), Address::from(0xc));
+        let (call, other_call) = (
+            Resource::new(Address::from(0x100), vec![1, 2, 3, 4]),
+            Resource::new(Address::from(0x101), vec![5, 6, 7, 8]),
+        );
+        let mut before = get_temp_state();
+        before.senders = vec![kept, revoked].into_iter().collect();
+        before.creators = vec![revoked].into_iter().collect();
+        before.account_permissions.insert(kept, vec![call.clone()]);
+        before.account_permissions.insert(revoked, vec![call.clone(), other_call.clone()]);
+        let mut after = before.clone();
+        assert!(after.permissions_diff(&before).is_empty());
+
+        after.senders.remove(&revoked);
+        after.senders.insert(granted);
+        after.creators.remove(&revoked);
+        after.creators.insert(kept);
+        after.account_permissions.insert(granted, vec![other_call.clone()]);
+        after.account_permissions.remove(&revoked);
+        after.account_permissions.insert(kept, vec![other_call.clone(), call.clone()]);
+
+        let diff = after.permissions_diff(&before);
+        assert_eq!(diff.added_senders, vec![granted]);
+        assert_eq!(diff.removed_senders, vec![revoked]);
+        assert_eq!(diff.added_creators, vec![kept]);
+        assert_eq!(diff.removed_creators, vec![revoked]);
+        assert_eq!(
+            diff.added_resources,
+            vec![(kept, vec![other_call.clone()]), (granted, vec![other_call.clone()])]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            diff.removed_resources,
+            vec![(revoked, vec![call, other_call])].into_iter().collect()
+        );
 
-    //     assert_eq!(result.trace, expected_trace);
-    // }
+        let reverse = before.permissions_diff(&after);
+        assert_eq!(reverse.added_senders, diff.removed_senders);
+        assert_eq!(reverse.removed_resources, diff.added_resources);
+    }
 
     #[test]
-    fn code_from_database() {
+    fn checkpoint_depth_limit() {
         let a = Address::zero();
-        let (root, db) = {
-            let mut state = get_temp_state();
-            state
-                .require_or_from(&a, false, false, || Account::new_contract(0.into()), |_| {})
-                .unwrap();
-            state.init_code(&a, vec![1, 2, 3]).unwrap();
-            assert_eq!(
-                state.code(&a).unwrap(),
-                Some(Arc::new([1u8, 2, 3].to_vec()))
-            );
+        let mut state = get_temp_state();
+        state.max_checkpoint_depth = Some(2);
+        state.checkpoint().unwrap();
+        state.inc_nonce(&a).unwrap();
+        state.checkpoint().unwrap();
+        assert_eq!(state.checkpoint(), Err(CheckpointLimitExceeded { limit: 2 }));
+        assert_eq!(state.checkpoint_depth(), 2);
 
-            state.commit().unwrap();
-            assert_eq!(
-                state.code(&a).unwrap(),
-                Some(Arc::new([1u8, 2, 3].to_vec()))
-            );
-            state.drop()
+        state.revert_to_checkpoint();
+        state.checkpoint().unwrap();
+        state.revert_to_checkpoint();
+        state.revert_to_checkpoint();
+        assert_eq!(state.nonce(&a).unwrap(), U256::zero());
+
+        // a transaction needs a checkpoint of its own
+        state.max_checkpoint_depth = Some(0);
+        let sender = Address::from(0x1234);
+        let mut t = Transaction {
+            action: Action::Store,
+            gas: 100_000.into(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        }.fake_sign(sender);
+        assert!(state.apply(&EnvInfo::default(), &mut t, false, false, false).is_err());
+        assert_eq!(state.nonce(&sender).unwrap(), U256::zero());
+        assert_eq!(state.checkpoint_depth(), 0);
+    }
+
+    #[test]
+    fn resolve_proxy_chain() {
+        let proxy: Address = 0xa.into();
+        let middle: Address = 0xb.into();
+        let implementation: Address = 0xc.into();
+        let loop_a: Address = 0xd.into();
+        let loop_b: Address = 0xe.into();
+        let mut state = get_temp_state();
+        // proxies carry the address they redirect to as their code
+        state.init_code(&proxy, middle.to_vec()).unwrap();
+        state.init_code(&middle, implementation.to_vec()).unwrap();
+        state.init_code(&implementation, vec![0x60, 0x00]).unwrap();
+        state.init_code(&loop_a, loop_b.to_vec()).unwrap();
+        state.init_code(&loop_b, loop_a.to_vec()).unwrap();
+        state.commit().unwrap();
+        state.clear();
+
+        let follow = |account: &Account| match account.code() {
+            Some(ref code) if code.len() == 20 => Some(Address::from_slice(code)),
+            _ => None,
         };
+        assert_eq!(state.resolve_account(&proxy, &follow).unwrap(), Some(implementation));
+        assert_eq!(state.resolve_account(&implementation, &follow).unwrap(), Some(implementation));
+        assert_eq!(state.resolve_account(&0xf.into(), &follow).unwrap(), None);
+        assert!(state.resolve_account(&loop_a, &follow).is_err());
+    }
+
+    #[test]
+    fn commit_subset_of_accounts() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&b, 1.into(), 2.into()).unwrap();
+        state.commit_accounts(&[a]).unwrap();
 
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        let committed = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(committed.nonce(&a).unwrap(), U256::from(1));
+        assert!(!committed.exists(&b).unwrap());
+        assert_eq!(state.storage_at(&b, &1.into()).unwrap(), 2.into());
+
+        state.commit().unwrap();
+        let committed = State::from_existing(state.db.boxed_clone(), *state.root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(committed.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(committed.storage_at(&b, &1.into()).unwrap(), 2.into());
+    }
 
+    #[test]
+    fn commit_plan_is_deterministic() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let mut first = get_temp_state();
+        first.inc_nonce(&c).unwrap();
+        first.set_storage(&a, 2.into(), 3.into()).unwrap();
+        first.set_storage(&a, 1.into(), 2.into()).unwrap();
+        first.init_code(&b, vec![0x60, 0x00]).unwrap();
+
+        let mut second = get_temp_state();
+        second.init_code(&b, vec![0x60, 0x00]).unwrap();
+        second.set_storage(&a, 1.into(), 2.into()).unwrap();
+        second.inc_nonce(&c).unwrap();
+        second.set_storage(&a, 2.into(), 3.into()).unwrap();
+
+        let plan = first.commit_plan();
+        assert_eq!(plan, second.commit_plan());
         assert_eq!(
-            state.code(&a).unwrap(),
-            Some(Arc::new([1u8, 2, 3].to_vec()))
+            plan,
+            vec![
+                CommitStep::CommitStorage {
+                    address: a,
+                    changes: vec![(1.into(), 2.into()), (2.into(), 3.into())],
+                },
+                CommitStep::CommitCode(b),
+                CommitStep::InsertAccount(a),
+                CommitStep::InsertAccount(b),
+                CommitStep::InsertAccount(c),
+            ]
         );
+
+        first.commit().unwrap();
+        assert!(first.commit_plan().is_empty());
     }
 
     #[test]
-    fn abi_from_database() {
-        let a = Address::zero();
-        let (root, db) = {
+    fn prune_storage_of_killed_accounts() {
+        let a: Address = 0xa.into();
+        let storage_left_after_kill = |prune: bool| {
             let mut state = get_temp_state();
-            state
-                .require_or_from(&a, false, false, || Account::new_contract(0.into()), |_| {})
-                .unwrap();
-            state.init_abi(&a, vec![1, 2, 3]).unwrap();
-            assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
+            state.prune_killed_storage = prune;
+            state.init_code(&a, vec![0x60, 0x00]).unwrap();
+            for i in 1..10u64 {
+                state.set_storage(&a, i.into(), (i * 10).into()).unwrap();
+            }
+            state.commit().unwrap();
+            let (address_hash, nodes) = {
+                let cache = state.cache.borrow();
+                let account = cache[&a].account.as_ref().unwrap();
+                (account.address_hash(&a), state.account_storage_nodes(&a, account).unwrap())
+            };
+            assert!(!nodes.is_empty());
 
+            state.kill_account(&a);
             state.commit().unwrap();
-            assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
-            state.drop()
+            assert!(!state.exists(&a).unwrap());
+            let account_db = state
+                .factories
+                .accountdb
+                .readonly(state.db.as_hashdb(), address_hash);
+            nodes
+                .iter()
+                .filter(|&&(ref hash, _)| account_db.contains(hash))
+                .count()
         };
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
+        assert_eq!(storage_left_after_kill(true), 0);
+        assert!(storage_left_after_kill(false) > 0);
+    }
 
-        assert_eq!(state.abi(&a).unwrap(), Some(Arc::new([1u8, 2, 3].to_vec())));
+    #[test]
+    fn pod_state_round_trip() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let (root, db) = get_temp_state().drop();
+        let mut state =
+            State::from_existing_with_spec(db, root, U256::from(0u8), Default::default(), TrieSpec::Fat).unwrap();
+        state.inc_nonce(&a).unwrap();
+        state.init_code(&b, vec![0x60, 0x00]).unwrap();
+        state.init_abi(&b, vec![0x01, 0x02]).unwrap();
+        state.set_storage(&b, 1.into(), 2.into()).unwrap();
+        state.set_storage(&b, 3.into(), 4.into()).unwrap();
+        state.commit().unwrap();
+        state.clear();
+
+        let pod = state.to_pod().unwrap().unwrap();
+        assert_eq!(pod.get().len(), 2);
+        assert_eq!(pod.get()[&b].storage.len(), 2);
+        let restored = State::from_pod(get_temp_state_db(), pod.clone(), Default::default()).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.code(&b).unwrap(), Some(Arc::new(vec![0x60, 0x00])));
+        assert_eq!(restored.storage_at(&b, &3.into()).unwrap(), 4.into());
+
+        assert_eq!(get_temp_state().to_pod().unwrap(), None);
     }
 
     #[test]
-    fn storage_at_from_database() {
-        let a = Address::zero();
-        let (root, db) = {
-            let mut state = get_temp_state();
-            state
-                .set_storage(&a, H256::from(1u64), H256::from(69u64))
-                .unwrap();
-            state.commit().unwrap();
-            state.drop()
-        };
+    fn empty_trie_root_matches_committed_empty_trie() {
+        let mut db = MemoryDB::new();
+        let mut root = H256::new();
+        {
+            let factories = Factories::default();
+            let mut trie = factories.trie.create(&mut db, &mut root);
+            trie.insert(b"key", b"value").unwrap();
+            trie.remove(b"key").unwrap();
+        }
+        assert_eq!(root, empty_trie_root());
 
-        let s = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-        assert_eq!(
-            s.storage_at(&a, &H256::from(1u64)).unwrap(),
-            H256::from(69u64)
-        );
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        assert_eq!(*state.root(), empty_trie_root());
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        assert_eq!(state.storage_root(&a).unwrap(), Some(empty_trie_root()));
     }
 
     #[test]
-    fn get_from_database() {
-        let a = Address::zero();
-        let (root, db) = {
-            let mut state = get_temp_state();
-            state.inc_nonce(&a).unwrap();
-            state.commit().unwrap();
-            assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
-            state.drop()
-        };
+    fn account_access_counts() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        assert!(state.account_access_counts().is_empty());
 
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
+        state.meter_account_access = true;
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.nonce(&a).unwrap();
+        state.storage_at(&b, &1.into()).unwrap();
+        let counts = state.account_access_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&a], (2, 2));
+        assert_eq!(counts[&b], (1, 0));
+
+        state.commit().unwrap();
+        state.clear();
+        assert!(state.account_access_counts().is_empty());
     }
 
     #[test]
-    fn remove() {
-        let a = Address::zero();
+    fn verify_against_root_detects_tampering() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
         let mut state = get_temp_state();
-        assert_eq!(state.exists(&a).unwrap(), false);
-        assert_eq!(state.exists_and_not_null(&a).unwrap(), false);
         state.inc_nonce(&a).unwrap();
-        assert_eq!(state.exists(&a).unwrap(), true);
-        assert_eq!(state.exists_and_not_null(&a).unwrap(), true);
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
-        state.kill_account(&a);
-        assert_eq!(state.exists(&a).unwrap(), false);
-        assert_eq!(state.exists_and_not_null(&a).unwrap(), false);
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.commit().unwrap();
+        let root = *state.root();
+
+        let mut fractions = Vec::new();
+        assert!(state.verify_against_root(root, |f| fractions.push(f)).unwrap());
+        assert_eq!(fractions, vec![0.5, 1.0]);
+        assert!(!state.verify_against_root(H256::from(1), |_| {}).unwrap());
+
+        // serve the nodes of a later root under the hash of the verified one
+        state.set_storage(&a, 1.into(), 3.into()).unwrap();
+        state.commit().unwrap();
+        let tampered = state.db.as_hashdb().get(state.root()).unwrap();
+        state.db.as_hashdb_mut().remove(&root);
+        state.db.as_hashdb_mut().emplace(root, tampered);
+        let state = State::from_existing(state.db.boxed_clone(), root, U256::zero(), Default::default()).unwrap();
+        assert!(!state.verify_against_root(root, |_| {}).unwrap());
+
+        // an account leaf that is not an account mismatches instead of panicking
+        let mut db = state.db.boxed_clone();
+        let mut garbage_root = H256::new();
+        {
+            let mut trie = trie::TrieDBMut::new(db.as_hashdb_mut(), &mut garbage_root);
+            trie.insert(&a.crypt_hash(), &[0x01, 0x02]).unwrap();
+        }
+        let state = State::from_existing(db, garbage_root, U256::zero(), Default::default()).unwrap();
+        assert!(!state.verify_against_root(garbage_root, |_| {}).unwrap());
     }
 
     #[test]
-    fn empty_account_is_not_created() {
-        let a = Address::zero();
-        let db = get_temp_state_db();
-        let (root, db) = {
-            let mut state = State::new(db, U256::from(0u8), Default::default());
-            state.commit().unwrap();
-            state.drop()
-        };
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-        assert!(!state.exists(&a).unwrap());
-        assert!(!state.exists_and_not_null(&a).unwrap());
+    fn frozen_slot_rejects_writes() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.freeze_slot(&a, 1.into());
+        match state.set_storage(&a, 1.into(), 3.into()) {
+            Err(Error::SlotFrozen { address, key }) => {
+                assert_eq!(address, a);
+                assert_eq!(key, 1.into());
+            }
+            other => panic!("expected SlotFrozen, got {:?}", other),
+        }
+        state.set_storage(&a, 2.into(), 3.into()).unwrap();
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 2.into());
+
+        state.checkpoint().unwrap();
+        state.freeze_slot(&a, 2.into());
+        assert!(state.set_storage(&a, 2.into(), 4.into()).is_err());
+        state.revert_to_checkpoint();
+        assert!(!state.is_slot_frozen(&a, &2.into()));
+        assert!(state.is_slot_frozen(&a, &1.into()));
+        state.set_storage(&a, 2.into(), 4.into()).unwrap();
+
+        state.checkpoint().unwrap();
+        state.checkpoint().unwrap();
+        state.freeze_slot(&a, 3.into());
+        state.discard_checkpoint();
+        state.revert_to_checkpoint();
+        assert!(!state.is_slot_frozen(&a, &3.into()));
     }
 
     #[test]
-    fn empty_account_exists_when_creation_forced() {
-        let a = Address::zero();
-        let db = get_temp_state_db();
-        let (root, db) = {
-            let mut state = State::new(db, U256::from(0u8), Default::default());
-            state.require(&a, false, false).unwrap();
-            state.commit().unwrap();
-            state.drop()
-        };
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-        assert!(state.exists(&a).unwrap());
-        assert!(!state.exists_and_not_null(&a).unwrap());
+    fn frozen_slot_rejects_bulk_storage_changes() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let mut state = get_temp_state();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.set_storage(&a, 2.into(), 3.into()).unwrap();
+        state.set_storage(&b, 2.into(), 3.into()).unwrap();
+        state.freeze_slot(&a, 1.into());
+
+        let mut storage = HashMap::new();
+        storage.insert(2.into(), 4.into());
+        assert!(match state.replace_storage(&a, storage.clone()) {
+            Err(Error::SlotFrozen { address, key }) => address == a && key == 1.into(),
+            _ => false,
+        });
+        assert!(state.clear_storage(&a).is_err());
+        assert!(state.swap_storage(&a, &b).is_err());
+        assert!(state.swap_storage(&b, &a).is_err());
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 2.into());
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 3.into());
+
+        // keeping the frozen value is fine
+        storage.insert(1.into(), 2.into());
+        state.replace_storage(&a, storage).unwrap();
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 4.into());
+        state.set_storage(&b, 1.into(), 2.into()).unwrap();
+        state.swap_storage(&a, &b).unwrap();
+        assert_eq!(state.storage_at(&a, &2.into()).unwrap(), 3.into());
     }
 
     #[test]
-    fn remove_from_database() {
-        let a = Address::zero();
-        let (root, db) = {
-            let mut state = get_temp_state();
-            state.inc_nonce(&a).unwrap();
-            state.commit().unwrap();
-            assert_eq!(state.exists(&a).unwrap(), true);
-            assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
-            state.drop()
-        };
+    fn account_count_delta_between_roots() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+        let old_root = *state.root();
+        assert_eq!(state.account_count_delta(old_root).unwrap(), 0);
 
-        let (root, db) = {
-            let mut state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-            assert_eq!(state.exists(&a).unwrap(), true);
-            assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
-            state.kill_account(&a);
-            state.commit().unwrap();
-            assert_eq!(state.exists(&a).unwrap(), false);
-            assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
-            state.drop()
-        };
+        state.inc_nonce(&b).unwrap();
+        state.inc_nonce(&c).unwrap();
+        state.kill_account(&a);
+        state.commit().unwrap();
+        assert_eq!(state.account_count_delta(old_root).unwrap(), 1);
 
-        let state = State::from_existing(db, root, U256::from(0u8), Default::default()).unwrap();
-        assert_eq!(state.exists(&a).unwrap(), false);
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+        let new_root = *state.root();
+        let state = State::from_existing(state.db.boxed_clone(), old_root, U256::zero(), Default::default()).unwrap();
+        assert_eq!(state.account_count_delta(new_root).unwrap(), -1);
     }
 
     #[test]
-    fn alter_nonce() {
+    fn read_through_mmap() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
         let mut state = get_temp_state();
-        let a = Address::zero();
-        state.inc_nonce(&a).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(1u64));
         state.inc_nonce(&a).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(2u64));
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.init_code(&b, vec![0x60, 0x00]).unwrap();
+        state.set_storage(&b, 3.into(), 4.into()).unwrap();
         state.commit().unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(2u64));
-        state.inc_nonce(&a).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(3u64));
+
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.to_path_buf().join("state");
+        state.export_mmap(&path).unwrap();
+
+        let mut mapped = State::from_mmap(&path, *state.root(), U256::zero(), Default::default()).unwrap();
+        assert_eq!(mapped.nonce(&a).unwrap(), state.nonce(&a).unwrap());
+        assert_eq!(mapped.storage_at(&a, &1.into()).unwrap(), 2.into());
+        assert_eq!(mapped.storage_at(&b, &3.into()).unwrap(), 4.into());
+        assert_eq!(mapped.code(&b).unwrap(), Some(Arc::new(vec![0x60, 0x00])));
+        assert!(!mapped.exists(&0xc.into()).unwrap());
+
+        mapped.inc_nonce(&a).unwrap();
+        match mapped.commit() {
+            Err(Error::ReadOnlyState) => {}
+            other => panic!("expected ReadOnlyState, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reexecute_from_witness() {
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
+        let mut state = get_temp_state();
+        for i in 0..64u64 {
+            state.inc_nonce(&Address::from(0x100 + i)).unwrap();
+        }
+        // PUSH1 0x00 SLOAD PUSH1 0x01 SSTORE
+        state
+            .init_code(&contract, vec![0x60, 0x00, 0x54, 0x60, 0x01, 0x55])
+            .unwrap();
+        state.set_storage(&contract, H256::from(0), H256::from(5)).unwrap();
         state.commit().unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(3u64));
+
+        let t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let witness = state.execution_witness(&EnvInfo::default(), &t).unwrap();
+        assert_eq!(witness.root, *state.root());
+        assert!(witness.verify());
+        assert!(witness.nodes.len() < state.db.as_hashdb().keys().len());
+
+        let mut stateless = witness
+            .clone()
+            .into_state(U256::zero(), Default::default())
+            .unwrap();
+        let outcome = stateless
+            .apply_with_options(&EnvInfo::default(), &mut t.clone(), TransactOptions::default())
+            .unwrap();
+        stateless.commit().unwrap();
+        let expected = state
+            .apply_with_options(&EnvInfo::default(), &mut t.clone(), TransactOptions::default())
+            .unwrap();
+        state.commit().unwrap();
+        assert_eq!(outcome, expected);
+        assert_eq!(stateless.root(), state.root());
+        assert_eq!(stateless.storage_at(&contract, &H256::from(1)).unwrap(), H256::from(5));
+
+        // a node of the contract moved under the key of another account
+        let mut moved = witness.clone();
+        let (key, value) = moved
+            .nodes
+            .iter()
+            .find(|&(key, value)| (&value[..]).crypt_hash() != *key)
+            .map(|(key, value)| (*key, value.clone()))
+            .unwrap();
+        moved.nodes.remove(&key);
+        let mut other = key;
+        other[31] ^= 1;
+        moved.nodes.insert(other, value);
+        assert!(!moved.verify());
+
+        let mut tampered = witness;
+        let key = *tampered.nodes.keys().next().unwrap();
+        tampered.nodes.insert(key, DBValue::from_slice(b"tampered"));
+        assert!(!tampered.verify());
     }
 
     #[test]
-    fn nonce() {
+    fn apply_against_witness_backend() {
+        let sender = Address::from(0x1234);
+        let contract = Address::from(0xabcd);
         let mut state = get_temp_state();
-        let a = Address::zero();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+        for i in 0..64u64 {
+            state.inc_nonce(&Address::from(0x100 + i)).unwrap();
+        }
+        // PUSH1 0x2a PUSH1 0x00 SSTORE
+        state
+            .init_code(&contract, vec![0x60, 0x2a, 0x60, 0x00, 0x55])
+            .unwrap();
         state.commit().unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(0u64));
+        let root = *state.root();
+
+        let t = Transaction {
+            action: Action::Call(contract),
+            gas: 100_000.into(),
+            ..Default::default()
+        }.fake_sign(sender);
+        let witness = state.execution_witness(&EnvInfo::default(), &t).unwrap();
+        // nothing below the root node
+        let insufficient = Witness {
+            root: root,
+            nodes: witness
+                .nodes
+                .iter()
+                .filter(|&(key, _)| *key == root)
+                .map(|(key, value)| (*key, value.clone()))
+                .collect(),
+        };
+
+        let mut stateless = State::from_existing(WitnessBackend::from(witness), root, U256::zero(), Default::default())
+            .unwrap();
+        stateless.allow_missing_nodes = true;
+        let receipt = stateless
+            .apply(&EnvInfo::default(), &mut t.clone(), false, false, false)
+            .unwrap()
+            .receipt;
+        let expected = state
+            .apply(&EnvInfo::default(), &mut t.clone(), false, false, false)
+            .unwrap()
+            .receipt;
+        assert_eq!(receipt, expected);
+
+        let mut stateless = insufficient
+            .into_state(U256::zero(), Default::default())
+            .unwrap();
+        match stateless.apply(&EnvInfo::default(), &mut t.clone(), false, false, false) {
+            Err(Error::StateIncomplete { .. }) => {}
+            other => panic!("expected StateIncomplete, got {:?}", other),
+        }
     }
 
     #[test]
-    fn ensure_cached() {
+    fn contract_identity_covers_code_and_abi() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
         let mut state = get_temp_state();
-        let a = Address::zero();
-        state.require(&a, false, false).unwrap();
+        for address in &[a, b, c] {
+            state.init_code(address, vec![0x60, 0x00]).unwrap();
+            state.init_abi(address, vec![0x01]).unwrap();
+        }
         state.commit().unwrap();
-        if HASH_NAME == "sha3" {
-            assert_eq!(
-                state.root().hex(),
-                "98560ba094af6f0874e6a965207d24e049b76fcb8b94bee33d219a21d1636f83"
-            );
-        } else if HASH_NAME == "blake2b" {
-            assert_eq!(
-                state.root().hex(),
-                "1d20d29c3bef1ce4b24e171b3d94371176ccf6a5a624e184bc48f3c3be98e083"
-            );
+        let identity = state.contract_identity(&a).unwrap();
+        assert!(identity.is_some());
+        assert_eq!(state.contract_identity(&b).unwrap(), identity);
+
+        state.reset_code(&b, vec![0x60, 0x01]).unwrap();
+        state.reset_abi(&c, vec![0x02]).unwrap();
+        assert!(state.contract_identity(&b).unwrap() != identity);
+        assert!(state.contract_identity(&c).unwrap() != identity);
+        assert!(state.contract_identity(&b).unwrap() != state.contract_identity(&c).unwrap());
+
+        state.inc_nonce(&0xd.into()).unwrap();
+        assert_eq!(state.contract_identity(&0xd.into()).unwrap(), None);
+        assert_eq!(state.contract_identity(&0xe.into()).unwrap(), None);
+    }
+
+    #[test]
+    fn parallel_storage_commit_matches_serial() {
+        let contracts: Vec<Address> = (1..33u64).map(Address::from).collect();
+        let mut serial = get_temp_state();
+        let mut parallel = get_temp_state();
+        parallel.commit_threads = 4;
+        for round in 1..3u64 {
+            for state in &mut [&mut serial, &mut parallel] {
+                for (i, contract) in contracts.iter().enumerate() {
+                    let i = i as u64;
+                    state.init_code(contract, vec![0x60, i as u8]).unwrap();
+                    for key in 0..8u64 {
+                        state
+                            .set_storage(contract, (key * round).into(), (i * 100 + key + round).into())
+                            .unwrap();
+                    }
+                }
+                state.inc_nonce(&0xffff.into()).unwrap();
+                state.commit().unwrap();
+            }
+            assert_eq!(parallel.root(), serial.root());
         }
+
+        parallel.clear();
+        assert_eq!(parallel.storage_at(&contracts[5], &2.into()).unwrap(), (500 + 1 + 2).into());
+        assert_eq!(parallel.storage_at(&contracts[7], &7.into()).unwrap(), (700 + 7 + 1).into());
     }
 
     #[test]
-    fn checkpoint_basic() {
+    fn trace_storage_chain_follows_values() {
+        let a: Address = 0xa.into();
         let mut state = get_temp_state();
+        // 1 -> 2 -> 3 -> end
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.set_storage(&a, 2.into(), 3.into()).unwrap();
+        state.set_storage(&a, 3.into(), 0.into()).unwrap();
+        // 5 -> 6 -> 7 -> 5
+        state.set_storage(&a, 5.into(), 6.into()).unwrap();
+        state.set_storage(&a, 6.into(), 7.into()).unwrap();
+        state.set_storage(&a, 7.into(), 5.into()).unwrap();
+        state.commit().unwrap();
+
+        assert_eq!(
+            state.trace_storage_chain(&a, 1.into(), 10).unwrap(),
+            (vec![(1.into(), 2.into()), (2.into(), 3.into())], ChainEnd::Zero)
+        );
+        assert_eq!(
+            state.trace_storage_chain(&a, 1.into(), 1).unwrap(),
+            (vec![(1.into(), 2.into())], ChainEnd::MaxSteps)
+        );
+        assert_eq!(
+            state.trace_storage_chain(&a, 5.into(), 10).unwrap(),
+            (
+                vec![(5.into(), 6.into()), (6.into(), 7.into()), (7.into(), 5.into())],
+                ChainEnd::Cycle
+            )
+        );
+        assert_eq!(
+            state.trace_storage_chain(&a, 9.into(), 10).unwrap(),
+            (vec![], ChainEnd::Zero)
+        );
+    }
+
+    #[test]
+    fn compact_empty_checkpoints() {
         let a = Address::zero();
-        state.checkpoint();
-        state.inc_nonce(&a).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
-        state.discard_checkpoint();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
-        state.checkpoint();
+        let mut state = get_temp_state();
+        state.checkpoint().unwrap();
+        state.checkpoint().unwrap();
+        state.checkpoint().unwrap();
         state.inc_nonce(&a).unwrap();
-        assert_eq!(state.nonce(&a).unwrap(), U256::from(2));
+        state.checkpoint().unwrap();
+        state.checkpoint().unwrap();
+        state.checkpoint().unwrap();
+        assert_eq!(state.checkpoint_depth(), 6);
+
+        let depths = state.compact_checkpoints();
+        assert_eq!(depths, vec![0, 1, 1, 2, 3, 3, 3]);
+        assert_eq!(state.checkpoint_depth(), 3);
         state.revert_to_checkpoint();
         assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        state.revert_to_checkpoint();
+        assert_eq!(state.nonce(&a).unwrap(), U256::zero());
+        assert_eq!(state.checkpoint_depth(), 1);
     }
 
-    // #[test]
-    // fn checkpoint_nested() {
-    //     let mut state = get_temp_state();
-    //     let a = Address::zero();
-    //     state.checkpoint();
-    //     state.checkpoint();
-    //     state
-    //         .add_balance(&a, &U256::from(69u64), CleanupMode::NoEmpty)
-    //         .unwrap();
-    //     assert_eq!(state.balance(&a).unwrap(), U256::from(69u64));
-    //     state.discard_checkpoint();
-    //     assert_eq!(state.balance(&a).unwrap(), U256::from(69u64));
-    //     state.revert_to_checkpoint();
-    //     assert_eq!(state.balance(&a).unwrap(), U256::from(0));
-    // }
+    #[test]
+    fn restore_dirty_snapshot() {
+        let a: Address = 0xa.into();
+        let b: Address = 0xb.into();
+        let c: Address = 0xc.into();
+        let d: Address = 0xd.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&a, 1.into(), 2.into()).unwrap();
+        state.inc_nonce(&c).unwrap();
+        state.commit().unwrap();
+
+        state.inc_nonce(&a).unwrap();
+        state.inc_nonce(&b).unwrap();
+        state.kill_account(&c);
+        let snapshot = state.dirty_snapshot();
+
+        state.inc_nonce(&a).unwrap();
+        state.set_storage(&a, 1.into(), 3.into()).unwrap();
+        state.kill_account(&b);
+        state.inc_nonce(&c).unwrap();
+        state.inc_nonce(&d).unwrap();
+        state.restore_dirty(snapshot);
+
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(2));
+        assert_eq!(state.storage_at(&a, &1.into()).unwrap(), 2.into());
+        assert!(state.exists(&b).unwrap());
+        assert!(!state.exists(&c).unwrap());
+        assert!(!state.exists(&d).unwrap());
+    }
 
     #[test]
     fn create_empty() {
@@ -0,0 +1,112 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Account trie nodes kept between commits.
+//!
+//! Accounts missing from the account cache are looked up in the account trie, and
+//! lookups of nearby addresses walk the same upper nodes. With `State::cache_trie_nodes`
+//! set, nodes read by these lookups are kept until the next commit so later lookups
+//! of the block serve them from memory.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use util::*;
+
+/// Most nodes kept between commits.
+const TRIE_NODE_CACHE_ITEMS: usize = 8192;
+
+/// Account trie nodes read since the last commit.
+#[derive(Default)]
+pub struct TrieNodeCache {
+    nodes: HashMap<H256, DBValue>,
+    // nodes read from the state database
+    loads: u64,
+}
+
+impl TrieNodeCache {
+    /// Number of nodes read from the state database, served nodes excluded.
+    pub fn loads(&self) -> u64 {
+        self.loads
+    }
+
+    /// Forget the kept nodes.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+}
+
+/// `HashDB` view serving nodes from a `TrieNodeCache` before the state database.
+/// Databases must be `Sync`, hence the lock.
+pub struct NodeCachingDB<'a> {
+    db: &'a HashDB,
+    cache: &'a Mutex<TrieNodeCache>,
+    // keep the nodes read, only count them otherwise
+    retain: bool,
+}
+
+impl<'a> NodeCachingDB<'a> {
+    /// View of `db` through `cache`, adding the nodes read to it if `retain` is set.
+    pub fn new(db: &'a HashDB, cache: &'a Mutex<TrieNodeCache>, retain: bool) -> Self {
+        NodeCachingDB {
+            db: db,
+            cache: cache,
+            retain: retain,
+        }
+    }
+}
+
+impl<'a> HashDB for NodeCachingDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        self.db.keys()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        let mut cache = self.cache.lock().expect("trie node cache lock poisoned");
+        if let Some(value) = cache.nodes.get(key) {
+            return Some(value.clone());
+        }
+        let value = self.db.get(key);
+        if let Some(ref value) = value {
+            cache.loads += 1;
+            if self.retain && cache.nodes.len() < TRIE_NODE_CACHE_ITEMS {
+                cache.nodes.insert(*key, value.clone());
+            }
+        }
+        value
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        let cached = self.cache
+            .lock()
+            .expect("trie node cache lock poisoned")
+            .nodes
+            .contains_key(key);
+        cached || self.db.contains(key)
+    }
+
+    fn insert(&mut self, _value: &[u8]) -> H256 {
+        unimplemented!()
+    }
+
+    fn emplace(&mut self, _key: H256, _value: DBValue) {
+        unimplemented!()
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        unimplemented!()
+    }
+}
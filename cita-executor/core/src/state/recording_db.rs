@@ -0,0 +1,78 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trie nodes read while walking the state, collected by `State::export_mmap`
+//! and `State::execution_witness`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use util::*;
+
+/// `HashDB` view remembering every node read through it.
+/// Databases must be `Sync`, hence the lock.
+pub struct RecordingDB<'a> {
+    db: &'a HashDB,
+    read: Mutex<BTreeMap<H256, DBValue>>,
+}
+
+impl<'a> RecordingDB<'a> {
+    /// View of `db` remembering nothing yet.
+    pub fn new(db: &'a HashDB) -> Self {
+        RecordingDB {
+            db: db,
+            read: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The distinct nodes read so far, by hash.
+    pub fn into_nodes(self) -> BTreeMap<H256, DBValue> {
+        self.read.into_inner().expect("recorded nodes lock poisoned")
+    }
+}
+
+impl<'a> HashDB for RecordingDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        self.db.keys()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        let value = self.db.get(key);
+        if let Some(ref value) = value {
+            self.read
+                .lock()
+                .expect("recorded nodes lock poisoned")
+                .insert(*key, value.clone());
+        }
+        value
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.db.contains(key)
+    }
+
+    fn insert(&mut self, _value: &[u8]) -> H256 {
+        unimplemented!()
+    }
+
+    fn emplace(&mut self, _key: H256, _value: DBValue) {
+        unimplemented!()
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        unimplemented!()
+    }
+}
@@ -0,0 +1,159 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Owned, read-only handle to a committed state root.
+
+use account_db::Factory as AccountFactory;
+use state::account::Account;
+use state::backend::Backend;
+use state_db::StateDB;
+use std::sync::Arc;
+use util::*;
+use util::trie::TrieFactory;
+
+/// Cheap, cloneable and thread-safe read-only view of the state.
+///
+/// The snapshot reflects the state at the root it was captured at. Changes made
+/// to the originating `State` afterwards, committed or not, are not visible.
+#[derive(Clone)]
+pub struct StateSnapshot {
+    db: Arc<StateDB>,
+    root: H256,
+    account_start_nonce: U256,
+    trie: TrieFactory,
+    accountdb: AccountFactory,
+}
+
+impl StateSnapshot {
+    /// Create a snapshot of `root` over a private copy of `db`.
+    pub fn new(
+        db: StateDB,
+        root: H256,
+        account_start_nonce: U256,
+        trie: TrieFactory,
+        accountdb: AccountFactory,
+    ) -> StateSnapshot {
+        StateSnapshot {
+            db: Arc::new(db),
+            root: root,
+            account_start_nonce: account_start_nonce,
+            trie: trie,
+            accountdb: accountdb,
+        }
+    }
+
+    /// Return the state root the snapshot was captured at.
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    fn account(&self, a: &Address) -> trie::Result<Option<Account>> {
+        let db = self.trie.readonly(self.db.as_hashdb(), &self.root)?;
+        db.get_with(a, Account::from_rlp)
+    }
+
+    /// Determine whether an account exists.
+    pub fn exists(&self, a: &Address) -> trie::Result<bool> {
+        self.account(a).map(|a| a.is_some())
+    }
+
+    /// Get the nonce of account `a`.
+    pub fn nonce(&self, a: &Address) -> trie::Result<U256> {
+        self.account(a)
+            .map(|a| a.map_or(self.account_start_nonce, |a| *a.nonce()))
+    }
+
+    /// Get the storage value of account `a` at `key`.
+    pub fn storage_at(&self, a: &Address, key: &H256) -> trie::Result<H256> {
+        match self.account(a)? {
+            Some(account) => {
+                let account_db = self.accountdb
+                    .readonly(self.db.as_hashdb(), account.address_hash(a));
+                account.storage_at(&self.trie, account_db.as_hashdb(), key)
+            }
+            None => Ok(H256::new()),
+        }
+    }
+
+    /// Get an account's code hash.
+    pub fn code_hash(&self, a: &Address) -> trie::Result<H256> {
+        self.account(a)
+            .map(|a| a.map_or(HASH_EMPTY, |a| a.code_hash()))
+    }
+
+    /// Get accounts' code.
+    pub fn code(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
+        Ok(self.account(a)?.and_then(|mut account| {
+            let account_db = self.accountdb
+                .readonly(self.db.as_hashdb(), account.address_hash(a));
+            account.cache_code(account_db.as_hashdb())
+        }))
+    }
+
+    /// Get accounts' ABI.
+    pub fn abi(&self, a: &Address) -> trie::Result<Option<Arc<Bytes>>> {
+        Ok(self.account(a)?.and_then(|mut account| {
+            let account_db = self.accountdb
+                .readonly(self.db.as_hashdb(), account.address_hash(a));
+            account.cache_abi(account_db.as_hashdb())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tests::helpers::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn snapshot_is_send_and_sync() {
+        assert_send_sync::<StateSnapshot>();
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let a: Address = 0xa.into();
+        let mut state = get_temp_state();
+        state.inc_nonce(&a).unwrap();
+        state.init_code(&a, vec![0x60, 0x00]).unwrap();
+        state.set_storage(&a, 1.into(), 0x45.into()).unwrap();
+        state.commit().unwrap();
+
+        let snapshot = state.snapshot_handle();
+        // later changes are not visible to the snapshot
+        state.inc_nonce(&a).unwrap();
+        state.commit().unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let snapshot = snapshot.clone();
+                thread::spawn(move || {
+                    assert_eq!(snapshot.nonce(&a).unwrap(), 1.into());
+                    assert_eq!(snapshot.storage_at(&a, &1.into()).unwrap(), 0x45.into());
+                    assert_eq!(snapshot.code(&a).unwrap(), Some(Arc::new(vec![0x60, 0x00])));
+                    assert!(!snapshot.exists(&0xb.into()).unwrap());
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// CITA
+// Copyright 2016-2017 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Witnesses for stateless execution.
+//!
+//! `State::execution_witness` dry-runs a transaction against the committed state and
+//! keeps every trie node, code and abi it reads. A client holding only the witness
+//! re-executes the transaction with `Witness::into_state` and arrives at the same
+//! outcome and state root. Transactions needing nodes beyond the witness fail with
+//! `Error::StateIncomplete`.
+
+use super::backend::Backend;
+use super::recording_db::RecordingDB;
+use super::{key_address_hash, State};
+use account_db::combine_key;
+use env_info::EnvInfo;
+use error::Error;
+use executive::TransactOptions;
+use factory::Factories;
+use std::collections::{BTreeMap, HashSet};
+use types::basic_account::BasicAccount;
+use types::transaction::SignedTransaction;
+use util::*;
+
+/// Nodes read by executing a transaction, see `State::execution_witness`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Witness {
+    /// State root the transaction was executed against.
+    pub root: H256,
+    /// Nodes read, by their key in the state database.
+    pub nodes: BTreeMap<H256, DBValue>,
+}
+
+impl Witness {
+    /// Whether the witness holds the root node and every node is reached from it under
+    /// its exact key. The account trie is walked from `root`; storage nodes, code and abi
+    /// of an account are looked up by their hash mangled with the address hash of the
+    /// account leaf, or by the plain hash for unmangled account databases.
+    /// Nodes the transaction did not read may be missing, their subtries are skipped.
+    pub fn verify(&self) -> bool {
+        if self.root != HASH_NULL_RLP && !self.nodes.contains_key(&self.root) {
+            return false;
+        }
+        let mut reached = HashSet::new();
+        let mut accounts = Vec::new();
+        {
+            let mut leaf = |key: &[u8], value: &[u8]| match UntrustedRlp::new(value).as_val::<BasicAccount>() {
+                Ok(account) => {
+                    accounts.push((key_address_hash(key), account));
+                    true
+                }
+                Err(_) => false,
+            };
+            if !self.walk_hash(&self.root, &|hash: &H256| *hash, &mut Vec::new(), &mut reached, &mut leaf) {
+                return false;
+            }
+        }
+        for (address_hash, account) in accounts {
+            let key_of = |hash: &H256| {
+                let mangled = combine_key(&address_hash, hash);
+                if self.nodes.contains_key(&mangled) {
+                    mangled
+                } else {
+                    *hash
+                }
+            };
+            let mut leaf = |_: &[u8], _: &[u8]| true;
+            if !self.walk_hash(&account.storage_root, &key_of, &mut Vec::new(), &mut reached, &mut leaf) {
+                return false;
+            }
+            for hash in &[account.code_hash, account.abi_hash] {
+                let key = key_of(hash);
+                if let Some(value) = self.nodes.get(&key) {
+                    if (&value[..]).crypt_hash() != *hash {
+                        return false;
+                    }
+                    reached.insert(key);
+                }
+            }
+        }
+        reached.len() == self.nodes.len()
+    }
+
+    // walk the trie node stored under `key_of(hash)` and check it hashes to `hash`,
+    // nodes missing from the witness are skipped.
+    fn walk_hash<K, L>(
+        &self,
+        hash: &H256,
+        key_of: &K,
+        path: &mut Vec<u8>,
+        reached: &mut HashSet<H256>,
+        leaf: &mut L,
+    ) -> bool
+    where
+        K: Fn(&H256) -> H256,
+        L: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let key = key_of(hash);
+        match self.nodes.get(&key) {
+            Some(value) => {
+                if (&value[..]).crypt_hash() != *hash {
+                    return false;
+                }
+                reached.insert(key);
+                self.walk_node(&UntrustedRlp::new(value), key_of, path, reached, leaf)
+            }
+            None => true,
+        }
+    }
+
+    // walk a trie node at the nibbles of `path`, calling `leaf` with the key and value of
+    // every leaf reached.
+    fn walk_node<K, L>(
+        &self,
+        node: &UntrustedRlp,
+        key_of: &K,
+        path: &mut Vec<u8>,
+        reached: &mut HashSet<H256>,
+        leaf: &mut L,
+    ) -> bool
+    where
+        K: Fn(&H256) -> H256,
+        L: FnMut(&[u8], &[u8]) -> bool,
+    {
+        if !node.is_list() {
+            // a reference to a child, empty for no child
+            return match node.data() {
+                Ok(data) if data.is_empty() => true,
+                Ok(data) if data.len() == 32 => self.walk_hash(&H256::from_slice(data), key_of, path, reached, leaf),
+                _ => false,
+            };
+        }
+        match node.item_count() {
+            // leaf or extension with a hex-prefix encoded partial path
+            Ok(2) => {
+                let (encoded, next) = match (node.at(0).and_then(|r| r.data()), node.at(1)) {
+                    (Ok(encoded), Ok(next)) if !encoded.is_empty() => (encoded, next),
+                    _ => return false,
+                };
+                let len = path.len();
+                if encoded[0] & 0x10 != 0 {
+                    path.push(encoded[0] & 0x0f);
+                }
+                for byte in &encoded[1..] {
+                    path.push(byte >> 4);
+                    path.push(byte & 0x0f);
+                }
+                let valid = if encoded[0] & 0x20 != 0 {
+                    match next.data() {
+                        Ok(value) if path.len() % 2 == 0 => {
+                            let key: Vec<u8> = path.chunks(2).map(|n| n[0] << 4 | n[1]).collect();
+                            leaf(&key, value)
+                        }
+                        _ => false,
+                    }
+                } else {
+                    self.walk_node(&next, key_of, path, reached, leaf)
+                };
+                path.truncate(len);
+                valid
+            }
+            // branch, keys are of the same length so the value is not used
+            Ok(17) => (0..16).all(|nibble| match node.at(nibble) {
+                Ok(child) => {
+                    path.push(nibble as u8);
+                    let valid = self.walk_node(&child, key_of, path, reached, leaf);
+                    path.pop();
+                    valid
+                }
+                Err(_) => false,
+            }),
+            _ => false,
+        }
+    }
+
+    /// State at `root` served from the witness alone, to re-execute the transaction.
+    /// `allow_missing_nodes` is set, so `apply` reports nodes missing from the witness
+    /// as `Error::StateIncomplete`.
+    pub fn into_state(self, account_start_nonce: U256, factories: Factories) -> Result<State<WitnessBackend>, Error> {
+        let root = self.root;
+        let mut state = State::from_existing(WitnessBackend::from(self), root, account_start_nonce, factories)?;
+        state.allow_missing_nodes = true;
+        Ok(state)
+    }
+}
+
+/// `Backend` holding the nodes of a `Witness` and nothing else.
+/// Commits add their nodes to it.
+pub struct WitnessBackend {
+    db: MemoryDB,
+}
+
+impl From<Witness> for WitnessBackend {
+    fn from(witness: Witness) -> Self {
+        let mut db = MemoryDB::new();
+        for (key, value) in witness.nodes {
+            db.emplace(key, value);
+        }
+        WitnessBackend { db: db }
+    }
+}
+
+impl Backend for WitnessBackend {
+    fn as_hashdb(&self) -> &HashDB {
+        &self.db
+    }
+
+    fn as_hashdb_mut(&mut self) -> &mut HashDB {
+        &mut self.db
+    }
+}
+
+/// `Backend` remembering the nodes read from another one, writes panic.
+struct RecordingBackend<'a> {
+    db: RecordingDB<'a>,
+}
+
+impl<'a> Backend for RecordingBackend<'a> {
+    fn as_hashdb(&self) -> &HashDB {
+        &self.db
+    }
+
+    fn as_hashdb_mut(&mut self) -> &mut HashDB {
+        &mut self.db
+    }
+}
+
+impl<B: Backend> State<B> {
+    /// Execute `t` against the committed state without changing it and collect the nodes
+    /// read, including those `commit` would read to compute the new root.
+    /// The transaction is executed with default `TransactOptions`.
+    ///
+    /// NOTE: Uncommitted changes are not seen and code or abi kept in a `blob_store`
+    /// is not collected.
+    pub fn execution_witness(&self, env_info: &EnvInfo, t: &SignedTransaction) -> Result<Witness, Error> {
+        let backend = RecordingBackend {
+            db: RecordingDB::new(self.db.as_hashdb()),
+        };
+        let mut dry_run = State::from_existing(backend, self.root, self.account_start_nonce, self.factories.clone())?;
+        dry_run.copy_execution_config(self);
+
+        let mut t = t.clone();
+        dry_run.apply_with_options(env_info, &mut t, TransactOptions::default())?;
+        dry_run.simulate_commit()?;
+
+        Ok(Witness {
+            root: self.root,
+            nodes: dry_run.db.db.into_nodes(),
+        })
+    }
+}
@@ -15,23 +15,38 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use lru_cache::LruCache;
 use state::backend::*;
-use util::{DBTransaction, H256, HashDB, JournalDB, UtilError};
+use std::sync::{Arc, Mutex, RwLock};
+use util::{Bytes, DBTransaction, H256, HashDB, JournalDB, UtilError};
+
+/// Number of contract codes kept in the code cache shared between clones.
+const CODE_CACHE_ITEMS: usize = 1024;
 
 pub struct StateDB {
     /// Backing database.
     db: Box<JournalDB>,
+    /// Code by hash, shared by all clones of this database.
+    code_cache: Arc<Mutex<LruCache<H256, Arc<Bytes>>>>,
+    /// Root of the latest commit, shared by all clones of this database.
+    latest_root: Arc<RwLock<Option<H256>>>,
 }
 
 impl StateDB {
     pub fn new(db: Box<JournalDB>) -> StateDB {
-        StateDB { db: db }
+        StateDB {
+            db: db,
+            code_cache: Arc::new(Mutex::new(LruCache::new(CODE_CACHE_ITEMS))),
+            latest_root: Arc::new(RwLock::new(None)),
+        }
     }
 
     /// Clone the database.
     pub fn boxed_clone(&self) -> StateDB {
         StateDB {
             db: self.db.boxed_clone(),
+            code_cache: Arc::clone(&self.code_cache),
+            latest_root: Arc::clone(&self.latest_root),
         }
     }
 
@@ -57,4 +72,27 @@ impl Backend for StateDB {
     fn as_hashdb_mut(&mut self) -> &mut HashDB {
         self.db.as_hashdb_mut()
     }
+
+    fn get_cached_code(&self, hash: &H256) -> Option<Arc<Bytes>> {
+        self.code_cache
+            .lock()
+            .expect("code cache lock poisoned")
+            .get_mut(hash)
+            .map(Arc::clone)
+    }
+
+    fn cache_code(&self, hash: H256, code: Arc<Bytes>) {
+        self.code_cache
+            .lock()
+            .expect("code cache lock poisoned")
+            .insert(hash, code);
+    }
+
+    fn latest_root(&self) -> Option<H256> {
+        *self.latest_root.read().expect("latest root lock poisoned")
+    }
+
+    fn note_root(&self, root: H256) {
+        *self.latest_root.write().expect("latest root lock poisoned") = Some(root);
+    }
 }
@@ -39,6 +39,9 @@ pub struct Substate {
     /// Refund counter of SSTORE nonzero -> zero.
     pub sstore_clears_count: U256,
 
+    /// Gas charged for SSTORE.
+    pub storage_gas: U256,
+
     /// Created contracts.
     pub contracts_created: Vec<Address>,
 }
@@ -49,15 +52,39 @@ impl Substate {
         Substate::default()
     }
 
+    /// Empty the substate for another transaction, keeping the allocated capacity.
+    pub fn clear(&mut self) {
+        self.suicides.clear();
+        self.garbage.clear();
+        self.logs.clear();
+        self.sstore_clears_count = U256::zero();
+        self.storage_gas = U256::zero();
+        self.contracts_created.clear();
+    }
+
     /// Merge secondary substate `s` into self, accruing each element correspondingly.
     pub fn accrue(&mut self, s: Substate) {
         self.suicides.extend(s.suicides.into_iter());
         self.garbage.extend(s.garbage.into_iter());
         self.logs.extend(s.logs.into_iter());
         self.sstore_clears_count = self.sstore_clears_count + s.sstore_clears_count;
+        self.storage_gas = self.storage_gas + s.storage_gas;
         self.contracts_created.extend(s.contracts_created.into_iter());
     }
 
+    /// Merge secondary substate `s` into self like `accrue`, leaving `s` empty with its
+    /// allocated capacity kept.
+    pub fn accrue_drain(&mut self, s: &mut Substate) {
+        self.suicides.extend(s.suicides.drain());
+        self.garbage.extend(s.garbage.drain());
+        self.logs.extend(s.logs.drain(..));
+        self.sstore_clears_count = self.sstore_clears_count + s.sstore_clears_count;
+        self.storage_gas = self.storage_gas + s.storage_gas;
+        self.contracts_created.extend(s.contracts_created.drain(..));
+        s.sstore_clears_count = U256::zero();
+        s.storage_gas = U256::zero();
+    }
+
     /// Get the cleanup mode object from this.
     #[cfg_attr(feature = "dev", allow(wrong_self_convention))]
     pub fn to_cleanup_mode(&mut self, schedule: &Schedule) -> CleanupMode {
@@ -90,6 +117,7 @@ mod tests {
                                 data: vec![],
                             });
         sub_state.sstore_clears_count = 5.into();
+        sub_state.storage_gas = 20000.into();
         sub_state.suicides.insert(10u64.into());
 
         let mut sub_state_2 = Substate::new();
@@ -100,10 +128,38 @@ mod tests {
                                   data: vec![],
                               });
         sub_state_2.sstore_clears_count = 7.into();
+        sub_state_2.storage_gas = 5000.into();
 
         sub_state.accrue(sub_state_2);
         assert_eq!(sub_state.contracts_created.len(), 2);
         assert_eq!(sub_state.sstore_clears_count, 12.into());
+        assert_eq!(sub_state.storage_gas, 25000.into());
         assert_eq!(sub_state.suicides.len(), 1);
+
+        sub_state.clear();
+        assert!(sub_state.contracts_created.is_empty() && sub_state.logs.is_empty());
+        assert_eq!(sub_state.storage_gas, 0.into());
+        assert!(sub_state.suicides.is_empty());
+    }
+
+    #[test]
+    fn accrue_drain() {
+        let mut sub_state = Substate::new();
+        let mut sub_state_2 = Substate::new();
+        sub_state_2.contracts_created.push(2u64.into());
+        sub_state_2.logs.push(LogEntry {
+                                  address: 1u64.into(),
+                                  topics: vec![],
+                                  data: vec![],
+                              });
+        sub_state_2.storage_gas = 5000.into();
+
+        sub_state.accrue_drain(&mut sub_state_2);
+        assert_eq!(sub_state.contracts_created.len(), 1);
+        assert_eq!(sub_state.logs.len(), 1);
+        assert_eq!(sub_state.storage_gas, 5000.into());
+        assert!(sub_state_2.contracts_created.is_empty() && sub_state_2.logs.is_empty());
+        assert!(sub_state_2.logs.capacity() > 0);
+        assert_eq!(sub_state_2.storage_gas, 0.into());
     }
 }
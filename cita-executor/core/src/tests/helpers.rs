@@ -52,6 +52,14 @@ pub fn get_temp_state() -> State<StateDB> {
     State::new(journal_db, 0.into(), Default::default())
 }
 
+/// Temporary state together with the key-value database backing it, so tests
+/// can journal commits and read historical roots.
+pub fn get_temp_state_with_kvdb() -> (State<StateDB>, Arc<KeyValueDB>) {
+    let db = new_db();
+    let journal_db = journaldb::new(db.clone(), journaldb::Algorithm::Archive, ::db::COL_STATE);
+    (State::new(StateDB::new(journal_db), 0.into(), Default::default()), db)
+}
+
 fn new_db() -> Arc<KeyValueDB> {
     Arc::new(::util::kvdb::in_memory(8))
 }
@@ -18,14 +18,39 @@
 #![rustfmt_skip]
 
 use action_params::ActionParams;
+use std::sync::{Arc, Mutex};
 use trace::{Tracer, VMTracer, FlatTrace, TraceError};
 use trace::trace::{Call, Create, Action, Res, CreateResult, CallResult, VMTrace, VMOperation, VMExecutedOperation, MemoryDiff, StorageDiff, Suicide};
 use util::{Bytes, Address, U256};
 
+/// Emptied trace buffers shared by a tracer and its subtracers, see `ExecutiveTracer::reusing`.
+pub type TraceBuffers = Arc<Mutex<Vec<Vec<FlatTrace>>>>;
+
 /// Simple executive tracer. Traces all calls and creates. Ignores delegatecalls.
 #[derive(Default)]
 pub struct ExecutiveTracer {
     traces: Vec<FlatTrace>,
+    buffers: TraceBuffers,
+}
+
+impl ExecutiveTracer {
+    /// Tracer recording into a buffer taken from `buffers`. The buffers of its finished
+    /// subtracers are emptied and returned there, so nested calls do not allocate anew.
+    pub fn reusing(buffers: &TraceBuffers) -> Self {
+        let traces = buffers.lock().expect("trace buffers lock poisoned").pop().unwrap_or_default();
+        ExecutiveTracer {
+            traces: traces,
+            buffers: buffers.clone(),
+        }
+    }
+
+    fn extend_subtraces(&mut self, subs: Vec<FlatTrace>) {
+        let mut subs = prefix_subtrace_addresses(subs);
+        self.traces.extend(subs.drain(..));
+        if subs.capacity() > 0 {
+            self.buffers.lock().expect("trace buffers lock poisoned").push(subs);
+        }
+    }
 }
 
 fn top_level_subtraces(traces: &[FlatTrace]) -> usize {
@@ -115,7 +140,7 @@ impl Tracer for ExecutiveTracer {
         };
         debug!(target: "trace", "Traced call {:?}", trace);
         self.traces.push(trace);
-        self.traces.extend(prefix_subtrace_addresses(subs));
+        self.extend_subtraces(subs);
     }
 
     fn trace_create(&mut self, create: Option<Create>, gas_used: U256, code: Option<Bytes>, address: Address, subs: Vec<FlatTrace>) {
@@ -131,7 +156,7 @@ impl Tracer for ExecutiveTracer {
         };
         debug!(target: "trace", "Traced create {:?}", trace);
         self.traces.push(trace);
-        self.traces.extend(prefix_subtrace_addresses(subs));
+        self.extend_subtraces(subs);
     }
 
     fn trace_failed_call(&mut self, call: Option<Call>, subs: Vec<FlatTrace>, error: TraceError) {
@@ -143,7 +168,7 @@ impl Tracer for ExecutiveTracer {
         };
         debug!(target: "trace", "Traced failed call {:?}", trace);
         self.traces.push(trace);
-        self.traces.extend(prefix_subtrace_addresses(subs));
+        self.extend_subtraces(subs);
     }
 
     fn trace_failed_create(&mut self, create: Option<Create>, subs: Vec<FlatTrace>, error: TraceError) {
@@ -155,7 +180,7 @@ impl Tracer for ExecutiveTracer {
         };
         debug!(target: "trace", "Traced failed create {:?}", trace);
         self.traces.push(trace);
-        self.traces.extend(prefix_subtrace_addresses(subs));
+        self.extend_subtraces(subs);
     }
 
     fn trace_suicide(&mut self, address: Address, balance: U256, refund_address: Address) {
@@ -174,7 +199,7 @@ impl Tracer for ExecutiveTracer {
     }
 
     fn subtracer(&self) -> Self {
-        ExecutiveTracer::default()
+        ExecutiveTracer::reusing(&self.buffers)
     }
 
     fn traces(self) -> Vec<FlatTrace> {
@@ -29,7 +29,7 @@ mod types;
 pub use self::config::Config;
 pub use self::db::TraceDB;
 pub use self::error::Error;
-pub use self::executive_tracer::{ExecutiveTracer, ExecutiveVMTracer};
+pub use self::executive_tracer::{ExecutiveTracer, ExecutiveVMTracer, TraceBuffers};
 pub use self::import::ImportRequest;
 pub use self::localized::LocalizedTrace;
 pub use self::noop_tracer::{NoopTracer, NoopVMTracer};
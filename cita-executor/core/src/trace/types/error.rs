@@ -45,6 +45,12 @@ pub enum Error {
     OutOfBounds,
     /// Execution has been reverted with REVERT instruction.
     Reverted,
+    /// The transaction executed more SSTOREs than allowed.
+    TooManyStorageWrites,
+    /// Contract creation from code that is not whitelisted.
+    CodeNotWhitelisted,
+    /// Call to a contract that self-destructed earlier in the block.
+    SuicidedContract,
 }
 
 impl<'a> From<&'a EvmError> for Error {
@@ -59,6 +65,9 @@ impl<'a> From<&'a EvmError> for Error {
             EvmError::MutableCallInStaticContext => Error::MutableCallInStaticContext,
             EvmError::OutOfBounds => Error::OutOfBounds,
             EvmError::Reverted => Error::Reverted,
+            EvmError::TooManyStorageWrites { .. } => Error::TooManyStorageWrites,
+            EvmError::CodeNotWhitelisted => Error::CodeNotWhitelisted,
+            EvmError::SuicidedContract => Error::SuicidedContract,
         }
     }
 }
@@ -82,6 +91,9 @@ impl fmt::Display for Error {
             MutableCallInStaticContext => "Mutable Call In Static Context",
             OutOfBounds => "Out of bounds",
             Reverted => "Reverted",
+            TooManyStorageWrites => "Too many storage writes",
+            CodeNotWhitelisted => "Contract code is not whitelisted",
+            SuicidedContract => "Contract self-destructed earlier in the block",
         };
         message.fmt(f)
     }
@@ -100,6 +112,9 @@ impl Encodable for Error {
             MutableCallInStaticContext => 6,
             OutOfBounds => 7,
             Reverted => 8,
+            TooManyStorageWrites => 9,
+            CodeNotWhitelisted => 10,
+            SuicidedContract => 11,
         };
 
         s.append_internal(&value);
@@ -120,6 +135,9 @@ impl Decodable for Error {
             6 => Ok(MutableCallInStaticContext),
             7 => Ok(OutOfBounds),
             8 => Ok(Reverted),
+            9 => Ok(TooManyStorageWrites),
+            10 => Ok(CodeNotWhitelisted),
+            11 => Ok(SuicidedContract),
             _ => Err(DecoderError::Custom("Invalid error type")),
         }
     }